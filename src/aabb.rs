@@ -0,0 +1,142 @@
+use super::interval::{self, Interval};
+use super::ray::Ray;
+use super::vec3::Point3;
+
+/// Axis-aligned bounding box, stored as one interval per axis.
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        EMPTY
+    }
+}
+
+impl Aabb {
+    pub const fn new(x: Interval, y: Interval, z: Interval) -> Self {
+        Aabb { x, y, z }
+    }
+
+    /// Treat the two points a and b as extrema for the bounding box, so we don't require a
+    /// particular minimum/maximum coordinate order.
+    pub fn new_points(a: &Point3, b: &Point3) -> Self {
+        Aabb {
+            x: Interval::new_val(a.x().min(b.x()), a.x().max(b.x())),
+            y: Interval::new_val(a.y().min(b.y()), a.y().max(b.y())),
+            z: Interval::new_val(a.z().min(b.z()), a.z().max(b.z())),
+        }
+    }
+
+    /// Smallest box enclosing both box0 and box1
+    pub fn new_boxes(box0: &Aabb, box1: &Aabb) -> Self {
+        Aabb {
+            x: Interval::new_enclosing(&box0.x, &box1.x),
+            y: Interval::new_enclosing(&box0.y, &box1.y),
+            z: Interval::new_enclosing(&box0.z, &box1.z),
+        }
+    }
+
+    pub fn axis(&self, n: usize) -> &Interval {
+        match n {
+            1 => &self.y,
+            2 => &self.z,
+            _ => &self.x,
+        }
+    }
+
+    /// Index of the axis along which the box is the widest
+    pub fn longest_axis(&self) -> usize {
+        if self.x.size() > self.y.size() {
+            if self.x.size() > self.z.size() {
+                0
+            } else {
+                2
+            }
+        } else if self.y.size() > self.z.size() {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.size() < 0.0 || self.y.size() < 0.0 || self.z.size() < 0.0
+    }
+
+    /// Return a box whose sides are at least delta wide, so that flat primitives
+    /// still produce something the slab test can hit.
+    pub fn pad(&self, delta: f64) -> Self {
+        let pad_axis = |i: &Interval| {
+            if i.size() < delta {
+                i.expand(delta)
+            } else {
+                *i
+            }
+        };
+        Aabb::new(pad_axis(&self.x), pad_axis(&self.y), pad_axis(&self.z))
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let (dx, dy, dz) = (self.x.size(), self.y.size(), self.z.size());
+        2.0 * (dx * dy + dy * dz + dz * dx)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        Point3::new(
+            (self.x.min + self.x.max) * 0.5,
+            (self.y.min + self.y.max) * 0.5,
+            (self.z.min + self.z.max) * 0.5,
+        )
+    }
+
+    ///
+    /// Slab test against the ray
+    /// * `r` - Ray
+    /// * `ray_t` - Ray Interval
+    /// # Returns
+    /// Return true if the ray overlaps the box anywhere inside ray_t
+    pub fn hit(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.clip(r, ray_t).is_some()
+    }
+
+    /// Part of ray_t during which the ray is inside the box, if there is any
+    pub fn clip(&self, r: &Ray, ray_t: Interval) -> Option<Interval> {
+        let ray_orig = r.origin();
+        let ray_dir = r.direction();
+        let mut t_min = ray_t.min;
+        let mut t_max = ray_t.max;
+
+        for axis in 0..3 {
+            let ax = self.axis(axis);
+            let (orig, dir) = match axis {
+                0 => (ray_orig.x(), ray_dir.x()),
+                1 => (ray_orig.y(), ray_dir.y()),
+                _ => (ray_orig.z(), ray_dir.z()),
+            };
+            let adinv = 1.0 / dir;
+
+            let t0 = (ax.min - orig) * adinv;
+            let t1 = (ax.max - orig) * adinv;
+
+            let (t0, t1) = if adinv < 0.0 { (t1, t0) } else { (t0, t1) };
+            if t0 > t_min {
+                t_min = t0;
+            }
+            if t1 < t_max {
+                t_max = t1;
+            }
+
+            if t_max <= t_min {
+                return None;
+            }
+        }
+        Some(Interval::new_val(t_min, t_max))
+    }
+}
+
+pub static EMPTY: Aabb = Aabb::new(interval::EMPTY, interval::EMPTY, interval::EMPTY);
+pub static UNIVERSE: Aabb = Aabb::new(interval::UNIVERSE, interval::UNIVERSE, interval::UNIVERSE);
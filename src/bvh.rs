@@ -0,0 +1,98 @@
+use super::aabb::{self, Aabb};
+use super::hittable::{HitRecord, Hittable};
+use super::hittable_list::HittableList;
+use super::interval::Interval;
+use super::ray::Ray;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// Bounding volume hierarchy node. Each node splits its objects in half along the longest
+/// axis of their combined bounding box, so a ray only visits the subtrees whose box it
+/// overlaps instead of walking every object in the scene.
+pub struct BvhNode {
+    left: Arc<dyn Hittable + Sync + Send>,
+    right: Arc<dyn Hittable + Sync + Send>,
+    bbox: Aabb,
+}
+
+impl BvhNode {
+    pub fn new(list: HittableList) -> Self {
+        let mut objects: Vec<Arc<dyn Hittable + Sync + Send>> =
+            list.objects.into_iter().map(Arc::from).collect();
+        BvhNode::from_objects(&mut objects)
+    }
+
+    /// Build the hierarchy over a slice of shared objects, reordering the slice in place
+    pub fn from_objects(objects: &mut [Arc<dyn Hittable + Sync + Send>]) -> Self {
+        let bbox = objects.iter().fold(aabb::EMPTY, |bbox, obj| {
+            Aabb::new_boxes(&bbox, &obj.bounding_box())
+        });
+
+        let (left, right): (
+            Arc<dyn Hittable + Sync + Send>,
+            Arc<dyn Hittable + Sync + Send>,
+        ) = match objects.len() {
+            0 => {
+                let empty: Arc<dyn Hittable + Sync + Send> = Arc::new(HittableList::new());
+                (empty.clone(), empty)
+            }
+            1 => (objects[0].clone(), objects[0].clone()),
+            2 => (objects[0].clone(), objects[1].clone()),
+            _ => {
+                let axis = bbox.longest_axis();
+                objects.sort_by(|a, b| BvhNode::box_compare(a.as_ref(), b.as_ref(), axis));
+
+                let mid = objects.len() / 2;
+                let (lower, upper) = objects.split_at_mut(mid);
+                (
+                    Arc::new(BvhNode::from_objects(lower)),
+                    Arc::new(BvhNode::from_objects(upper)),
+                )
+            }
+        };
+
+        BvhNode { left, right, bbox }
+    }
+
+    fn box_compare(
+        a: &(dyn Hittable + Sync + Send),
+        b: &(dyn Hittable + Sync + Send),
+        axis: usize,
+    ) -> Ordering {
+        let a_min = a.bounding_box().axis(axis).min;
+        let b_min = b.bounding_box().axis(axis).min;
+        a_min.partial_cmp(&b_min).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        if !self.bbox.hit(r, ray_t) {
+            return false;
+        }
+
+        let hit_left = self.left.hit(r, ray_t, rec);
+        let right_max = if hit_left { rec.t } else { ray_t.max };
+        let hit_right = self
+            .right
+            .hit(r, Interval::new_val(ray_t.min, right_max), rec);
+
+        hit_left || hit_right
+    }
+
+    fn transmittance(&self, r: &Ray, ray_t: Interval) -> f64 {
+        if !self.bbox.hit(r, ray_t) {
+            return 1.0;
+        }
+
+        let left = self.left.transmittance(r, ray_t);
+        if left == 0.0 || Arc::ptr_eq(&self.left, &self.right) {
+            return left;
+        }
+        left * self.right.transmittance(r, ray_t)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
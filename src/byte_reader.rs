@@ -0,0 +1,70 @@
+use super::load_error::LoadError;
+
+/// Cursor over the binary section of a file, producing offset-tagged errors when it runs out
+pub(crate) struct ByteReader<'a> {
+    file: &'a str,
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(file: &'a str, data: &'a [u8], pos: usize, big_endian: bool) -> Self {
+        ByteReader {
+            file,
+            data,
+            pos,
+            big_endian,
+        }
+    }
+
+    pub fn error(&self, message: impl Into<String>) -> LoadError {
+        LoadError::data(self.file, self.pos, message)
+    }
+
+    pub fn bytes<const N: usize>(&mut self) -> Result<[u8; N], LoadError> {
+        let end = self.pos + N;
+        if end > self.data.len() {
+            return Err(self.error("unexpected end of file"));
+        }
+        let mut out = [0u8; N];
+        out.copy_from_slice(&self.data[self.pos..end]);
+        if self.big_endian {
+            out.reverse();
+        }
+        self.pos = end;
+        Ok(out)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    pub fn i8(&mut self) -> Result<i8, LoadError> {
+        Ok(i8::from_le_bytes(self.bytes()?))
+    }
+
+    pub fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(u16::from_le_bytes(self.bytes()?))
+    }
+
+    pub fn i16(&mut self) -> Result<i16, LoadError> {
+        Ok(i16::from_le_bytes(self.bytes()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, LoadError> {
+        Ok(i32::from_le_bytes(self.bytes()?))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, LoadError> {
+        Ok(f32::from_le_bytes(self.bytes()?))
+    }
+
+    pub fn f64(&mut self) -> Result<f64, LoadError> {
+        Ok(f64::from_le_bytes(self.bytes()?))
+    }
+}
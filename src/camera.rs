@@ -1,12 +1,15 @@
 use super::{
-    color::{write_color, Color},
+    color::Color,
     hittable::{HitRecord, Hittable},
+    image::Image,
     interval::Interval,
+    material::ScatterRecord,
     ray::Ray,
     rtweekend::{degrees_to_radians, random_double, INFINITY},
+    spectrum::{SampledSpectrum, SampledWavelengths},
     vec3::{Point3, Vec3},
 };
-use std::{sync::Arc, sync::Mutex, thread};
+use std::{sync::Arc, thread};
 use num_cpus;
 
 #[derive(Clone)]
@@ -25,6 +28,24 @@ pub struct Camera {
     pub defocus_angle: f64,
     pub focus_dist: f64,
 
+    /// Color of rays that escape the scene, None for the default sky gradient
+    pub background: Option<Color>,
+
+    /// Emissive objects to sample directly at every diffuse bounce. When set, light and
+    /// material samples are combined with multiple importance sampling. Every emitter of the
+    /// scene should be in here, in addition to being in the world.
+    pub lights: Option<Arc<dyn Hittable + Sync + Send>>,
+
+    /// Times the shutter opens and closes, every camera ray gets a random time in between.
+    /// Moving objects are blurred along the motion they make over this interval.
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+
+    /// Trace a handful of wavelengths per path instead of RGB, so glass with a dispersive
+    /// index splits light into colors. RGB albedos and emission are turned into spectra and
+    /// the result is converted back to RGB per sample. Slower, off by default.
+    pub spectral: bool,
+
     image_height: i32,
     center: Point3,
     pixel00_loc: Point3,
@@ -50,6 +71,11 @@ impl Default for Camera {
             vup: Vec3::new(0.0, 1.0, 0.0),
             defocus_angle: 0.0,
             focus_dist: 10.0,
+            background: None,
+            lights: None,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+            spectral: false,
 
             image_height: 0,
             center: Point3 {
@@ -73,15 +99,16 @@ impl Default for Camera {
     }
 }
 
+/// Render rows j_start..j_end, returning their pixels averaged over the samples
 fn thread_func(
     camera: Camera,
     world: &(dyn Hittable + Sync),
     j_start: i32,
     j_end: i32,
-    output_str_arc: Arc<Mutex<Vec<(i32, String)>>>,
-) {
-    let mut out_str = String::new();
+) -> Vec<Color> {
+    let mut pixels = Vec::with_capacity(((j_end - j_start) * camera.image_width) as usize);
     eprintln!("Thread for {j_start} starting");
+    let scale = 1.0 / (camera.samples_per_pixel as f64);
     for j in j_start..j_end {
         for i in 0..camera.image_width {
             let mut pixel_color = Color {
@@ -92,29 +119,42 @@ fn thread_func(
                 pixel_color = pixel_color + camera.ray_color(&r, camera.max_depth, world);
             }
 
-            write_color(&mut out_str, &pixel_color, camera.samples_per_pixel);
+            pixels.push(pixel_color * scale);
         }
         eprintln!("{j} is done");
     }
 
-    output_str_arc.lock().unwrap().push((j_start, out_str));
     eprintln!("Thread for {j_start} finished");
-    return ();
+    pixels
+}
+
+/// Light reaching a hit point from a point sampled on the lights, contributing
+/// f * emitted * scale. The factors are kept apart so a spectral render can convert each.
+struct LightSample {
+    f: Color,
+    emitted: Color,
+    scale: f64,
+}
+
+/// Weight of a sample taken with density pdf_a when pdf_b could also have produced it
+fn power_heuristic(pdf_a: f64, pdf_b: f64) -> f64 {
+    let a2 = pdf_a * pdf_a;
+    let b2 = pdf_b * pdf_b;
+    if a2 + b2 <= 0.0 {
+        return 0.0;
+    }
+    a2 / (a2 + b2)
 }
 
 impl Camera {
-    pub fn render(&mut self, world: Box<dyn Hittable + Sync + Send>) {
+    pub fn render(&mut self, world: Box<dyn Hittable + Sync + Send>) -> Image {
         self.initialize();
 
-        println!("P3\n{} {}\n255\n", self.image_width, self.image_height);
-
-        let num_threads = (num_cpus::get() * 2 / 3) as i32;
+        let num_threads = ((num_cpus::get() * 2 / 3) as i32).clamp(1, self.image_height);
         eprintln!("Spawning {num_threads} threads");
         let j_per_thread = self.image_height / num_threads;
-        let output_str_arc = Arc::new(Mutex::new(Vec::new()));
         let world_arc = Arc::new(world);
-        let thread_list: Vec<thread::JoinHandle<()>> = (0..num_threads)
-            .into_iter()
+        let thread_list: Vec<(i32, thread::JoinHandle<Vec<Color>>)> = (0..num_threads)
             .map(|tid| {
                 let j_start = j_per_thread * tid;
                 let j_end = if tid == num_threads - 1 {
@@ -124,34 +164,28 @@ impl Camera {
                 };
                 let my_camera = self.clone();
                 let my_world = world_arc.clone();
-                let my_output_str_arc = output_str_arc.clone();
-
-                return thread::spawn(move || {
-                    thread_func(
-                        my_camera,
-                        &(*(*my_world)),
-                        j_start,
-                        j_end,
-                        my_output_str_arc,
-                    );
-                });
+
+                let handle =
+                    thread::spawn(move || thread_func(my_camera, &(*(*my_world)), j_start, j_end));
+                (j_start, handle)
             })
             .collect();
 
-        for t in thread_list {
-            t.join().unwrap();
-        }
-        let mut res = output_str_arc.lock().unwrap().clone();
-        res.sort_by(|lhs, rhs| lhs.0.partial_cmp(&(rhs.0)).unwrap());
-
-        for (_, s) in res {
-            println!("{s}");
+        let width = self.image_width as usize;
+        let mut image = Image::new(width, self.image_height as usize);
+        for (j_start, t) in thread_list {
+            let pixels = t.join().unwrap();
+            for (index, pixel_color) in pixels.into_iter().enumerate() {
+                image.set_pixel(
+                    index % width,
+                    (j_start as usize) + index / width,
+                    pixel_color,
+                    self.samples_per_pixel as u32,
+                );
+            }
         }
 
-        //for j in 0..self.image_height {
-        //eprint!("\rScanlines remaining: {} ", self.image_height - j);
-        //eprint!("\rDone.              \n");
-        //}
+        image
     }
 
     fn initialize(&mut self) {
@@ -194,6 +228,13 @@ impl Camera {
     }
 
     fn ray_color(&self, r: &Ray, depth: i32, world: &dyn Hittable) -> Color {
+        if self.spectral {
+            return self.ray_color_spectral(r, depth, world);
+        }
+        if let Some(lights) = &self.lights {
+            return self.ray_color_light_sampling(r, depth, world, lights.as_ref());
+        }
+
         let mut rec = HitRecord {
             ..Default::default()
         };
@@ -204,14 +245,220 @@ impl Camera {
             };
         }
 
-        if world.hit(r, Interval::new_val(0.001, INFINITY), &mut rec) {
-            let mut scattered = Ray::default();
-            let mut attenuation = Color::default();
-            if rec.mat.scatter(r, &rec, &mut attenuation, &mut scattered) {
-                return self.ray_color(&scattered, depth - 1, world) * attenuation;
+        if !world.hit(r, Interval::new_val(0.001, INFINITY), &mut rec) {
+            return self.background_color(r);
+        }
+
+        let color_from_emission = rec.mat.emitted(rec.u, rec.v, &rec.p);
+
+        let mut srec = ScatterRecord::default();
+        if !rec.mat.scatter(r, &rec, &mut srec) || !rec.same_side(&srec.scattered.direction()) {
+            return color_from_emission;
+        }
+
+        let color_from_scatter =
+            self.ray_color(&srec.scattered, depth - 1, world) * srec.attenuation;
+        color_from_emission + color_from_scatter
+    }
+
+    /// Path tracer with next event estimation: at every non-specular bounce a point on the
+    /// lights is sampled directly, and both that sample and the material sample are weighted
+    /// with the power heuristic so neither strategy double counts the light.
+    fn ray_color_light_sampling(
+        &self,
+        r: &Ray,
+        max_depth: i32,
+        world: &dyn Hittable,
+        lights: &dyn Hittable,
+    ) -> Color {
+        let mut radiance = Color::default();
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = r.clone();
+        // Density the current ray was sampled with, None for camera rays and specular bounces
+        // whose emission hits can't have come from light sampling
+        let mut bsdf_pdf: Option<f64> = None;
+
+        for _depth in 0..max_depth {
+            let mut rec = HitRecord::default();
+            if !world.hit(&ray, Interval::new_val(0.001, INFINITY), &mut rec) {
+                radiance = radiance + throughput * self.background_color(&ray);
+                break;
+            }
+
+            let emitted = rec.mat.emitted(rec.u, rec.v, &rec.p);
+            if !emitted.near_zero() {
+                let weight = Camera::emission_weight(&ray, &rec, bsdf_pdf, lights);
+                radiance = radiance + throughput * emitted * weight;
+            }
+
+            let mut srec = ScatterRecord::default();
+            if !rec.mat.scatter(&ray, &rec, &mut srec) {
+                break;
+            }
+
+            if !srec.is_specular {
+                if let Some(light) = self.sample_light(&ray, &rec, world, lights) {
+                    radiance = radiance + throughput * light.f * light.emitted * light.scale;
+                }
+            }
+
+            // Shading normals can send the path through the surface it's on
+            if !rec.same_side(&srec.scattered.direction()) {
+                break;
+            }
+            throughput = throughput * srec.attenuation;
+            bsdf_pdf = if srec.is_specular {
+                None
+            } else {
+                Some(srec.pdf)
+            };
+            ray = srec.scattered;
+        }
+
+        radiance
+    }
+
+    /// Path tracer carrying `SampledWavelengths` instead of RGB, with the same light sampling
+    /// as `ray_color_light_sampling` when there are lights. Rays carry the hero wavelength,
+    /// and a dispersive scatter leaves the hero alone on the path.
+    fn ray_color_spectral(&self, r: &Ray, max_depth: i32, world: &dyn Hittable) -> Color {
+        let mut lambdas = SampledWavelengths::sample_visible(random_double());
+        let lights = self.lights.as_deref();
+        let mut radiance = SampledSpectrum::default();
+        let mut throughput = SampledSpectrum::new(1.0);
+        let mut ray = r.clone().with_wavelength(lambdas.hero());
+        let mut bsdf_pdf: Option<f64> = None;
+
+        for _depth in 0..max_depth {
+            let mut rec = HitRecord::default();
+            if !world.hit(&ray, Interval::new_val(0.001, INFINITY), &mut rec) {
+                let background = SampledSpectrum::from_rgb(&self.background_color(&ray), &lambdas);
+                radiance = radiance + throughput * background;
+                break;
+            }
+
+            let emitted = rec.mat.emitted(rec.u, rec.v, &rec.p);
+            if !emitted.near_zero() {
+                let weight = match lights {
+                    Some(lights) => Camera::emission_weight(&ray, &rec, bsdf_pdf, lights),
+                    None => 1.0,
+                };
+                radiance =
+                    radiance + throughput * SampledSpectrum::from_rgb(&emitted, &lambdas) * weight;
+            }
+
+            let mut srec = ScatterRecord::default();
+            if !rec.mat.scatter(&ray, &rec, &mut srec) {
+                break;
+            }
+            if srec.dispersive {
+                lambdas.terminate_secondary();
+            }
+
+            if let (false, Some(lights)) = (srec.is_specular, lights) {
+                if let Some(light) = self.sample_light(&ray, &rec, world, lights) {
+                    let f = SampledSpectrum::from_rgb(&light.f, &lambdas);
+                    let emitted = SampledSpectrum::from_rgb(&light.emitted, &lambdas);
+                    radiance = radiance + throughput * f * emitted * light.scale;
+                }
+            }
+
+            if !rec.same_side(&srec.scattered.direction()) {
+                break;
+            }
+            throughput = throughput * SampledSpectrum::from_rgb(&srec.attenuation, &lambdas);
+            bsdf_pdf = if srec.is_specular {
+                None
+            } else {
+                Some(srec.pdf)
+            };
+            ray = srec.scattered.with_wavelength(lambdas.hero());
+        }
+
+        radiance.to_rgb(&lambdas)
+    }
+
+    /// MIS weight of emission found at rec by a ray sampled with density bsdf_pdf, None for
+    /// camera rays and specular bounces
+    fn emission_weight(
+        ray: &Ray,
+        rec: &HitRecord,
+        bsdf_pdf: Option<f64>,
+        lights: &dyn Hittable,
+    ) -> f64 {
+        // Emission that isn't on the lights, like a glowing medium, can't have been sampled by
+        // sample_light and keeps its full weight
+        let mut light_rec = HitRecord::default();
+        let on_lights = lights.hit(
+            ray,
+            Interval::new_val(rec.t * 0.999, rec.t * 1.001),
+            &mut light_rec,
+        );
+        match bsdf_pdf {
+            Some(pdf) if on_lights => {
+                power_heuristic(pdf, lights.pdf_value(&ray.origin(), &ray.direction()))
             }
+            _ => 1.0,
+        }
+    }
+
+    /// MIS weighted light arriving at the hit point from one direction sampled on the lights,
+    /// None when the sample brings no light
+    fn sample_light(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        world: &dyn Hittable,
+        lights: &dyn Hittable,
+    ) -> Option<LightSample> {
+        let direction = lights.random(&rec.p);
+        let light_pdf = lights.pdf_value(&rec.p, &direction);
+        // Directions through the surface are kept for the transmission lobes, so every
+        // direction the material samples could have come from here too and the MIS weights
+        // of emission_weight hold on both sides
+        if light_pdf <= 0.0 || !rec.same_side(&direction) {
+            return None;
+        }
+
+        let shadow_ray = Ray::new_time(&rec.p, &direction, r_in.time());
+        let f = rec.mat.eval(r_in, rec, &shadow_ray);
+        if f.near_zero() {
+            return None;
+        }
+
+        let mut light_rec = HitRecord::default();
+        if !lights.hit(
+            &shadow_ray,
+            Interval::new_val(0.001, INFINITY),
+            &mut light_rec,
+        ) {
+            return None;
+        }
+        let emitted = light_rec
+            .mat
+            .emitted(light_rec.u, light_rec.v, &light_rec.p);
+        if emitted.near_zero() {
+            return None;
+        }
 
-            return Color::default();
+        // Surfaces in front of the light block it, media only dim it
+        let transmittance =
+            world.transmittance(&shadow_ray, Interval::new_val(0.001, light_rec.t - 0.001));
+        if transmittance <= 0.0 {
+            return None;
+        }
+
+        let weight = power_heuristic(light_pdf, rec.mat.scattering_pdf(r_in, rec, &shadow_ray));
+        Some(LightSample {
+            f,
+            emitted,
+            scale: transmittance * weight / light_pdf,
+        })
+    }
+
+    fn background_color(&self, r: &Ray) -> Color {
+        if let Some(background) = self.background {
+            return background;
         }
 
         let unit_direction = Vec3::unit_vector(&(r.direction()));
@@ -233,8 +480,10 @@ impl Camera {
             self.defocus_disk_sample()
         };
         let ray_direction = pixel_sample - ray_origin;
+        let ray_time =
+            self.shutter_open + (self.shutter_close - self.shutter_open) * random_double();
 
-        return Ray::new(&ray_origin, &ray_direction);
+        Ray::new_time(&ray_origin, &ray_direction, ray_time)
     }
 
     fn pixel_sample_square(&self) -> Vec3 {
@@ -248,3 +497,100 @@ impl Camera {
         return self.center + self.defocus_disk_u * p.x() + self.defocus_disk_v * p.y();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cuboid::Cuboid;
+    use crate::hittable_list::HittableList;
+    use crate::constant_medium::ConstantMedium;
+    use crate::material::{Dielectric, DiffuseLight, RoughDielectric};
+    use crate::sphere::Sphere;
+    use crate::quad::Quad;
+
+    const SAMPLES: usize = 100_000;
+    const TOLERANCE: f64 = 0.02;
+
+    fn light() -> Quad {
+        Quad::new(
+            Point3::new(-0.3, -0.3, 0.6),
+            Vec3::new(0.6, 0.0, 0.0),
+            Vec3::new(0.0, 0.6, 0.0),
+            Box::new(DiffuseLight::new(&Color::new(4.0, 4.0, 4.0))),
+        )
+    }
+
+    fn mean(camera: &Camera, world: &dyn Hittable, samples: usize) -> f64 {
+        let mut sum = 0.0;
+        for _ in 0..samples {
+            let target = Vec3::new(random_double() - 0.5, random_double() - 0.5, 0.0) * 0.4;
+            let origin = Point3::new(0.0, 0.0, -1.0);
+            let ray = Ray::new(&origin, &(target - origin));
+            let c = camera.ray_color(&ray, camera.max_depth, world);
+            sum += (c.x() + c.y() + c.z()) / 3.0;
+        }
+        sum / samples as f64
+    }
+
+    /// Average of a render lit only through the glass, with and without light sampling
+    fn lit_through(glass: Box<dyn Hittable + Sync + Send>) -> (f64, f64) {
+        let mut world = HittableList::new();
+        world.add(glass);
+        world.add(Box::new(light()));
+
+        let mut camera = Camera {
+            background: Some(Color::default()),
+            max_depth: 8,
+            ..Default::default()
+        };
+        let brute = mean(&camera, &world, SAMPLES);
+        camera.lights = Some(Arc::new(light()));
+        let mis = mean(&camera, &world, SAMPLES);
+        (brute, mis)
+    }
+
+    #[test]
+    fn light_behind_rough_glass_matches_without_mis() {
+        // A single sheet is lit by light samples through it, a slab by ones leaving its back
+        let sheet = Quad::new(
+            Point3::new(-1.0, -1.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            Box::new(RoughDielectric::new(1.5, 0.3)),
+        );
+        let slab = Cuboid::new(
+            &Point3::new(-1.0, -1.0, 0.0),
+            &Point3::new(1.0, 1.0, 0.2),
+            Box::new(RoughDielectric::new(1.5, 0.3)),
+        );
+        for glass in [
+            Box::new(sheet) as Box<dyn Hittable + Sync + Send>,
+            Box::new(slab),
+        ] {
+            let (brute, mis) = lit_through(glass);
+            assert!((brute - mis).abs() < TOLERANCE * brute, "{brute} vs {mis}");
+        }
+    }
+
+    #[test]
+    fn fog_inside_glass_matches_without_mis() {
+        // Light samples from inside the shell are blocked by the glass, so the light reaching
+        // the fog through it all comes from paths refracting out with full weight
+        let glass = |radius| {
+            Sphere::new(
+                Point3::new(0.0, 0.0, 0.3),
+                radius,
+                Box::new(Dielectric::new(1.5)),
+            )
+        };
+        let mut shell = HittableList::new();
+        shell.add(Box::new(glass(0.25)));
+        shell.add(Box::new(ConstantMedium::new(
+            Arc::new(glass(0.249)),
+            3.0,
+            &Color::new(0.8, 0.8, 0.8),
+        )));
+        let (brute, mis) = lit_through(Box::new(shell));
+        assert!((brute - mis).abs() < TOLERANCE * brute, "{brute} vs {mis}");
+    }
+}
@@ -0,0 +1,135 @@
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::Material;
+use super::onb::Onb;
+use super::poly::solve_quadratic;
+use super::ray::Ray;
+use super::rtweekend::PI;
+use super::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Points within radius of the segment from p0 to p1: a cylinder closed by two half spheres.
+/// u is the angle around the axis and v runs from the tip of the p0 cap to the tip of the
+/// p1 cap, both in [0, 1].
+pub struct Capsule {
+    p0: Point3,
+    length: f64,
+    radius: f64,
+    mat: Arc<Box<dyn Material + Sync + Send>>,
+    /// Frame whose w points from p0 to p1, hits are computed in it
+    frame: Onb,
+    bbox: Aabb,
+}
+
+impl Capsule {
+    pub fn new(p0: Point3, p1: Point3, radius: f64, m: Box<dyn Material + Sync + Send>) -> Self {
+        let rvec = Vec3::new(radius, radius, radius);
+        let bbox = Aabb::new_boxes(
+            &Aabb::new_points(&(p0 - rvec), &(p0 + rvec)),
+            &Aabb::new_points(&(p1 - rvec), &(p1 + rvec)),
+        );
+
+        // A capsule with both ends on the same spot is a sphere, any axis will do
+        let axis = p1 - p0;
+        let frame = if axis.near_zero() {
+            Onb::new_from_w(&Vec3::new(0.0, 0.0, 1.0))
+        } else {
+            Onb::new_from_w(&axis)
+        };
+
+        Capsule {
+            p0,
+            length: axis.length(),
+            radius,
+            mat: Arc::new(m),
+            frame,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Capsule {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let o = self.frame.to_local(&(r.origin() - self.p0));
+        let d = self.frame.to_local(&r.direction());
+        let radius_squared = self.radius * self.radius;
+
+        // Closest hit so far, with its normal in the capsule's frame
+        let mut closest: Option<(f64, Vec3)> = None;
+        let mut consider = |t: f64, normal: Vec3| {
+            if ray_t.surrounds(t) && closest.is_none_or(|(best, _)| t < best) {
+                closest = Some((t, normal));
+            }
+        };
+
+        // Cylindrical part, only between the two end points
+        let (roots, count) = solve_quadratic(
+            d.x() * d.x() + d.y() * d.y(),
+            2.0 * (o.x() * d.x() + o.y() * d.y()),
+            o.x() * o.x() + o.y() * o.y() - radius_squared,
+        );
+        for &t in &roots[..count] {
+            let p = o + d * t;
+            if (0.0..=self.length).contains(&p.z()) {
+                consider(t, Vec3::new(p.x(), p.y(), 0.0) / self.radius);
+            }
+        }
+
+        // Half spheres, each only on its own side of the segment
+        for center_z in [0.0, self.length] {
+            let oc = o - Vec3::new(0.0, 0.0, center_z);
+            let (roots, count) = solve_quadratic(
+                d.length_squared(),
+                2.0 * Vec3::dot(&oc, &d),
+                oc.length_squared() - radius_squared,
+            );
+            for &t in &roots[..count] {
+                let p = oc + d * t;
+                let outside = if center_z == 0.0 {
+                    p.z() <= 0.0
+                } else {
+                    p.z() >= 0.0
+                };
+                if outside {
+                    consider(t, p / self.radius);
+                }
+            }
+        }
+
+        let (t, normal) = match closest {
+            Some(hit) => hit,
+            None => return false,
+        };
+
+        let local = o + d * t;
+        rec.u = (local.y().atan2(local.x()) + PI) / (2.0 * PI);
+        rec.v = ((local.z() + self.radius) / (self.length + 2.0 * self.radius)).clamp(0.0, 1.0);
+
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.mat = self.mat.clone();
+        rec.vertex_color = None;
+        rec.set_face_normal(r, &self.frame.local(&normal));
+        // v grows with the height along the axis, so dpdv follows the surface towards p1 at
+        // whatever slope gives that height. It vanishes at the tips of the caps.
+        let dpdu = Vec3::new(-local.y(), local.x(), 0.0) * (2.0 * PI);
+        let sin_squared = 1.0 - normal.z() * normal.z();
+        let dpdv = if sin_squared < 1e-8 {
+            Vec3::default()
+        } else {
+            Vec3::new(
+                -normal.x() * normal.z(),
+                -normal.y() * normal.z(),
+                sin_squared,
+            ) * ((self.length + 2.0 * self.radius) / sin_squared)
+        };
+        rec.set_derivatives(&self.frame.local(&dpdu), &self.frame.local(&dpdv));
+
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
@@ -1,37 +1,42 @@
 use super::vec3::Vec3;
 use super::interval::Interval;
+use std::io::{self, Write};
 
 pub type Color = Vec3;
 
-fn linear_to_gamma(linear_component: f64) -> f64 {
-    return linear_component.sqrt();
+/// Encode a linear component with the sRGB transfer function
+pub fn linear_to_srgb(linear_component: f64) -> f64 {
+    if linear_component <= 0.0031308 {
+        12.92 * linear_component
+    } else {
+        1.055 * linear_component.powf(1.0 / 2.4) - 0.055
+    }
 }
 
-pub fn write_color(out: &mut String, pixel_color: &Color, samples_per_pixel: i32) {
-    let mut r = pixel_color.x();
-    let mut g = pixel_color.y();
-    let mut b = pixel_color.z();
-
-    let scale = 1.0 / (samples_per_pixel as f64);
-    r *= scale;
-    g *= scale;
-    b *= scale;
+/// Decode an sRGB encoded component back to linear
+pub fn srgb_to_linear(encoded_component: f64) -> f64 {
+    if encoded_component <= 0.04045 {
+        encoded_component / 12.92
+    } else {
+        ((encoded_component + 0.055) / 1.055).powf(2.4)
+    }
+}
 
-    // Apply the linear to gmma transform
-    r = linear_to_gamma(r);
-    g = linear_to_gamma(g);
-    b = linear_to_gamma(b);
+/// Write one pixel as an ASCII triplet, pixel_color being the linear color averaged over
+/// its samples
+pub fn write_color<W: Write>(out: &mut W, pixel_color: &Color) -> io::Result<()> {
+    // Apply the linear to sRGB transform
+    let r = linear_to_srgb(pixel_color.x());
+    let g = linear_to_srgb(pixel_color.y());
+    let b = linear_to_srgb(pixel_color.z());
 
     let intensity = Interval::new_val(0.0, 0.999);
 
-    out.push_str(
-        format!(
-            "{} {} {}\n",
-            (256.0 * intensity.clamp(r)) as i32,
-            (256.0 * intensity.clamp(g)) as i32,
-            (256.0 * intensity.clamp(b)) as i32,
-        )
-        .as_str(),
+    writeln!(
+        out,
+        "{} {} {}",
+        (256.0 * intensity.clamp(r)) as i32,
+        (256.0 * intensity.clamp(g)) as i32,
+        (256.0 * intensity.clamp(b)) as i32,
     )
 }
-
@@ -0,0 +1,151 @@
+use super::aabb::Aabb;
+use super::disk::disk_extent;
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::Material;
+use super::onb::Onb;
+use super::poly::solve_quadratic;
+use super::ray::Ray;
+use super::rtweekend::PI;
+use super::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Closed cone from base to base + axis, whose radius goes linearly from base_radius to
+/// top_radius. A zero top radius gives a pointed cone, anything else a truncated one. UVs
+/// follow the same layout as `Cylinder`.
+pub struct Cone {
+    base: Point3,
+    height: f64,
+    base_radius: f64,
+    top_radius: f64,
+    mat: Arc<Box<dyn Material + Sync + Send>>,
+    /// Frame whose w points along the axis, hits are computed in it
+    frame: Onb,
+    bbox: Aabb,
+}
+
+impl Cone {
+    /// Pointed cone with its apex at base + axis
+    pub fn new(base: Point3, axis: Vec3, radius: f64, m: Box<dyn Material + Sync + Send>) -> Self {
+        Cone::new_truncated(base, axis, radius, 0.0, m)
+    }
+
+    pub fn new_truncated(
+        base: Point3,
+        axis: Vec3,
+        base_radius: f64,
+        top_radius: f64,
+        m: Box<dyn Material + Sync + Send>,
+    ) -> Self {
+        let frame = Onb::new_from_w(&axis);
+        let base_extent = disk_extent(&frame.w, base_radius);
+        let top_extent = disk_extent(&frame.w, top_radius);
+        let bbox = Aabb::new_boxes(
+            &Aabb::new_points(&(base - base_extent), &(base + base_extent)),
+            &Aabb::new_points(&(base + axis - top_extent), &(base + axis + top_extent)),
+        );
+
+        Cone {
+            base,
+            height: axis.length(),
+            base_radius,
+            top_radius,
+            mat: Arc::new(m),
+            frame,
+            bbox,
+        }
+    }
+
+    fn radius_at(&self, z: f64) -> f64 {
+        self.base_radius + (self.top_radius - self.base_radius) * z / self.height
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let o = self.frame.to_local(&(r.origin() - self.base));
+        let d = self.frame.to_local(&r.direction());
+
+        // Closest hit so far, with its normal in the cone's frame
+        let mut closest: Option<(f64, Vec3)> = None;
+        let mut consider = |t: f64, normal: Vec3| {
+            if ray_t.surrounds(t) && closest.is_none_or(|(best, _)| t < best) {
+                closest = Some((t, normal));
+            }
+        };
+
+        // x^2 + y^2 = radius(z)^2, with the radius changing by slope per unit of height
+        let slope = (self.top_radius - self.base_radius) / self.height;
+        let origin_radius = self.radius_at(o.z());
+        let (roots, count) = solve_quadratic(
+            d.x() * d.x() + d.y() * d.y() - slope * slope * d.z() * d.z(),
+            2.0 * (o.x() * d.x() + o.y() * d.y() - slope * origin_radius * d.z()),
+            o.x() * o.x() + o.y() * o.y() - origin_radius * origin_radius,
+        );
+        for &t in &roots[..count] {
+            let p = o + d * t;
+            // The equation also describes the mirrored cone past the apex, which the height
+            // check throws away
+            if (0.0..=self.height).contains(&p.z()) {
+                let normal = Vec3::new(p.x(), p.y(), -slope * self.radius_at(p.z()));
+                consider(t, Vec3::unit_vector(&normal));
+            }
+        }
+
+        if d.z() != 0.0 {
+            for (z, radius, normal_z) in [
+                (0.0, self.base_radius, -1.0),
+                (self.height, self.top_radius, 1.0),
+            ] {
+                let t = (z - o.z()) / d.z();
+                let p = o + d * t;
+                if p.x() * p.x() + p.y() * p.y() <= radius * radius {
+                    consider(t, Vec3::new(0.0, 0.0, normal_z));
+                }
+            }
+        }
+
+        let (t, normal) = match closest {
+            Some(hit) => hit,
+            None => return false,
+        };
+
+        let local = o + d * t;
+        let (dpdu, dpdv) = if normal.x() != 0.0 || normal.y() != 0.0 {
+            rec.u = (local.y().atan2(local.x()) + PI) / (2.0 * PI);
+            rec.v = local.z() / self.height;
+            // Moving up the side also moves outwards by slope per unit of height
+            let radius = self.radius_at(local.z()).max(1e-8);
+            (
+                Vec3::new(-local.y(), local.x(), 0.0) * (2.0 * PI),
+                Vec3::new(slope * local.x() / radius, slope * local.y() / radius, 1.0)
+                    * self.height,
+            )
+        } else {
+            let radius = if normal.z() < 0.0 {
+                self.base_radius
+            } else {
+                self.top_radius
+            };
+            rec.u = 0.5 * (local.x() / radius + 1.0);
+            rec.v = 0.5 * (local.y() / radius + 1.0);
+            (
+                Vec3::new(2.0 * radius, 0.0, 0.0),
+                Vec3::new(0.0, 2.0 * radius, 0.0),
+            )
+        };
+
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.mat = self.mat.clone();
+        rec.vertex_color = None;
+        rec.set_face_normal(r, &self.frame.local(&normal));
+        rec.set_derivatives(&self.frame.local(&dpdu), &self.frame.local(&dpdv));
+
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
@@ -0,0 +1,195 @@
+use super::aabb::Aabb;
+use super::color::Color;
+use super::hittable::{HitRecord, Hittable};
+use super::interval::{self, Interval};
+use super::material::{Isotropic, Material};
+use super::ray::Ray;
+use super::rtweekend::{random_double, INFINITY};
+use super::texture::Texture;
+use super::vec3::Vec3;
+use std::sync::Arc;
+
+/// Volume of uniform density filling a closed boundary, such as fog or smoke. A ray going
+/// through it scatters at a random distance whose probability grows with the density,
+/// otherwise it passes through as if the volume wasn't there.
+///
+/// The boundary may have any closed shape, including several separate pieces, and rays may
+/// start inside the volume.
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable + Sync + Send>,
+    neg_inv_density: f64,
+    phase_function: Arc<Box<dyn Material + Sync + Send>>,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hittable + Sync + Send>, density: f64, albedo: &Color) -> Self {
+        ConstantMedium::new_material(boundary, density, Box::new(Isotropic::new(albedo)))
+    }
+
+    pub fn new_texture(
+        boundary: Arc<dyn Hittable + Sync + Send>,
+        density: f64,
+        albedo: Arc<dyn Texture + Sync + Send>,
+    ) -> Self {
+        ConstantMedium::new_material(boundary, density, Box::new(Isotropic::new_texture(albedo)))
+    }
+
+    /// Medium scattering with a phase function other than the isotropic one
+    pub fn new_material(
+        boundary: Arc<dyn Hittable + Sync + Send>,
+        density: f64,
+        phase_function: Box<dyn Material + Sync + Send>,
+    ) -> Self {
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase_function: Arc::new(phase_function),
+        }
+    }
+
+    /// Every stretch of r inside the boundary within ray_t, as entry and exit pairs in order
+    fn spans(&self, r: &Ray, ray_t: Interval) -> Vec<(f64, f64)> {
+        // Walk the whole line from one boundary crossing to the next, then clip each stretch
+        // to ray_t, which is how rays starting inside get the right stretch
+        let mut spans = Vec::new();
+        let mut search = interval::UNIVERSE;
+        loop {
+            let mut rec1 = HitRecord::default();
+            let mut rec2 = HitRecord::default();
+
+            if !self.boundary.hit(r, search, &mut rec1) || rec1.t >= ray_t.max {
+                break;
+            }
+
+            if !self
+                .boundary
+                .hit(r, Interval::new_val(rec1.t + 0.0001, INFINITY), &mut rec2)
+            {
+                break;
+            }
+
+            let t_enter = rec1.t.max(ray_t.min).max(0.0);
+            let t_exit = rec2.t.min(ray_t.max);
+            if t_enter < t_exit {
+                spans.push((t_enter, t_exit));
+            }
+            search = Interval::new_val(rec2.t + 0.0001, INFINITY);
+        }
+
+        spans
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let ray_length = r.direction().length();
+        let mut hit_distance = self.neg_inv_density * random_double().ln();
+
+        // The free flight distance only counts while inside, so it carries over the gaps
+        // between the stretches of a boundary that isn't convex
+        let mut t_hit = None;
+        for (t_enter, t_exit) in self.spans(r, ray_t) {
+            let distance_inside_boundary = (t_exit - t_enter) * ray_length;
+            if hit_distance <= distance_inside_boundary {
+                t_hit = Some(t_enter + hit_distance / ray_length);
+                break;
+            }
+            hit_distance -= distance_inside_boundary;
+        }
+        let t_hit = match t_hit {
+            Some(t) => t,
+            None => return false,
+        };
+
+        rec.t = t_hit;
+        rec.p = r.at(rec.t);
+
+        // Scattering inside a volume has no surface, the normal and sides are arbitrary
+        rec.normal = Vec3::new(1.0, 0.0, 0.0);
+        rec.shading_normal = rec.normal;
+        rec.front_face = true;
+        rec.u = 0.0;
+        rec.v = 0.0;
+        rec.vertex_color = None;
+        rec.mat = self.phase_function.clone();
+
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.boundary.bounding_box()
+    }
+
+    /// Beer-Lambert over the stretches of the ray inside the boundary
+    fn transmittance(&self, r: &Ray, ray_t: Interval) -> f64 {
+        let inside: f64 = self
+            .spans(r, ray_t)
+            .iter()
+            .map(|(t_enter, t_exit)| t_exit - t_enter)
+            .sum();
+        let distance = inside * r.direction().length();
+        (distance / self.neg_inv_density).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable_list::HittableList;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vec3::Point3;
+
+    const DENSITY: f64 = 0.5;
+
+    /// Fog filling two unit spheres centered at x = 0 and x = 4
+    fn two_blobs() -> ConstantMedium {
+        let mut boundary = HittableList::new();
+        for x in [0.0, 4.0] {
+            boundary.add(Box::new(Sphere::new(
+                Point3::new(x, 0.0, 0.0),
+                1.0,
+                Box::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5))),
+            )));
+        }
+        ConstantMedium::new(Arc::new(boundary), DENSITY, &Color::new(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn transmittance_covers_every_piece() {
+        let fog = two_blobs();
+        let through = Ray::new(&Point3::new(-5.0, 0.0, 0.0), &Vec3::new(1.0, 0.0, 0.0));
+        let t = fog.transmittance(&through, Interval::new_val(0.001, INFINITY));
+        assert!((t - (-DENSITY * 4.0).exp()).abs() < 1e-9);
+
+        // Starting inside the first sphere and stopping inside the second
+        let inside = Ray::new(&Point3::new(0.5, 0.0, 0.0), &Vec3::new(2.0, 0.0, 0.0));
+        let t = fog.transmittance(&inside, Interval::new_val(0.0, 1.75));
+        assert!((t - (-DENSITY * 1.5).exp()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn hits_match_transmittance() {
+        let fog = two_blobs();
+        let r = Ray::new(&Point3::new(-5.0, 0.0, 0.0), &Vec3::new(1.0, 0.0, 0.0));
+        let samples = 200_000;
+        let mut in_second = 0;
+        let mut missed = 0;
+        for _ in 0..samples {
+            let mut rec = HitRecord::default();
+            if !fog.hit(&r, Interval::new_val(0.001, INFINITY), &mut rec) {
+                missed += 1;
+                continue;
+            }
+            let x = rec.p.x();
+            assert!(x.abs() <= 1.0 + 1e-9 || (x - 4.0).abs() <= 1.0 + 1e-9);
+            if x > 2.0 {
+                in_second += 1;
+            }
+        }
+        let fraction = |count: i32| count as f64 / samples as f64;
+        let one_blob = (-DENSITY * 2.0).exp();
+        assert!((fraction(missed) - one_blob * one_blob).abs() < 0.01);
+        assert!((fraction(in_second) - one_blob * (1.0 - one_blob)).abs() < 0.01);
+    }
+}
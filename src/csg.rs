@@ -0,0 +1,305 @@
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::ray::Ray;
+use super::rtweekend::INFINITY;
+use super::vec3::Vec3;
+use std::sync::Arc;
+
+/// Distance, in world units, a search for the next crossing starts past the previous one
+const CROSSING_EPSILON: f64 = 1e-7;
+
+/// Upper bound on the surface crossings walked along one ray, in case a child never stops
+/// reporting hits
+const MAX_CROSSINGS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsgOperation {
+    /// Inside either child
+    Union,
+    /// Inside both children
+    Intersection,
+    /// Inside the left child but not the right one
+    Difference,
+}
+
+impl CsgOperation {
+    fn contains(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// Boolean combination of two closed objects. Both children are walked along the ray one
+/// surface crossing at a time, keeping track of whether the ray is inside each of them, and
+/// the first crossing that changes whether it is inside the result is reported.
+///
+/// Children must be closed and have outward facing normals, `front_face` is what tells
+/// entering and leaving apart. CSG nodes can be children of other CSG nodes.
+pub struct Csg {
+    operation: CsgOperation,
+    left: Arc<dyn Hittable + Sync + Send>,
+    right: Arc<dyn Hittable + Sync + Send>,
+    bbox: Aabb,
+}
+
+impl Csg {
+    pub fn new(
+        operation: CsgOperation,
+        left: Arc<dyn Hittable + Sync + Send>,
+        right: Arc<dyn Hittable + Sync + Send>,
+    ) -> Self {
+        let left_box = left.bounding_box();
+        let right_box = right.bounding_box();
+        let bbox = match operation {
+            CsgOperation::Union => Aabb::new_boxes(&left_box, &right_box),
+            CsgOperation::Intersection => Aabb::new(
+                overlap(&left_box.x, &right_box.x),
+                overlap(&left_box.y, &right_box.y),
+                overlap(&left_box.z, &right_box.z),
+            ),
+            CsgOperation::Difference => left_box,
+        };
+
+        Csg {
+            operation,
+            left,
+            right,
+            bbox,
+        }
+    }
+
+    pub fn union(
+        left: Arc<dyn Hittable + Sync + Send>,
+        right: Arc<dyn Hittable + Sync + Send>,
+    ) -> Self {
+        Csg::new(CsgOperation::Union, left, right)
+    }
+
+    pub fn intersection(
+        left: Arc<dyn Hittable + Sync + Send>,
+        right: Arc<dyn Hittable + Sync + Send>,
+    ) -> Self {
+        Csg::new(CsgOperation::Intersection, left, right)
+    }
+
+    pub fn difference(
+        left: Arc<dyn Hittable + Sync + Send>,
+        right: Arc<dyn Hittable + Sync + Send>,
+    ) -> Self {
+        Csg::new(CsgOperation::Difference, left, right)
+    }
+
+    /// First surface crossing of object along r after t_min, however far away
+    fn next_crossing(
+        object: &Arc<dyn Hittable + Sync + Send>,
+        r: &Ray,
+        t_min: f64,
+    ) -> Option<HitRecord> {
+        let mut rec = HitRecord::default();
+        if object.hit(r, Interval::new_val(t_min, INFINITY), &mut rec) {
+            Some(rec)
+        } else {
+            None
+        }
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        if !self.bbox.hit(r, ray_t) {
+            return false;
+        }
+
+        let step = CROSSING_EPSILON / r.direction().length();
+        let mut next_left = Csg::next_crossing(&self.left, r, ray_t.min);
+        let mut next_right = Csg::next_crossing(&self.right, r, ray_t.min);
+
+        // A closed object is left before it is entered when the ray starts inside it
+        let mut in_left = next_left.as_ref().is_some_and(|h| !h.front_face);
+        let mut in_right = next_right.as_ref().is_some_and(|h| !h.front_face);
+        let inside = self.operation.contains(in_left, in_right);
+
+        for _ in 0..MAX_CROSSINGS {
+            let from_left = match (&next_left, &next_right) {
+                (Some(left), Some(right)) => left.t <= right.t,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return false,
+            };
+
+            let mut crossing = if from_left {
+                next_left.take()
+            } else {
+                next_right.take()
+            }
+            .unwrap_or_default();
+            if crossing.t >= ray_t.max {
+                return false;
+            }
+
+            if from_left {
+                in_left = crossing.front_face;
+                next_left = Csg::next_crossing(&self.left, r, crossing.t + step);
+            } else {
+                in_right = crossing.front_face;
+                next_right = Csg::next_crossing(&self.right, r, crossing.t + step);
+            }
+
+            let now_inside = self.operation.contains(in_left, in_right);
+            if now_inside == inside {
+                continue;
+            }
+
+            // Subtracted surfaces bound the result from the other side
+            let mut outward_normal = if crossing.front_face {
+                crossing.normal
+            } else {
+                -crossing.normal
+            };
+            if !from_left && self.operation == CsgOperation::Difference {
+                outward_normal = -outward_normal;
+            }
+
+            crossing.front_face = now_inside;
+            let normal = if now_inside {
+                outward_normal
+            } else {
+                -outward_normal
+            };
+            if Vec3::dot(&normal, &crossing.normal) < 0.0 {
+                crossing.shading_normal = -crossing.shading_normal;
+                crossing.update_shading_frame();
+            }
+            crossing.normal = normal;
+            *rec = crossing;
+            return true;
+        }
+
+        false
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+/// Part of two intervals they have in common, empty when they don't overlap
+fn overlap(a: &Interval, b: &Interval) -> Interval {
+    Interval::new_val(a.min.max(b.min), a.max.min(b.max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::Lambertian;
+    use crate::rtweekend::random_double;
+    use crate::sphere::Sphere;
+    use crate::vec3::Point3;
+
+    const LEFT: Point3 = Point3 {
+        e: (-0.5, 0.0, 0.0),
+    };
+    const RIGHT: Point3 = Point3 { e: (0.5, 0.0, 0.0) };
+
+    fn ball(center: Point3) -> Arc<dyn Hittable + Sync + Send> {
+        Arc::new(Sphere::new(
+            center,
+            1.0,
+            Box::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5))),
+        ))
+    }
+
+    fn hit(object: &Csg, origin: Point3, direction: Vec3) -> Option<HitRecord> {
+        let mut rec = HitRecord::default();
+        let r = Ray::new(&origin, &direction);
+        if object.hit(&r, Interval::new_val(0.001, INFINITY), &mut rec) {
+            Some(rec)
+        } else {
+            None
+        }
+    }
+
+    /// Where the first hit along x is, whether it enters, and the x of its normal
+    fn along_x(object: &Csg, from: f64) -> Option<(f64, bool, f64)> {
+        let direction = Vec3::new(if from < 0.0 { 1.0 } else { -1.0 }, 0.0, 0.0);
+        hit(object, Point3::new(from, 0.0, 0.0), direction)
+            .map(|rec| (rec.p.x(), rec.front_face, rec.normal.x()))
+    }
+
+    fn assert_crossing(found: Option<(f64, bool, f64)>, expected: (f64, bool, f64)) {
+        let (x, front_face, normal_x) = found.expect("expected a hit");
+        assert!((x - expected.0).abs() < 1e-9, "hit at {x}");
+        assert_eq!(front_face, expected.1);
+        assert!((normal_x - expected.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn operations_along_a_line() {
+        // The balls cover [-1.5, 0.5] and [-0.5, 1.5] along x
+        let union = Csg::union(ball(LEFT), ball(RIGHT));
+        assert_crossing(along_x(&union, -5.0), (-1.5, true, -1.0));
+        assert_crossing(along_x(&union, 0.0), (-1.5, false, 1.0));
+
+        let intersection = Csg::intersection(ball(LEFT), ball(RIGHT));
+        assert_crossing(along_x(&intersection, -5.0), (-0.5, true, -1.0));
+        assert_crossing(along_x(&intersection, 5.0), (0.5, true, 1.0));
+        assert_crossing(along_x(&intersection, -1.0), (-0.5, true, -1.0));
+
+        // The right ball is carved out of the left one, leaving [-1.5, -0.5]
+        let difference = Csg::difference(ball(LEFT), ball(RIGHT));
+        assert_crossing(along_x(&difference, -5.0), (-1.5, true, -1.0));
+        assert_crossing(along_x(&difference, 5.0), (-0.5, true, 1.0));
+        assert_crossing(along_x(&difference, -1.0), (-0.5, false, -1.0));
+        assert_crossing(along_x(&difference, 0.0), (-0.5, true, 1.0));
+    }
+
+    #[test]
+    fn nested_nodes_and_bounds() {
+        let pair = Arc::new(Csg::union(ball(LEFT), ball(RIGHT)));
+        let bite = ball(Point3::new(2.0, 0.0, 0.0));
+        let bitten = Csg::difference(pair, bite);
+        assert_crossing(along_x(&bitten, 5.0), (1.0, true, 1.0));
+        assert_crossing(along_x(&bitten, -5.0), (-1.5, true, -1.0));
+
+        // Intersections are bounded by the overlap of the children
+        let lens = Csg::intersection(ball(LEFT), ball(RIGHT));
+        let x = lens.bounding_box().x;
+        assert!((x.min + 0.5).abs() < 1e-9 && (x.max - 0.5).abs() < 1e-9);
+        let apart = Csg::intersection(ball(Point3::new(-3.0, 0.0, 0.0)), ball(RIGHT));
+        assert!(along_x(&apart, -5.0).is_none());
+    }
+
+    #[test]
+    fn hits_switch_between_inside_and_outside() {
+        let inside_left = |p: &Point3| (*p - LEFT).length_squared() < 1.0;
+        let inside_right = |p: &Point3| (*p - RIGHT).length_squared() < 1.0;
+
+        for operation in [
+            CsgOperation::Union,
+            CsgOperation::Intersection,
+            CsgOperation::Difference,
+        ] {
+            let object = Csg::new(operation, ball(LEFT), ball(RIGHT));
+            let inside = |p: &Point3| operation.contains(inside_left(p), inside_right(p));
+
+            for _ in 0..2_000 {
+                let origin = Vec3::random_unit_vector() * (3.0 * random_double());
+                let direction = Vec3::random_unit_vector();
+                let rec = match hit(&object, origin, direction) {
+                    Some(rec) => rec,
+                    None => continue,
+                };
+                let before = rec.p - direction * 1e-6;
+                let after = rec.p + direction * 1e-6;
+                assert_eq!(inside(&before), !rec.front_face, "{operation:?}");
+                assert_eq!(inside(&after), rec.front_face, "{operation:?}");
+                assert!(Vec3::dot(&rec.normal, &direction) < 0.0);
+            }
+        }
+    }
+}
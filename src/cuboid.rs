@@ -0,0 +1,91 @@
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable};
+use super::hittable_list::HittableList;
+use super::interval::Interval;
+use super::material::Material;
+use super::quad::Quad;
+use super::ray::Ray;
+use super::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Closed box made of six quads sharing one material, with outward facing normals
+pub struct Cuboid {
+    sides: HittableList,
+    bbox: Aabb,
+}
+
+impl Cuboid {
+    /// Axis-aligned box with opposite corners a and b
+    pub fn new(a: &Point3, b: &Point3, m: Box<dyn Material + Sync + Send>) -> Self {
+        let min = Point3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z()));
+        let max = Point3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z()));
+        let size = max - min;
+
+        Cuboid::new_oriented(
+            &min,
+            &Vec3::new(size.x(), 0.0, 0.0),
+            &Vec3::new(0.0, size.y(), 0.0),
+            &Vec3::new(0.0, 0.0, size.z()),
+            m,
+        )
+    }
+
+    ///
+    /// Box spanned by three edges leaving the same corner, which may point in any direction
+    /// * `corner` - Corner the edges start from
+    /// * `e0`, `e1`, `e2` - Edges, expected to be perpendicular to each other
+    /// * `m` - Material of every side
+    pub fn new_oriented(
+        corner: &Point3,
+        e0: &Vec3,
+        e1: &Vec3,
+        e2: &Vec3,
+        m: Box<dyn Material + Sync + Send>,
+    ) -> Self {
+        // Swap to a right-handed set of edges so every side's normal points outwards
+        let (e0, e1) = if Vec3::dot(&Vec3::cross(e0, e1), e2) < 0.0 {
+            (e1, e0)
+        } else {
+            (e0, e1)
+        };
+        let o = corner.to_owned();
+        let mat = Arc::new(m);
+
+        let mut sides = HittableList::new();
+        let faces = [
+            (o + e2, e0, e1),
+            (o, e1, e0),
+            (o + e0, e1, e2),
+            (o, e2, e1),
+            (o + e1, e2, e0),
+            (o, e0, e2),
+        ];
+        for (q, u, v) in faces {
+            sides.add(Box::new(Quad::new_shared(q, *u, *v, mat.clone())));
+        }
+
+        let bbox = sides.bounding_box();
+        Cuboid { sides, bbox }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        if !self.bbox.hit(r, ray_t) {
+            return false;
+        }
+        self.sides.hit(r, ray_t, rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.sides.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        self.sides.random(origin)
+    }
+}
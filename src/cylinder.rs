@@ -0,0 +1,116 @@
+use super::aabb::Aabb;
+use super::disk::disk_extent;
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::Material;
+use super::onb::Onb;
+use super::poly::solve_quadratic;
+use super::ray::Ray;
+use super::rtweekend::PI;
+use super::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Closed cylinder from base to base + axis. On the side u is the angle around the axis and
+/// v the height, both in [0, 1]; on the caps u and v are the planar position over the cap.
+pub struct Cylinder {
+    base: Point3,
+    height: f64,
+    radius: f64,
+    mat: Arc<Box<dyn Material + Sync + Send>>,
+    /// Frame whose w points along the axis, hits are computed in it
+    frame: Onb,
+    bbox: Aabb,
+}
+
+impl Cylinder {
+    pub fn new(base: Point3, axis: Vec3, radius: f64, m: Box<dyn Material + Sync + Send>) -> Self {
+        let frame = Onb::new_from_w(&axis);
+        let extent = disk_extent(&frame.w, radius);
+        let bbox = Aabb::new_boxes(
+            &Aabb::new_points(&(base - extent), &(base + extent)),
+            &Aabb::new_points(&(base + axis - extent), &(base + axis + extent)),
+        );
+
+        Cylinder {
+            base,
+            height: axis.length(),
+            radius,
+            mat: Arc::new(m),
+            frame,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let o = self.frame.to_local(&(r.origin() - self.base));
+        let d = self.frame.to_local(&r.direction());
+        let radius_squared = self.radius * self.radius;
+
+        // Closest hit so far, with its normal in the cylinder's frame
+        let mut closest: Option<(f64, Vec3)> = None;
+        let mut consider = |t: f64, normal: Vec3| {
+            if ray_t.surrounds(t) && closest.is_none_or(|(best, _)| t < best) {
+                closest = Some((t, normal));
+            }
+        };
+
+        let (roots, count) = solve_quadratic(
+            d.x() * d.x() + d.y() * d.y(),
+            2.0 * (o.x() * d.x() + o.y() * d.y()),
+            o.x() * o.x() + o.y() * o.y() - radius_squared,
+        );
+        for &t in &roots[..count] {
+            let p = o + d * t;
+            if (0.0..=self.height).contains(&p.z()) {
+                consider(t, Vec3::new(p.x(), p.y(), 0.0) / self.radius);
+            }
+        }
+
+        if d.z() != 0.0 {
+            for (z, normal_z) in [(0.0, -1.0), (self.height, 1.0)] {
+                let t = (z - o.z()) / d.z();
+                let p = o + d * t;
+                if p.x() * p.x() + p.y() * p.y() <= radius_squared {
+                    consider(t, Vec3::new(0.0, 0.0, normal_z));
+                }
+            }
+        }
+
+        let (t, normal) = match closest {
+            Some(hit) => hit,
+            None => return false,
+        };
+
+        let local = o + d * t;
+        let (dpdu, dpdv) = if normal.z() == 0.0 {
+            rec.u = (local.y().atan2(local.x()) + PI) / (2.0 * PI);
+            rec.v = local.z() / self.height;
+            (
+                Vec3::new(-local.y(), local.x(), 0.0) * (2.0 * PI),
+                Vec3::new(0.0, 0.0, self.height),
+            )
+        } else {
+            rec.u = 0.5 * (local.x() / self.radius + 1.0);
+            rec.v = 0.5 * (local.y() / self.radius + 1.0);
+            (
+                Vec3::new(2.0 * self.radius, 0.0, 0.0),
+                Vec3::new(0.0, 2.0 * self.radius, 0.0),
+            )
+        };
+
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.mat = self.mat.clone();
+        rec.vertex_color = None;
+        rec.set_face_normal(r, &self.frame.local(&normal));
+        rec.set_derivatives(&self.frame.local(&dpdu), &self.frame.local(&dpdv));
+
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
@@ -0,0 +1,122 @@
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::Material;
+use super::onb::Onb;
+use super::ray::Ray;
+use super::rtweekend::{random_double, INFINITY, PI};
+use super::triangle::{area_to_solid_angle_pdf, BBOX_PADDING};
+use super::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Half size along each axis of a circle of the given radius facing along the unit vector
+/// normal, which is radius times the sine of the angle between the axis and the normal
+pub(crate) fn disk_extent(normal: &Vec3, radius: f64) -> Vec3 {
+    Vec3::new(
+        radius * (1.0 - normal.x() * normal.x()).max(0.0).sqrt(),
+        radius * (1.0 - normal.y() * normal.y()).max(0.0).sqrt(),
+        radius * (1.0 - normal.z() * normal.z()).max(0.0).sqrt(),
+    )
+}
+
+/// Flat circle facing along normal. u is the angle around the center and v the distance
+/// from it, both remapped to [0, 1].
+pub struct Disk {
+    center: Point3,
+    radius: f64,
+    mat: Arc<Box<dyn Material + Sync + Send>>,
+    frame: Onb,
+    bbox: Aabb,
+}
+
+impl Disk {
+    pub fn new(
+        center: Point3,
+        normal: Vec3,
+        radius: f64,
+        m: Box<dyn Material + Sync + Send>,
+    ) -> Self {
+        let frame = Onb::new_from_w(&normal);
+        let extent = disk_extent(&frame.w, radius);
+        let bbox = Aabb::new_points(&(center - extent), &(center + extent)).pad(BBOX_PADDING);
+
+        Disk {
+            center,
+            radius,
+            mat: Arc::new(m),
+            frame,
+            bbox,
+        }
+    }
+
+    /// Ray parameter and the hit point in the disk's frame, if the ray hits the disk
+    fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<(f64, Vec3)> {
+        let denom = Vec3::dot(&self.frame.w, &r.direction());
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = Vec3::dot(&self.frame.w, &(self.center - r.origin())) / denom;
+        if !ray_t.surrounds(t) {
+            return None;
+        }
+
+        let local = self.frame.to_local(&(r.at(t) - self.center));
+        if local.x() * local.x() + local.y() * local.y() > self.radius * self.radius {
+            return None;
+        }
+
+        Some((t, local))
+    }
+
+    fn area(&self) -> f64 {
+        PI * self.radius * self.radius
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let (t, local) = match self.intersect(r, ray_t) {
+            Some(hit) => hit,
+            None => return false,
+        };
+
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.u = (local.y().atan2(local.x()) + PI) / (2.0 * PI);
+        rec.v = (local.x() * local.x() + local.y() * local.y()).sqrt() / self.radius;
+        rec.mat = self.mat.clone();
+        rec.vertex_color = None;
+        rec.set_face_normal(r, &self.frame.w);
+        let dpdu = Vec3::new(-local.y(), local.x(), 0.0) * (2.0 * PI);
+        let dpdv = Vec3::new(local.x(), local.y(), 0.0) / rec.v.max(1e-8);
+        rec.set_derivatives(&self.frame.local(&dpdu), &self.frame.local(&dpdv));
+
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        match self.intersect(
+            &Ray::new(origin, direction),
+            Interval::new_val(0.001, INFINITY),
+        ) {
+            Some((t, _)) => area_to_solid_angle_pdf(t, direction, &self.frame.w, self.area()),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        // The square root keeps points uniform over the area instead of bunched at the center
+        let r = self.radius * random_double().sqrt();
+        let phi = 2.0 * PI * random_double();
+        let p = self.center
+            + self
+                .frame
+                .local(&Vec3::new(r * phi.cos(), r * phi.sin(), 0.0));
+        p - origin
+    }
+}
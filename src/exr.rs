@@ -0,0 +1,440 @@
+use super::image::{Channel, Image};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const COMPRESSION_LEVEL: u8 = 6;
+/// Longest attribute or channel name readers accept without the long names flag
+const MAX_SHORT_NAME: usize = 31;
+const LONG_NAMES: u32 = 0x400;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExrPixelType {
+    /// 16-bit float
+    Half,
+    /// 32-bit float
+    Float,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExrCompression {
+    None,
+    /// Deflate, one scanline per block
+    Zips,
+    /// Deflate, 16 scanlines per block
+    Zip,
+}
+
+impl ExrCompression {
+    fn id(&self) -> u8 {
+        match self {
+            ExrCompression::None => 0,
+            ExrCompression::Zips => 2,
+            ExrCompression::Zip => 3,
+        }
+    }
+
+    fn lines_per_block(&self) -> usize {
+        match self {
+            ExrCompression::None | ExrCompression::Zips => 1,
+            ExrCompression::Zip => 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExrOptions {
+    pub pixel_type: ExrPixelType,
+    pub compression: ExrCompression,
+}
+
+impl Default for ExrOptions {
+    fn default() -> Self {
+        ExrOptions {
+            pixel_type: ExrPixelType::Half,
+            compression: ExrCompression::Zip,
+        }
+    }
+}
+
+pub fn save_exr<P: AsRef<Path>>(image: &Image, path: P, options: ExrOptions) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_exr(image, &mut out, options)
+}
+
+/// Write the unclamped linear pixels as an RGB OpenEXR image
+pub fn write_exr<W: Write>(image: &Image, out: &mut W, options: ExrOptions) -> io::Result<()> {
+    let channels = image.to_channels("");
+    write_exr_channels(image.width(), image.height(), &channels, out, options)
+}
+
+///
+/// Write any number of named channels as a single part scanline OpenEXR image, so
+/// auxiliary buffers can travel in the same file as the beauty pass.
+/// * `channels` - Planes of width * height values, stored top row first
+pub fn write_exr_channels<W: Write>(
+    width: usize,
+    height: usize,
+    channels: &[Channel],
+    out: &mut W,
+    options: ExrOptions,
+) -> io::Result<()> {
+    if width == 0 || height == 0 {
+        return Err(invalid_input("image has no pixels"));
+    }
+    if channels.iter().any(|c| c.data.len() != width * height) {
+        return Err(invalid_input("channel size does not match the image size"));
+    }
+
+    // The format requires channels sorted by name
+    let mut sorted: Vec<&Channel> = channels.iter().collect();
+    sorted.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+    if sorted.windows(2).any(|pair| pair[0].name == pair[1].name) {
+        return Err(invalid_input("channel names must be unique"));
+    }
+    if sorted
+        .iter()
+        .any(|c| c.name.is_empty() || c.name.contains('\0'))
+    {
+        return Err(invalid_input("invalid channel name"));
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&MAGIC);
+    // Version 2, single part scanline file. Names past 31 bytes need the long names bit
+    let mut version = 2u32;
+    if sorted.iter().any(|c| c.name.len() > MAX_SHORT_NAME) {
+        version |= LONG_NAMES;
+    }
+    header.extend_from_slice(&version.to_le_bytes());
+
+    let mut chlist = Vec::new();
+    for channel in sorted.iter() {
+        chlist.extend_from_slice(channel.name.as_bytes());
+        chlist.push(0);
+        let pixel_type: u32 = match options.pixel_type {
+            ExrPixelType::Half => 1,
+            ExrPixelType::Float => 2,
+        };
+        chlist.extend_from_slice(&pixel_type.to_le_bytes());
+        // pLinear and reserved bytes, then x and y sampling
+        chlist.extend_from_slice(&[0, 0, 0, 0]);
+        chlist.extend_from_slice(&1u32.to_le_bytes());
+        chlist.extend_from_slice(&1u32.to_le_bytes());
+    }
+    chlist.push(0);
+
+    let mut window = Vec::new();
+    for v in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&v.to_le_bytes());
+    }
+
+    write_attribute(&mut header, "channels", "chlist", &chlist);
+    write_attribute(
+        &mut header,
+        "compression",
+        "compression",
+        &[options.compression.id()],
+    );
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1.0f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    let lines_per_block = options.compression.lines_per_block();
+    let block_count = height.div_ceil(lines_per_block);
+
+    let mut blocks = Vec::with_capacity(block_count);
+    for block in 0..block_count {
+        let y_start = block * lines_per_block;
+        let y_end = (y_start + lines_per_block).min(height);
+
+        // Within a block, every scanline holds each channel's row one after another
+        let mut raw = Vec::new();
+        for y in y_start..y_end {
+            for channel in sorted.iter() {
+                let row = &channel.data[y * width..(y + 1) * width];
+                match options.pixel_type {
+                    ExrPixelType::Half => {
+                        for &v in row {
+                            raw.extend_from_slice(&f32_to_half(v).to_le_bytes());
+                        }
+                    }
+                    ExrPixelType::Float => {
+                        for &v in row {
+                            raw.extend_from_slice(&v.to_le_bytes());
+                        }
+                    }
+                }
+            }
+        }
+
+        let data = match options.compression {
+            ExrCompression::None => raw,
+            ExrCompression::Zips | ExrCompression::Zip => {
+                let compressed = compress_to_vec_zlib(&zip_predict(&raw), COMPRESSION_LEVEL);
+                // Blocks that don't shrink are stored as is, which readers detect by size
+                if compressed.len() < raw.len() {
+                    compressed
+                } else {
+                    raw
+                }
+            }
+        };
+        blocks.push((y_start, data));
+    }
+
+    // The offset table comes right after the header and points at every block
+    let mut offset = (header.len() + 8 * block_count) as u64;
+    out.write_all(&header)?;
+    for (_, data) in blocks.iter() {
+        out.write_all(&offset.to_le_bytes())?;
+        offset += 8 + data.len() as u64;
+    }
+    for (y_start, data) in blocks.iter() {
+        out.write_all(&(*y_start as i32).to_le_bytes())?;
+        out.write_all(&(data.len() as u32).to_le_bytes())?;
+        out.write_all(data)?;
+    }
+    out.flush()
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Reorder bytes and delta encode them the way ZIP compressed EXR blocks expect: first all
+/// even bytes then all odd bytes, with every byte replaced by its difference to the previous
+fn zip_predict(raw: &[u8]) -> Vec<u8> {
+    let half = raw.len().div_ceil(2);
+    let mut interleaved = vec![0u8; raw.len()];
+    for (i, &byte) in raw.iter().enumerate() {
+        let target = if i % 2 == 0 { i / 2 } else { half + i / 2 };
+        interleaved[target] = byte;
+    }
+
+    let mut previous = 0u8;
+    for (i, byte) in interleaved.iter_mut().enumerate() {
+        let current = *byte;
+        if i > 0 {
+            *byte = current.wrapping_sub(previous).wrapping_add(128);
+        }
+        previous = current;
+    }
+    interleaved
+}
+
+/// Convert to IEEE 754 half precision, rounding to nearest even
+pub fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinity, NaN keeps a quiet NaN payload
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    if half_exponent <= 0 {
+        // Subnormal half, or too small and flushed to zero
+        if half_exponent < -10 {
+            return sign;
+        }
+        let m = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let round_bit = 1u32 << (shift - 1);
+        let mut half_mantissa = m >> shift;
+        if (m & round_bit) != 0 && (m & (3 * round_bit - 1)) != 0 {
+            half_mantissa += 1;
+        }
+        return sign | half_mantissa as u16;
+    }
+
+    let mut half = ((half_exponent as u32) << 10) | (mantissa >> 13);
+    let round_bit = 0x1000;
+    if (mantissa & round_bit) != 0 && (mantissa & (3 * round_bit - 1)) != 0 {
+        // A carry out of the mantissa correctly bumps the exponent
+        half += 1;
+    }
+    sign | half as u16
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+    fn channel(name: &str, data: Vec<f32>) -> Channel {
+        Channel {
+            name: name.to_string(),
+            data,
+        }
+    }
+
+    fn version(file: &[u8]) -> u32 {
+        u32::from_le_bytes(file[4..8].try_into().unwrap())
+    }
+
+    /// Skip the magic, version and attributes up to the null byte closing the header
+    fn header_len(file: &[u8]) -> usize {
+        let mut at = 8;
+        loop {
+            if file[at] == 0 {
+                return at + 1;
+            }
+            for _ in 0..2 {
+                at += file[at..].iter().position(|&b| b == 0).unwrap() + 1;
+            }
+            let size = u32::from_le_bytes(file[at..at + 4].try_into().unwrap()) as usize;
+            at += 4 + size;
+        }
+    }
+
+    /// Undo the predictor the way a reader does
+    fn zip_unpredict(data: &[u8]) -> Vec<u8> {
+        let mut deltas = data.to_vec();
+        for i in 1..deltas.len() {
+            deltas[i] = deltas[i - 1].wrapping_add(deltas[i]).wrapping_sub(128);
+        }
+        let half = deltas.len().div_ceil(2);
+        (0..deltas.len())
+            .map(|i| {
+                if i % 2 == 0 {
+                    deltas[i / 2]
+                } else {
+                    deltas[half + i / 2]
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn half_conversion() {
+        assert_eq!(f32_to_half(0.0), 0x0000);
+        assert_eq!(f32_to_half(-0.0), 0x8000);
+        assert_eq!(f32_to_half(1.0), 0x3c00);
+        assert_eq!(f32_to_half(-2.0), 0xc000);
+        assert_eq!(f32_to_half(0.5), 0x3800);
+        assert_eq!(f32_to_half(65504.0), 0x7bff);
+        assert_eq!(f32_to_half(65536.0), 0x7c00);
+        assert_eq!(f32_to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_half(f32::NEG_INFINITY), 0xfc00);
+        assert_eq!(f32_to_half(f32::NAN) & 0x7e00, 0x7e00);
+        // Smallest subnormal, underflow to zero and the smallest normal
+        assert_eq!(f32_to_half(2f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_half(2f32.powi(-26)), 0x0000);
+        assert_eq!(f32_to_half(2f32.powi(-14)), 0x0400);
+        // Ties round to even, anything past the tie rounds up
+        assert_eq!(f32_to_half(1.0 + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(f32_to_half(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+        assert_eq!(f32_to_half(1.0 + 2f32.powi(-11) + 2f32.powi(-20)), 0x3c01);
+        // Rounding carries into the exponent
+        assert_eq!(f32_to_half(2.0 - 2f32.powi(-12)), 0x4000);
+    }
+
+    #[test]
+    fn zip_predictor_round_trips() {
+        let raw: Vec<u8> = (0..37u32).map(|i| (i * i * 7 + 3) as u8).collect();
+        let predicted = zip_predict(&raw);
+        assert_eq!(predicted[0], raw[0]);
+        assert_eq!(zip_unpredict(&predicted), raw);
+
+        // A constant run turns into a run of 128s after the first byte of each half
+        let flat = zip_predict(&[5, 9, 5, 9, 5, 9]);
+        assert_eq!(flat, vec![5, 128, 128, 132, 128, 128]);
+    }
+
+    #[test]
+    fn zip_blocks_decode_to_the_pixels() {
+        let (width, height) = (4, 3);
+        let data: Vec<f32> = (0..width * height).map(|i| i as f32 * 0.25).collect();
+        let options = ExrOptions {
+            pixel_type: ExrPixelType::Float,
+            compression: ExrCompression::Zip,
+        };
+        let mut file = Vec::new();
+        write_exr_channels(
+            width,
+            height,
+            &[channel("Y", data.clone())],
+            &mut file,
+            options,
+        )
+        .unwrap();
+
+        // All three lines fit one block, found through the offset table after the header
+        let table = header_len(&file);
+        let offset = u64::from_le_bytes(file[table..table + 8].try_into().unwrap()) as usize;
+        assert_eq!(offset, table + 8);
+        assert_eq!(&file[offset..offset + 4], &0i32.to_le_bytes());
+        let size = u32::from_le_bytes(file[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        let block_start = offset + 8;
+        assert_eq!(block_start + size, file.len());
+
+        let stored = &file[block_start..];
+        let raw = if stored.len() < width * height * 4 {
+            zip_unpredict(&decompress_to_vec_zlib(stored).unwrap())
+        } else {
+            stored.to_vec()
+        };
+        let decoded: Vec<f32> = raw
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn long_channel_names_set_the_flag() {
+        let options = ExrOptions::default();
+        let mut short = Vec::new();
+        write_exr_channels(1, 1, &[channel("R", vec![1.0])], &mut short, options).unwrap();
+        assert_eq!(version(&short), 2);
+
+        let name = "diffuse.indirect.albedo.filtered.R";
+        assert!(name.len() > MAX_SHORT_NAME);
+        let mut long = Vec::new();
+        write_exr_channels(1, 1, &[channel(name, vec![1.0])], &mut long, options).unwrap();
+        assert_eq!(version(&long), 2 | LONG_NAMES);
+    }
+
+    #[test]
+    fn rejects_bad_channels() {
+        let options = ExrOptions::default();
+        let mut out = Vec::new();
+        let duplicate = [channel("R", vec![0.0]), channel("R", vec![0.0])];
+        assert!(write_exr_channels(1, 1, &duplicate, &mut out, options).is_err());
+        assert!(write_exr_channels(1, 1, &[channel("", vec![0.0])], &mut out, options).is_err());
+        assert!(write_exr_channels(2, 1, &[channel("R", vec![0.0])], &mut out, options).is_err());
+    }
+}
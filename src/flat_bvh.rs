@@ -0,0 +1,581 @@
+use super::aabb::{self, Aabb};
+use super::hittable::{HitRecord, Hittable};
+use super::hittable_list::HittableList;
+use super::interval::Interval;
+use super::ray::Ray;
+use super::vec3::Point3;
+use std::cmp::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Relative cost of visiting an interior node compared to testing one object
+const TRAVERSAL_COST: f64 = 0.125;
+const INTERSECTION_COST: f64 = 1.0;
+const MAX_OBJECTS_PER_LEAF: usize = 4;
+/// Past this depth nodes are split evenly, which bounds the traversal stack
+const MAX_SAH_DEPTH: usize = 64;
+const STACK_SIZE: usize = 128;
+
+/// How a node picks the plane its objects are divided by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitMethod {
+    /// Split at the middle of the centroid bounds along the longest axis
+    Midpoint,
+    /// Evaluate the surface area heuristic at every object boundary along every axis
+    Sah,
+    /// Evaluate the surface area heuristic at the borders of `bins` equally sized buckets
+    BinnedSah { bins: usize },
+}
+
+/// Numbers describing a finished hierarchy, to compare builders on the same scene
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BvhStats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub max_depth: usize,
+    /// Expected cost of tracing a random ray through the tree, in object intersection units
+    pub sah_cost: f64,
+    pub build_time: Duration,
+}
+
+/// A node of the flattened tree. The left child of an interior node is always the node
+/// right after it in the array, so only the index of the right child is stored.
+#[derive(Debug, Clone, Copy)]
+struct FlatNode {
+    bbox: Aabb,
+    /// First object index for a leaf, index of the right child for an interior node
+    offset: u32,
+    /// Number of objects, zero for an interior node
+    count: u32,
+    axis: u8,
+}
+
+#[derive(Clone, Copy)]
+struct ObjectInfo {
+    index: usize,
+    bbox: Aabb,
+    centroid: Point3,
+}
+
+/// Bounding volume hierarchy stored as a single depth-first array of nodes instead of a tree
+/// of boxed hittables, which keeps traversal on contiguous memory.
+pub struct FlatBvh {
+    nodes: Vec<FlatNode>,
+    objects: Vec<Arc<dyn Hittable + Sync + Send>>,
+    stats: BvhStats,
+}
+
+impl FlatBvh {
+    pub fn new(list: HittableList, method: SplitMethod) -> Self {
+        FlatBvh::from_objects(list.objects.into_iter().map(Arc::from).collect(), method)
+    }
+
+    pub fn from_objects(
+        objects: Vec<Arc<dyn Hittable + Sync + Send>>,
+        method: SplitMethod,
+    ) -> Self {
+        let start = Instant::now();
+
+        let mut infos: Vec<ObjectInfo> = objects
+            .iter()
+            .enumerate()
+            .map(|(index, obj)| {
+                let bbox = obj.bounding_box();
+                ObjectInfo {
+                    index,
+                    bbox,
+                    centroid: bbox.centroid(),
+                }
+            })
+            .collect();
+
+        let mut bvh = FlatBvh {
+            nodes: Vec::with_capacity(2 * objects.len()),
+            objects: Vec::with_capacity(objects.len()),
+            stats: BvhStats::default(),
+        };
+
+        if infos.is_empty() {
+            bvh.nodes.push(FlatNode {
+                bbox: aabb::EMPTY,
+                offset: 0,
+                count: 0,
+                axis: 0,
+            });
+        } else {
+            bvh.build(&mut infos, &objects, method, 1);
+        }
+
+        bvh.stats.node_count = bvh.nodes.len();
+        bvh.stats.sah_cost = bvh.compute_sah_cost();
+        bvh.stats.build_time = start.elapsed();
+        bvh
+    }
+
+    pub fn stats(&self) -> BvhStats {
+        self.stats
+    }
+
+    /// Recursively emit the subtree for infos in depth-first order, returning its node index
+    fn build(
+        &mut self,
+        infos: &mut [ObjectInfo],
+        objects: &[Arc<dyn Hittable + Sync + Send>],
+        method: SplitMethod,
+        depth: usize,
+    ) -> usize {
+        self.stats.max_depth = self.stats.max_depth.max(depth);
+
+        let bbox = infos
+            .iter()
+            .fold(aabb::EMPTY, |bbox, info| Aabb::new_boxes(&bbox, &info.bbox));
+        let centroid_bounds = infos.iter().fold(aabb::EMPTY, |bbox, info| {
+            Aabb::new_boxes(&bbox, &Aabb::new_points(&info.centroid, &info.centroid))
+        });
+
+        let node_index = self.nodes.len();
+        self.nodes.push(FlatNode {
+            bbox,
+            offset: 0,
+            count: 0,
+            axis: 0,
+        });
+
+        let axis = centroid_bounds.longest_axis();
+        let split = if infos.len() == 1 || centroid_bounds.axis(axis).size() <= 0.0 {
+            // Every centroid sits on the same spot, no plane can separate them
+            None
+        } else if depth > MAX_SAH_DEPTH {
+            None
+        } else {
+            match method {
+                SplitMethod::Midpoint => FlatBvh::split_midpoint(infos, &centroid_bounds, axis),
+                SplitMethod::Sah => FlatBvh::split_sah(infos, &bbox),
+                SplitMethod::BinnedSah { bins } => {
+                    FlatBvh::split_binned_sah(infos, &bbox, &centroid_bounds, bins.max(2))
+                }
+            }
+        };
+
+        let split = match split {
+            Some(split) => Some(split),
+            None if infos.len() > MAX_OBJECTS_PER_LEAF => {
+                // Too many objects for one leaf, fall back to an even split
+                infos.sort_by(|a, b| centroid_compare(a, b, axis));
+                Some((infos.len() / 2, axis))
+            }
+            None => None,
+        };
+
+        match split {
+            Some((mid, split_axis)) => {
+                let (lower, upper) = infos.split_at_mut(mid);
+                self.build(lower, objects, method, depth + 1);
+                let right = self.build(upper, objects, method, depth + 1);
+
+                let node = &mut self.nodes[node_index];
+                node.offset = right as u32;
+                node.axis = split_axis as u8;
+            }
+            None => {
+                let node = &mut self.nodes[node_index];
+                node.offset = self.objects.len() as u32;
+                node.count = infos.len() as u32;
+                for info in infos.iter() {
+                    self.objects.push(objects[info.index].clone());
+                }
+                self.stats.leaf_count += 1;
+            }
+        }
+
+        node_index
+    }
+
+    fn split_midpoint(
+        infos: &mut [ObjectInfo],
+        centroid_bounds: &Aabb,
+        axis: usize,
+    ) -> Option<(usize, usize)> {
+        if infos.len() <= MAX_OBJECTS_PER_LEAF {
+            return None;
+        }
+
+        let ax = centroid_bounds.axis(axis);
+        let mid_value = (ax.min + ax.max) * 0.5;
+        let mid = partition(infos, |info| centroid_axis(info, axis) < mid_value);
+        if mid == 0 || mid == infos.len() {
+            return None;
+        }
+        Some((mid, axis))
+    }
+
+    fn split_sah(infos: &mut [ObjectInfo], bbox: &Aabb) -> Option<(usize, usize)> {
+        let n = infos.len();
+        let parent_area = bbox.surface_area();
+        if !parent_area.is_finite() || parent_area <= 0.0 {
+            return None;
+        }
+
+        let mut best_cost = INTERSECTION_COST * (n as f64);
+        let mut best: Option<(usize, usize)> = None;
+        let mut right_areas = vec![0.0; n];
+
+        for axis in 0..3 {
+            infos.sort_by(|a, b| centroid_compare(a, b, axis));
+
+            // Sweep from the right to know the area of every suffix, then from the left
+            let mut right_box = aabb::EMPTY;
+            for i in (1..n).rev() {
+                right_box = Aabb::new_boxes(&right_box, &infos[i].bbox);
+                right_areas[i] = right_box.surface_area();
+            }
+
+            let mut left_box = aabb::EMPTY;
+            for i in 1..n {
+                left_box = Aabb::new_boxes(&left_box, &infos[i - 1].bbox);
+                let cost = TRAVERSAL_COST
+                    + INTERSECTION_COST
+                        * (left_box.surface_area() * (i as f64)
+                            + right_areas[i] * ((n - i) as f64))
+                        / parent_area;
+                if cost < best_cost {
+                    best_cost = cost;
+                    best = Some((i, axis));
+                }
+            }
+        }
+
+        let (mid, axis) = best?;
+        infos.sort_by(|a, b| centroid_compare(a, b, axis));
+        Some((mid, axis))
+    }
+
+    fn split_binned_sah(
+        infos: &mut [ObjectInfo],
+        bbox: &Aabb,
+        centroid_bounds: &Aabb,
+        bin_count: usize,
+    ) -> Option<(usize, usize)> {
+        let n = infos.len();
+        let parent_area = bbox.surface_area();
+        if !parent_area.is_finite() || parent_area <= 0.0 {
+            return None;
+        }
+
+        let mut best_cost = INTERSECTION_COST * (n as f64);
+        let mut best: Option<(usize, f64, f64, usize)> = None;
+
+        for axis in 0..3 {
+            let ax = centroid_bounds.axis(axis);
+            if ax.size() <= 0.0 {
+                continue;
+            }
+            let scale = (bin_count as f64) / ax.size();
+
+            let mut bin_boxes = vec![aabb::EMPTY; bin_count];
+            let mut bin_counts = vec![0usize; bin_count];
+            for info in infos.iter() {
+                let b = bin_index(centroid_axis(info, axis), ax.min, scale, bin_count);
+                bin_boxes[b] = Aabb::new_boxes(&bin_boxes[b], &info.bbox);
+                bin_counts[b] += 1;
+            }
+
+            let mut right_areas = vec![0.0; bin_count];
+            let mut right_counts = vec![0usize; bin_count];
+            let mut right_box = aabb::EMPTY;
+            let mut right_count = 0;
+            for b in (1..bin_count).rev() {
+                right_box = Aabb::new_boxes(&right_box, &bin_boxes[b]);
+                right_count += bin_counts[b];
+                right_areas[b] = right_box.surface_area();
+                right_counts[b] = right_count;
+            }
+
+            let mut left_box = aabb::EMPTY;
+            let mut left_count = 0;
+            for b in 1..bin_count {
+                left_box = Aabb::new_boxes(&left_box, &bin_boxes[b - 1]);
+                left_count += bin_counts[b - 1];
+                if left_count == 0 || right_counts[b] == 0 {
+                    continue;
+                }
+                let cost = TRAVERSAL_COST
+                    + INTERSECTION_COST
+                        * (left_box.surface_area() * (left_count as f64)
+                            + right_areas[b] * (right_counts[b] as f64))
+                        / parent_area;
+                if cost < best_cost {
+                    best_cost = cost;
+                    best = Some((axis, ax.min, scale, b));
+                }
+            }
+        }
+
+        let (axis, min, scale, split_bin) = best?;
+        let mid = partition(infos, |info| {
+            bin_index(centroid_axis(info, axis), min, scale, bin_count) < split_bin
+        });
+        Some((mid, axis))
+    }
+
+    /// Expected cost of a random ray, weighting every node by the probability of a ray
+    /// hitting its box given that it hits the root box
+    fn compute_sah_cost(&self) -> f64 {
+        let root_area = self.nodes[0].bbox.surface_area();
+        if !root_area.is_finite() || root_area <= 0.0 {
+            return 0.0;
+        }
+
+        self.nodes
+            .iter()
+            .map(|node| {
+                let area = node.bbox.surface_area() / root_area;
+                if node.count > 0 {
+                    area * INTERSECTION_COST * (node.count as f64)
+                } else {
+                    area * TRAVERSAL_COST
+                }
+            })
+            .sum()
+    }
+}
+
+impl Hittable for FlatBvh {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let dir = r.direction();
+        let dir_is_neg = [dir.x() < 0.0, dir.y() < 0.0, dir.z() < 0.0];
+
+        let mut hit_anything = false;
+        let mut closest_so_far = ray_t.max;
+        let mut stack = [0usize; STACK_SIZE];
+        let mut stack_size = 1;
+
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node = &self.nodes[stack[stack_size]];
+            if !node
+                .bbox
+                .hit(r, Interval::new_val(ray_t.min, closest_so_far))
+            {
+                continue;
+            }
+
+            if node.count > 0 {
+                let start = node.offset as usize;
+                for object in &self.objects[start..start + node.count as usize] {
+                    if object.hit(r, Interval::new_val(ray_t.min, closest_so_far), rec) {
+                        hit_anything = true;
+                        closest_so_far = rec.t;
+                    }
+                }
+            } else {
+                // Visit the child closer to the ray origin first so the far one can be culled
+                let left = stack[stack_size] + 1;
+                let right = node.offset as usize;
+                let (near, far) = if dir_is_neg[node.axis as usize] {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+                stack[stack_size] = far;
+                stack[stack_size + 1] = near;
+                stack_size += 2;
+            }
+        }
+
+        hit_anything
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.nodes[0].bbox
+    }
+
+    /// Every object along the ray contributes, so there is no closest hit to cull against
+    fn transmittance(&self, r: &Ray, ray_t: Interval) -> f64 {
+        let mut transmittance = 1.0;
+        let mut stack = [0usize; STACK_SIZE];
+        let mut stack_size = 1;
+
+        while stack_size > 0 {
+            stack_size -= 1;
+            let node = &self.nodes[stack[stack_size]];
+            if !node.bbox.hit(r, ray_t) {
+                continue;
+            }
+
+            if node.count > 0 {
+                let start = node.offset as usize;
+                for object in &self.objects[start..start + node.count as usize] {
+                    transmittance *= object.transmittance(r, ray_t);
+                    if transmittance == 0.0 {
+                        return 0.0;
+                    }
+                }
+            } else {
+                stack[stack_size + 1] = stack[stack_size] + 1;
+                stack[stack_size] = node.offset as usize;
+                stack_size += 2;
+            }
+        }
+
+        transmittance
+    }
+}
+
+fn centroid_axis(info: &ObjectInfo, axis: usize) -> f64 {
+    match axis {
+        1 => info.centroid.y(),
+        2 => info.centroid.z(),
+        _ => info.centroid.x(),
+    }
+}
+
+fn centroid_compare(a: &ObjectInfo, b: &ObjectInfo, axis: usize) -> Ordering {
+    centroid_axis(a, axis)
+        .partial_cmp(&centroid_axis(b, axis))
+        .unwrap_or(Ordering::Equal)
+}
+
+fn bin_index(value: f64, min: f64, scale: f64, bin_count: usize) -> usize {
+    (((value - min) * scale) as usize).min(bin_count - 1)
+}
+
+/// Move every element matching pred to the front, returning how many matched
+fn partition<T, F: Fn(&T) -> bool>(items: &mut [T], pred: F) -> usize {
+    let mut first = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(first, i);
+            first += 1;
+        }
+    }
+    first
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::Lambertian;
+    use crate::rtweekend::{random_double_range, INFINITY};
+    use crate::sphere::Sphere;
+
+    const METHODS: [SplitMethod; 4] = [
+        SplitMethod::Midpoint,
+        SplitMethod::Sah,
+        SplitMethod::BinnedSah { bins: 12 },
+        SplitMethod::BinnedSah { bins: 2 },
+    ];
+
+    fn sphere(center: Point3, radius: f64) -> Sphere {
+        Sphere::new(
+            center,
+            radius,
+            Box::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5))),
+        )
+    }
+
+    fn random_point(extent: f64) -> Point3 {
+        Point3::new(
+            random_double_range(-extent, extent),
+            random_double_range(-extent, extent),
+            random_double_range(-extent, extent),
+        )
+    }
+
+    /// Scattered spheres of mixed sizes, a dense cluster and a pile sharing one centroid
+    fn scene() -> Vec<(Point3, f64)> {
+        let mut spheres = Vec::new();
+        for _ in 0..150 {
+            let center = random_point(10.0);
+            spheres.push((center, random_double_range(0.05, 1.5)));
+        }
+        for _ in 0..40 {
+            let center = Point3::new(3.0, 3.0, 3.0) + random_point(0.2);
+            spheres.push((center, 0.05));
+        }
+        for i in 0..10 {
+            spheres.push((Point3::new(-4.0, 6.0, 0.0), 0.1 + 0.1 * i as f64));
+        }
+        spheres
+    }
+
+    fn random_ray() -> Ray {
+        let origin = random_point(15.0);
+        let target = random_point(10.0);
+        Ray::new(&origin, &(target - origin))
+    }
+
+    #[test]
+    fn hits_match_the_list() {
+        let spheres = scene();
+        let mut list = HittableList::new();
+        for &(center, radius) in spheres.iter() {
+            list.add(Box::new(sphere(center, radius)));
+        }
+
+        for method in METHODS {
+            let objects: Vec<Arc<dyn Hittable + Sync + Send>> = spheres
+                .iter()
+                .map(|&(center, radius)| {
+                    Arc::new(sphere(center, radius)) as Arc<dyn Hittable + Sync + Send>
+                })
+                .collect();
+            let bvh = FlatBvh::from_objects(objects, method);
+
+            let stats = bvh.stats();
+            assert_eq!(stats.node_count, 2 * stats.leaf_count - 1, "{method:?}");
+
+            for _ in 0..5_000 {
+                let r = random_ray();
+                let ray_t = Interval::new_val(0.001, INFINITY);
+                let mut expected = HitRecord::default();
+                let mut found = HitRecord::default();
+                let hit = list.hit(&r, ray_t, &mut expected);
+                assert_eq!(bvh.hit(&r, ray_t, &mut found), hit, "{method:?}");
+                if hit {
+                    assert_eq!(found.t, expected.t, "{method:?}");
+                    assert_eq!(found.p.x(), expected.p.x());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_hold_every_object_once() {
+        for method in METHODS {
+            let objects: Vec<Arc<dyn Hittable + Sync + Send>> = scene()
+                .iter()
+                .map(|&(center, radius)| {
+                    Arc::new(sphere(center, radius)) as Arc<dyn Hittable + Sync + Send>
+                })
+                .collect();
+            let count = objects.len();
+            let bvh = FlatBvh::from_objects(objects, method);
+            let in_leaves: usize = bvh.nodes.iter().map(|n| n.count as usize).sum();
+            assert_eq!(in_leaves, count, "{method:?}");
+            assert_eq!(bvh.objects.len(), count);
+
+            // Every child box lies inside its parent's
+            for (i, node) in bvh.nodes.iter().enumerate() {
+                if node.count > 0 {
+                    continue;
+                }
+                for child in [i + 1, node.offset as usize] {
+                    let inner = bvh.nodes[child].bbox;
+                    for axis in 0..3 {
+                        assert!(inner.axis(axis).min >= node.bbox.axis(axis).min);
+                        assert!(inner.axis(axis).max <= node.bbox.axis(axis).max);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_tree_misses() {
+        let bvh = FlatBvh::new(HittableList::new(), SplitMethod::Sah);
+        let mut rec = HitRecord::default();
+        assert!(!bvh.hit(&random_ray(), Interval::new_val(0.001, INFINITY), &mut rec));
+    }
+}
@@ -0,0 +1,251 @@
+use super::aabb::Aabb;
+use super::byte_reader::ByteReader;
+use super::load_error::LoadError;
+use super::transform::Transform;
+use super::vec3::{Point3, Vec3};
+use super::voxel_grid::{DenseGrid, VoxelGrid};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"VGRD";
+const VERSION: u32 = 1;
+
+/// Contents of a raw grid file: named channels sharing one resolution and world space bounds.
+///
+/// The format is little endian throughout:
+/// * the magic bytes `VGRD` and a u32 version, currently 1
+/// * three u32 for the resolution along x, y and z
+/// * six f32 for the minimum and maximum corner of the bounds
+/// * a u32 channel count, then for every channel a u32 name length, the UTF-8 name and
+///   nx * ny * nz f32 values, x varying fastest, then y, then z
+///
+/// `GridMedium` looks for channels named "density", "emission" and "temperature".
+#[derive(Debug, Clone)]
+pub struct GridFile {
+    pub resolution: [usize; 3],
+    pub bounds: Aabb,
+    pub channels: Vec<(String, DenseGrid)>,
+}
+
+impl GridFile {
+    pub fn channel(&self, name: &str) -> Option<&DenseGrid> {
+        self.channels
+            .iter()
+            .find(|(channel_name, _)| channel_name == name)
+            .map(|(_, grid)| grid)
+    }
+
+    /// Transform from grid space, the unit cube, onto the bounds
+    pub fn transform(&self) -> Transform {
+        let min = Point3::new(self.bounds.x.min, self.bounds.y.min, self.bounds.z.min);
+        let size = Vec3::new(
+            self.bounds.x.size(),
+            self.bounds.y.size(),
+            self.bounds.z.size(),
+        );
+        Transform::translate(&min) * Transform::scale(&size)
+    }
+}
+
+pub fn load_grid<P: AsRef<Path>>(path: P) -> Result<GridFile, LoadError> {
+    let path = path.as_ref();
+    let data = fs::read(path)?;
+    parse_grid(&data, &path.display().to_string())
+}
+
+pub fn parse_grid(data: &[u8], file: &str) -> Result<GridFile, LoadError> {
+    let mut reader = ByteReader::new(file, data, 0, false);
+    if &reader.bytes::<4>()? != MAGIC {
+        return Err(LoadError::data(file, 0, "not a grid file"));
+    }
+    let version = reader.u32()?;
+    if version != VERSION {
+        return Err(reader.error(format!("unsupported grid version {version}")));
+    }
+
+    let resolution = [
+        reader.u32()? as usize,
+        reader.u32()? as usize,
+        reader.u32()? as usize,
+    ];
+    let voxel_count = resolution[0]
+        .checked_mul(resolution[1])
+        .and_then(|n| n.checked_mul(resolution[2]))
+        .filter(|&n| n.checked_mul(4).is_some_and(|bytes| bytes <= data.len()))
+        .ok_or_else(|| {
+            reader.error(format!(
+                "grid resolution {resolution:?} needs more data than the file holds"
+            ))
+        })?;
+
+    let mut read_point = || -> Result<Point3, LoadError> {
+        Ok(Point3::new(
+            reader.f32()? as f64,
+            reader.f32()? as f64,
+            reader.f32()? as f64,
+        ))
+    };
+    let min = read_point()?;
+    let max = read_point()?;
+
+    let channel_count = reader.u32()?;
+    let mut channels = Vec::new();
+    for _ in 0..channel_count {
+        let name_length = reader.u32()? as usize;
+        let mut name = Vec::new();
+        for _ in 0..name_length {
+            name.push(reader.u8()?);
+        }
+        let name =
+            String::from_utf8(name).map_err(|_| reader.error("channel name is not UTF-8"))?;
+
+        let mut values = Vec::with_capacity(voxel_count);
+        for _ in 0..voxel_count {
+            values.push(reader.f32()?);
+        }
+        channels.push((name, DenseGrid::new(resolution, values)));
+    }
+
+    Ok(GridFile {
+        resolution,
+        bounds: Aabb::new_points(&min, &max),
+        channels,
+    })
+}
+
+pub fn save_grid<P: AsRef<Path>>(grid: &GridFile, path: P) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_grid(grid, &mut out)?;
+    out.flush()
+}
+
+pub fn write_grid<W: Write>(grid: &GridFile, out: &mut W) -> io::Result<()> {
+    if grid
+        .channels
+        .iter()
+        .any(|(_, channel)| channel.resolution() != grid.resolution)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "channel resolution does not match the grid resolution",
+        ));
+    }
+
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    for n in grid.resolution {
+        out.write_all(&(n as u32).to_le_bytes())?;
+    }
+    let bounds = &grid.bounds;
+    for value in [
+        bounds.x.min,
+        bounds.y.min,
+        bounds.z.min,
+        bounds.x.max,
+        bounds.y.max,
+        bounds.z.max,
+    ] {
+        out.write_all(&(value as f32).to_le_bytes())?;
+    }
+
+    out.write_all(&(grid.channels.len() as u32).to_le_bytes())?;
+    for (name, channel) in &grid.channels {
+        out.write_all(&(name.len() as u32).to_le_bytes())?;
+        out.write_all(name.as_bytes())?;
+        for value in channel.data() {
+            out.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> GridFile {
+        let resolution = [3, 2, 2];
+        let ramp = DenseGrid::from_fn(resolution, |p| (p.x() + 2.0 * p.y() + 4.0 * p.z()) as f32);
+        let flat = DenseGrid::new(resolution, vec![0.5; 12]);
+        GridFile {
+            resolution,
+            bounds: Aabb::new_points(&Point3::new(-1.0, 0.0, 2.0), &Point3::new(1.0, 4.0, 3.0)),
+            channels: vec![
+                ("density".to_owned(), ramp),
+                ("température".to_owned(), flat),
+            ],
+        }
+    }
+
+    fn bytes(grid: &GridFile) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_grid(grid, &mut out).unwrap();
+        out
+    }
+
+    fn error(data: &[u8]) -> String {
+        parse_grid(data, "test.vgrd").unwrap_err().to_string()
+    }
+
+    #[test]
+    fn files_round_trip() {
+        let grid = sample();
+        let data = bytes(&grid);
+        assert_eq!(
+            data.len(),
+            4 + 4 + 12 + 24 + 4 + 2 * 4 + 7 + 12 + 2 * 12 * 4
+        );
+
+        let read = parse_grid(&data, "test.vgrd").unwrap();
+        assert_eq!(read.resolution, grid.resolution);
+        assert_eq!(read.bounds.y.max, 4.0);
+        assert_eq!(read.channels.len(), 2);
+        assert_eq!(
+            read.channel("density").unwrap().data(),
+            grid.channels[0].1.data()
+        );
+        assert_eq!(read.channel("température").unwrap().voxel(2, 1, 1), 0.5);
+        assert!(read.channel("emission").is_none());
+
+        // The corners of grid space land on the corners of the bounds
+        let transform = read.transform();
+        let corner = transform.point(&Point3::new(1.0, 1.0, 0.0));
+        assert!((corner - Point3::new(1.0, 4.0, 2.0)).length() < 1e-12);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let data = bytes(&sample());
+
+        let mut magic = data.clone();
+        magic[0] = b'X';
+        assert_eq!(error(&magic), "test.vgrd@0: not a grid file");
+
+        let mut version = data.clone();
+        version[4] = 2;
+        assert!(error(&version).contains("unsupported grid version 2"));
+
+        // Resolutions too large for the file are caught before allocating anything
+        let mut resolution = data.clone();
+        resolution[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        resolution[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(error(&resolution).contains("needs more data than the file holds"));
+
+        let mut name = data.clone();
+        name[52] = 0xff;
+        assert!(error(&name).contains("not UTF-8"));
+
+        assert!(parse_grid(&data[..data.len() - 1], "test.vgrd").is_err());
+    }
+
+    #[test]
+    fn channels_must_share_the_resolution() {
+        let mut grid = sample();
+        grid.channels
+            .push(("emission".to_owned(), DenseGrid::new([1, 1, 1], vec![1.0])));
+        let err = write_grid(&grid, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
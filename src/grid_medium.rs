@@ -0,0 +1,386 @@
+use super::aabb::Aabb;
+use super::color::Color;
+use super::grid_file::GridFile;
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::{Isotropic, Material, ScatterRecord};
+use super::ray::Ray;
+use super::rtweekend::random_double;
+use super::spectrum::blackbody;
+use super::transform::Transform;
+use super::vec3::{Point3, Vec3};
+use super::voxel_grid::VoxelGrid;
+use std::sync::Arc;
+
+/// Number of entries in the temperature to color table, spread evenly up to the hottest voxel
+const BLACKBODY_TABLE_SIZE: usize = 1024;
+
+/// Below this transmittance, ratio tracking starts playing russian roulette
+const ROULETTE_THRESHOLD: f64 = 0.1;
+
+/// Participating medium whose density comes from a voxel grid, such as a cloud or the smoke of
+/// an explosion. The grid fills the unit cube of its own space, which transform places in the
+/// world.
+///
+/// Scattering distances are found with delta tracking and shadow rays are attenuated with
+/// ratio tracking, both against the largest density of the grid, so neither is biased by the
+/// grid's resolution or by step sizes.
+///
+/// Emission and temperature grids make the medium glow. Light is only emitted where it is
+/// absorbed, so an albedo of one cancels any emission.
+pub struct GridMedium {
+    density: Arc<dyn VoxelGrid + Sync + Send>,
+    density_scale: f64,
+    /// Upper bound on the density anywhere in the grid
+    majorant: f64,
+    transform: Transform,
+    bbox: Aabb,
+    albedo: Color,
+    emission: Option<(Arc<dyn VoxelGrid + Sync + Send>, Color)>,
+    temperature: Option<(Arc<dyn VoxelGrid + Sync + Send>, f64, f64)>,
+    phase_function: Arc<Box<dyn Material + Sync + Send>>,
+}
+
+impl GridMedium {
+    ///
+    /// Medium scattering isotropically
+    /// * `density` - Grid of densities, multiplied by density_scale to get the extinction
+    ///   coefficient per world unit
+    /// * `transform` - Transform from grid space, the unit cube, to the world
+    /// * `albedo` - Fraction of the extinguished light that is scattered instead of absorbed
+    pub fn new(
+        density: Arc<dyn VoxelGrid + Sync + Send>,
+        density_scale: f64,
+        transform: Transform,
+        albedo: &Color,
+    ) -> Self {
+        let majorant = density_scale * density.max_value().max(0.0) as f64;
+        let bbox = transform.bounding_box(&unit_cube());
+        let mut medium = GridMedium {
+            density,
+            density_scale,
+            majorant,
+            transform,
+            bbox,
+            albedo: *albedo,
+            emission: None,
+            temperature: None,
+            phase_function: Arc::new(Box::new(Isotropic::default())),
+        };
+        medium.update_phase_function();
+        medium
+    }
+
+    /// Medium built from the "density" channel of a grid file, glowing with its "emission" and
+    /// "temperature" channels when it has them. Temperatures are taken to be in kelvin.
+    pub fn from_grid_file(file: &GridFile, density_scale: f64, albedo: &Color) -> Option<Self> {
+        let density = Arc::new(file.channel("density")?.clone());
+        let mut medium = GridMedium::new(density, density_scale, file.transform(), albedo);
+        if let Some(emission) = file.channel("emission") {
+            medium = medium.with_emission(Arc::new(emission.clone()), &Color::new(1.0, 1.0, 1.0));
+        }
+        if let Some(temperature) = file.channel("temperature") {
+            medium = medium.with_temperature(Arc::new(temperature.clone()), 1.0, 1.0);
+        }
+        Some(medium)
+    }
+
+    ///
+    /// Glow with the emission grid's values times color
+    /// * `emission` - Grid sharing the density grid's space, it may have another resolution
+    pub fn with_emission(
+        mut self,
+        emission: Arc<dyn VoxelGrid + Sync + Send>,
+        color: &Color,
+    ) -> Self {
+        self.emission = Some((emission, *color));
+        self.update_phase_function();
+        self
+    }
+
+    ///
+    /// Glow like a black body at the temperature grid's values
+    /// * `kelvin_scale` - Factor turning grid values into kelvin
+    /// * `intensity` - Factor on the radiance, which is relative to a 6500 K black body
+    pub fn with_temperature(
+        mut self,
+        temperature: Arc<dyn VoxelGrid + Sync + Send>,
+        kelvin_scale: f64,
+        intensity: f64,
+    ) -> Self {
+        self.temperature = Some((temperature, kelvin_scale, intensity));
+        self.update_phase_function();
+        self
+    }
+
+    /// Rebuild the material handed out with hits, which carries the emission lookups
+    fn update_phase_function(&mut self) {
+        let temperature = self
+            .temperature
+            .as_ref()
+            .map(|(grid, kelvin_scale, intensity)| {
+                let max_kelvin = grid.max_value().max(0.0) as f64 * kelvin_scale;
+                let colors = (0..BLACKBODY_TABLE_SIZE)
+                    .map(|i| {
+                        let kelvin = max_kelvin * i as f64 / (BLACKBODY_TABLE_SIZE - 1) as f64;
+                        blackbody(kelvin) * *intensity
+                    })
+                    .collect();
+                TemperatureEmission {
+                    grid: grid.clone(),
+                    kelvin_scale: *kelvin_scale,
+                    max_kelvin,
+                    colors,
+                }
+            });
+
+        self.phase_function = Arc::new(Box::new(GridPhaseFunction {
+            isotropic: Isotropic::new(&self.albedo),
+            absorption: Color::new(1.0, 1.0, 1.0) - self.albedo,
+            transform: self.transform,
+            emission: self.emission.clone(),
+            temperature,
+        }));
+    }
+
+    fn density_at(&self, p: &Point3) -> f64 {
+        (self.density_scale * self.density.lookup(p)).max(0.0)
+    }
+
+    /// The ray in grid space, and the stretch of ray_t it spends inside the grid
+    fn span(&self, r: &Ray, ray_t: Interval) -> Option<(Ray, Interval)> {
+        if self.majorant <= 0.0 {
+            return None;
+        }
+        let grid_ray = self.transform.inverse_ray(r);
+        let span = unit_cube().clip(&grid_ray, ray_t)?;
+        Some((grid_ray, span))
+    }
+
+    /// Parameter distance to the next tentative collision, sampled against the majorant
+    fn free_flight(&self, r: &Ray) -> f64 {
+        -(1.0 - random_double()).ln() / (self.majorant * r.direction().length())
+    }
+}
+
+impl Hittable for GridMedium {
+    /// Delta tracking: tentative collisions are sampled as if the whole grid had the majorant
+    /// density, and each is accepted as real with probability density / majorant
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let (grid_ray, span) = match self.span(r, ray_t) {
+            Some(span) => span,
+            None => return false,
+        };
+
+        let mut t = span.min;
+        loop {
+            t += self.free_flight(r);
+            if t >= span.max {
+                return false;
+            }
+            if random_double() * self.majorant < self.density_at(&grid_ray.at(t)) {
+                break;
+            }
+        }
+
+        rec.t = t;
+        rec.p = r.at(t);
+
+        // Scattering inside a volume has no surface, the normal and sides are arbitrary
+        rec.normal = Vec3::new(1.0, 0.0, 0.0);
+        rec.shading_normal = rec.normal;
+        rec.front_face = true;
+        rec.u = 0.0;
+        rec.v = 0.0;
+        rec.vertex_color = None;
+        rec.mat = self.phase_function.clone();
+
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    /// Ratio tracking: every tentative collision scales the estimate by the chance it is a
+    /// null one, which has far less variance than counting whether delta tracking got through
+    fn transmittance(&self, r: &Ray, ray_t: Interval) -> f64 {
+        let (grid_ray, span) = match self.span(r, ray_t) {
+            Some(span) => span,
+            None => return 1.0,
+        };
+
+        let mut transmittance = 1.0;
+        let mut t = span.min;
+        loop {
+            t += self.free_flight(r);
+            if t >= span.max {
+                return transmittance;
+            }
+            transmittance *= 1.0 - self.density_at(&grid_ray.at(t)) / self.majorant;
+
+            // Stop tracking light that's nearly gone without biasing the estimate
+            if transmittance < ROULETTE_THRESHOLD {
+                if random_double() < 0.5 {
+                    return 0.0;
+                }
+                transmittance *= 2.0;
+            }
+        }
+    }
+}
+
+/// Radiance of a black body at the temperature of a grid, tabulated since integrating Planck's
+/// law at every hit would dominate the render
+struct TemperatureEmission {
+    grid: Arc<dyn VoxelGrid + Sync + Send>,
+    kelvin_scale: f64,
+    max_kelvin: f64,
+    colors: Vec<Color>,
+}
+
+impl TemperatureEmission {
+    fn value(&self, p: &Point3) -> Color {
+        let kelvin = self.grid.lookup(p) * self.kelvin_scale;
+        if kelvin <= 0.0 || self.max_kelvin <= 0.0 {
+            return Color::default();
+        }
+
+        let position = (kelvin / self.max_kelvin).min(1.0) * (BLACKBODY_TABLE_SIZE - 1) as f64;
+        let index = (position as usize).min(BLACKBODY_TABLE_SIZE - 2);
+        let weight = position - index as f64;
+        self.colors[index] * (1.0 - weight) + self.colors[index + 1] * weight
+    }
+}
+
+/// Isotropic scattering, plus the glow of the emission and temperature grids at the hit point
+struct GridPhaseFunction {
+    isotropic: Isotropic,
+    /// One minus the albedo, the share of collisions that absorb and so emit
+    absorption: Color,
+    transform: Transform,
+    emission: Option<(Arc<dyn VoxelGrid + Sync + Send>, Color)>,
+    temperature: Option<TemperatureEmission>,
+}
+
+impl Material for GridPhaseFunction {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        self.isotropic.scatter(r_in, rec, srec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.isotropic.eval(r_in, rec, scattered)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.isotropic.scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn emitted(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        if self.emission.is_none() && self.temperature.is_none() {
+            return Color::default();
+        }
+
+        let grid_point = self.transform.inverse_point(p);
+        let mut radiance = Color::default();
+        if let Some((grid, color)) = &self.emission {
+            radiance = radiance + *color * grid.lookup(&grid_point).max(0.0);
+        }
+        if let Some(temperature) = &self.temperature {
+            radiance = radiance + temperature.value(&grid_point);
+        }
+        self.absorption * radiance
+    }
+}
+
+fn unit_cube() -> Aabb {
+    Aabb::new_points(&Point3::new(0.0, 0.0, 0.0), &Point3::new(1.0, 1.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtweekend::INFINITY;
+    use crate::voxel_grid::{BrickedGrid, DenseGrid};
+
+    const SAMPLES: usize = 100_000;
+
+    /// Ray along x through the middle of a grid stretched to [0, 3] x [0, 1] x [0, 1]
+    fn across() -> (Transform, Ray) {
+        let transform = Transform::scale(&Vec3::new(3.0, 1.0, 1.0));
+        let r = Ray::new(&Point3::new(-1.0, 0.5, 0.5), &Vec3::new(2.0, 0.0, 0.0));
+        (transform, r)
+    }
+
+    fn everything() -> Interval {
+        Interval::new_val(0.0, INFINITY)
+    }
+
+    fn ratio_tracked(medium: &GridMedium, r: &Ray, ray_t: Interval) -> f64 {
+        (0..SAMPLES)
+            .map(|_| medium.transmittance(r, ray_t))
+            .sum::<f64>()
+            / SAMPLES as f64
+    }
+
+    fn delta_tracked(medium: &GridMedium, r: &Ray) -> f64 {
+        let escaped = (0..SAMPLES)
+            .filter(|_| !medium.hit(r, everything(), &mut HitRecord::default()))
+            .count();
+        escaped as f64 / SAMPLES as f64
+    }
+
+    #[test]
+    fn constant_grid_follows_beer_lambert() {
+        let (transform, r) = across();
+        let grid = Arc::new(DenseGrid::new([2, 2, 2], vec![1.0; 8]));
+        let medium = GridMedium::new(grid, 0.4, transform, &Color::new(0.5, 0.5, 0.5));
+
+        let expected = (-0.4 * 3.0f64).exp();
+        assert!((ratio_tracked(&medium, &r, everything()) - expected).abs() < 0.01);
+        assert!((delta_tracked(&medium, &r) - expected).abs() < 0.01);
+
+        // Shadow rays stopping halfway only go through half the medium
+        let halfway = Interval::new_val(0.0, 1.25);
+        let t = ratio_tracked(&medium, &r, halfway);
+        assert!((t - (-0.4 * 1.5f64).exp()).abs() < 0.01);
+    }
+
+    #[test]
+    fn varying_grid_follows_beer_lambert() {
+        // Density 1 up to x = 0.25 fading to 0 at x = 0.75, half the optical depth of a full grid
+        let (transform, r) = across();
+        let dense = DenseGrid::new([2, 1, 1], vec![1.0, 0.0]);
+        let expected = (-0.8 * 3.0 * 0.5f64).exp();
+
+        let bricked = Arc::new(BrickedGrid::from_dense(&dense));
+        for grid in [Arc::new(dense) as Arc<dyn VoxelGrid + Sync + Send>, bricked] {
+            let medium = GridMedium::new(grid, 0.8, transform, &Color::new(0.5, 0.5, 0.5));
+            assert!((ratio_tracked(&medium, &r, everything()) - expected).abs() < 0.01);
+            assert!((delta_tracked(&medium, &r) - expected).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn empty_grids_let_everything_through() {
+        let (transform, r) = across();
+        let grid = Arc::new(DenseGrid::new([1, 1, 1], vec![0.0]));
+        let medium = GridMedium::new(grid, 1.0, transform, &Color::new(0.5, 0.5, 0.5));
+        assert_eq!(medium.transmittance(&r, everything()), 1.0);
+        assert_eq!(delta_tracked(&medium, &r), 1.0);
+    }
+
+    #[test]
+    fn emission_is_scaled_by_absorption() {
+        let (transform, _) = across();
+        let density = Arc::new(DenseGrid::new([1, 1, 1], vec![1.0]));
+        let emission = Arc::new(DenseGrid::new([2, 1, 1], vec![0.0, 2.0]));
+        let medium = GridMedium::new(density, 1.0, transform, &Color::new(0.75, 0.5, 0.0))
+            .with_emission(emission, &Color::new(1.0, 2.0, 3.0));
+
+        let glow = medium
+            .phase_function
+            .emitted(0.0, 0.0, &Point3::new(1.5, 0.5, 0.5));
+        assert!((glow - Color::new(0.25, 1.0, 3.0)).length() < 1e-12);
+    }
+}
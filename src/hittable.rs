@@ -1,14 +1,37 @@
+use super::aabb::Aabb;
+use super::color::Color;
 use super::ray::Ray;
 use super::vec3::{Point3, Vec3};
 use super::interval::Interval;
 use super::material::{Material, Lambertian};
+use super::onb::Onb;
 use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
+    /// Geometric normal, facing the side the ray came from
     pub normal: Vec3,
+    /// Normal materials shade with, interpolated or perturbed by a map but always on the same
+    /// side as `normal`
+    pub shading_normal: Vec3,
+    /// Unit vectors completing the shading frame, following dpdu and dpdv when the surface
+    /// has them. Tangent space normal maps are expressed in this frame.
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    /// Partial derivatives of the hit point along u and v, zero when the surface doesn't
+    /// provide them
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub mat: Arc<Box<dyn Material + Sync + Send>>,
     pub t: f64,
+    /// Surface coordinates of the hit point
+    pub u: f64,
+    pub v: f64,
+    /// Barycentric weights of the second and third vertex for triangle hits
+    pub barycentric: (f64, f64),
+    /// Interpolated vertex color, for meshes that carry one
+    pub vertex_color: Option<Color>,
     pub front_face: bool,
 }
 
@@ -17,14 +40,25 @@ impl Default for HitRecord {
         Self {
             p: Point3::default(),
             normal: Point3::default(),
+            shading_normal: Vec3::default(),
+            tangent: Vec3::default(),
+            bitangent: Vec3::default(),
+            dpdu: Vec3::default(),
+            dpdv: Vec3::default(),
             mat: Arc::new(Box::new(Lambertian::default())),
             t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            barycentric: (0.0, 0.0),
+            vertex_color: None,
             front_face: false,
         }
     }
 }
 
 impl HitRecord {
+    /// Set the geometric normal facing the ray and reset the shading frame to it. Surfaces
+    /// with UVs call `set_derivatives` afterwards.
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: &Vec3) {
         self.front_face = Vec3::dot(&(r.direction()), outward_normal) < 0.0;
         self.normal = if self.front_face {
@@ -32,6 +66,93 @@ impl HitRecord {
         } else {
             -outward_normal
         };
+        self.shading_normal = self.normal;
+        self.dpdu = Vec3::default();
+        self.dpdv = Vec3::default();
+        self.tangent = Vec3::default();
+        self.bitangent = Vec3::default();
+        self.update_shading_frame();
+    }
+
+    /// Same as set_face_normal, but with an interpolated shading normal, flipped onto the
+    /// side of the geometric one.
+    pub fn set_face_normal_smooth(
+        &mut self,
+        r: &Ray,
+        outward_normal: &Vec3,
+        outward_shading_normal: &Vec3,
+    ) {
+        self.set_face_normal(r, outward_normal);
+        self.shading_normal = if Vec3::dot(outward_shading_normal, &self.normal) < 0.0 {
+            -outward_shading_normal
+        } else {
+            outward_shading_normal.to_owned()
+        };
+        self.update_shading_frame();
+    }
+
+    /// Store the partial derivatives of the hit point and align the tangent frame with them
+    pub fn set_derivatives(&mut self, dpdu: &Vec3, dpdv: &Vec3) {
+        self.dpdu = dpdu.to_owned();
+        self.dpdv = dpdv.to_owned();
+        self.update_shading_frame();
+    }
+
+    /// Replace the shading normal, keeping the tangent frame as close to the old one as
+    /// possible. Normals facing away from the geometric side are flipped onto it.
+    pub fn set_shading_normal(&mut self, shading_normal: &Vec3) {
+        let n = Vec3::unit_vector(shading_normal);
+        self.shading_normal = if Vec3::dot(&n, &self.normal) < 0.0 { -n } else { n };
+        self.update_shading_frame();
+    }
+
+    /// Rebuild tangent and bitangent around the shading normal: the tangent is dpdu, or the
+    /// previous tangent, made perpendicular to it, and the bitangent points along dpdv.
+    pub(crate) fn update_shading_frame(&mut self) {
+        let n = self.shading_normal;
+        let reference = if self.dpdu.near_zero() {
+            self.tangent
+        } else {
+            self.dpdu
+        };
+        let projected = reference - n * Vec3::dot(&reference, &n);
+        if projected.length_squared() < 1e-12 {
+            let frame = Onb::new_from_w(&n);
+            self.tangent = frame.u;
+            self.bitangent = frame.v;
+            return;
+        }
+
+        self.tangent = Vec3::unit_vector(&projected);
+        let bitangent = Vec3::cross(&n, &self.tangent);
+        let reference = if self.dpdv.near_zero() {
+            self.bitangent
+        } else {
+            self.dpdv
+        };
+        // Mirrored UVs give a left handed frame
+        self.bitangent = if Vec3::dot(&bitangent, &reference) < 0.0 {
+            -bitangent
+        } else {
+            bitangent
+        };
+    }
+
+    /// Orthonormal frame materials shade in, w being the shading normal
+    pub fn shading_frame(&self) -> Onb {
+        Onb {
+            u: self.tangent,
+            v: self.bitangent,
+            w: self.shading_normal,
+        }
+    }
+
+    /// True when direction is on the same side of the geometric and the shading normal.
+    /// Directions the two normals disagree on would let light leak through the surface.
+    pub fn same_side(&self, direction: &Vec3) -> bool {
+        let geometric = Vec3::dot(direction, &self.normal);
+        let shading = Vec3::dot(direction, &self.shading_normal);
+        geometric * shading > 0.0
     }
 }
 
@@ -44,4 +165,36 @@ pub trait Hittable {
     /// # Returns
     /// Return true if it's hit
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool;
+
+    /// Bounding box enclosing the whole object, used to build acceleration structures
+    fn bounding_box(&self) -> Aabb;
+
+    ///
+    /// Density, over solid angle as seen from origin, with which `random` picks direction.
+    /// Only objects that can be sampled as lights need to implement it.
+    /// * `origin` - Point the object is seen from
+    /// * `direction` - Direction towards the object
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// Random direction from origin towards a point on the object
+    fn random(&self, _origin: &Point3) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    ///
+    /// Fraction of light making it along r through ray_t without being blocked, used by
+    /// shadow rays. Surfaces block everything they hit, participating media override this to
+    /// let part of the light through. The estimate may be random but must be unbiased.
+    /// * `r` - Ray
+    /// * `ray_t` - Stretch of the ray to test
+    fn transmittance(&self, r: &Ray, ray_t: Interval) -> f64 {
+        let mut rec = HitRecord::default();
+        if self.hit(r, ray_t, &mut rec) {
+            0.0
+        } else {
+            1.0
+        }
+    }
 }
@@ -1,6 +1,9 @@
+use super::aabb::{self, Aabb};
 use super::hittable::{HitRecord, Hittable};
 use super::interval::Interval;
 use super::ray::Ray;
+use super::rtweekend::random_double;
+use super::vec3::{Point3, Vec3};
 
 pub struct HittableList {
     pub objects: Vec<Box<dyn Hittable + Sync + Send>>,
@@ -42,4 +45,45 @@ impl Hittable for HittableList {
 
         return hit_anything;
     }
+
+    fn bounding_box(&self) -> Aabb {
+        self.objects.iter().fold(aabb::EMPTY, |bbox, obj| {
+            Aabb::new_boxes(&bbox, &obj.bounding_box())
+        })
+    }
+
+    fn transmittance(&self, r: &Ray, ray_t: Interval) -> f64 {
+        let mut transmittance = 1.0;
+        for object in self.objects.iter() {
+            transmittance *= object.transmittance(r, ray_t);
+            if transmittance == 0.0 {
+                break;
+            }
+        }
+
+        transmittance
+    }
+
+    /// Every object is equally likely to be sampled
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+
+        let weight = 1.0 / (self.objects.len() as f64);
+        self.objects
+            .iter()
+            .map(|object| weight * object.pdf_value(origin, direction))
+            .sum()
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        if self.objects.is_empty() {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+
+        let index =
+            ((random_double() * (self.objects.len() as f64)) as usize).min(self.objects.len() - 1);
+        self.objects[index].random(origin)
+    }
 }
@@ -0,0 +1,97 @@
+use super::color::{write_color, Color};
+use std::io::{self, Write};
+
+/// Rendered picture holding the linear RGB radiance of every pixel, averaged over the samples
+/// that went into it. Pixels are stored row by row starting from the top left.
+#[derive(Debug, Clone)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    samples: Vec<u32>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Image {
+            width,
+            height,
+            pixels: vec![Color::default(); width * height],
+            samples: vec![0; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[self.index(x, y)]
+    }
+
+    /// Number of samples averaged into the pixel
+    pub fn samples(&self, x: usize, y: usize) -> u32 {
+        self.samples[self.index(x, y)]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color, samples: u32) {
+        let index = self.index(x, y);
+        self.pixels[index] = color;
+        self.samples[index] = samples;
+    }
+
+    /// All pixels, row by row
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        assert!(
+            x < self.width && y < self.height,
+            "pixel ({x}, {y}) out of bounds"
+        );
+        y * self.width + x
+    }
+}
+
+/// Named plane of values, one per pixel in the same order as `Image::pixels`, so several
+/// buffers can be written to one multi-channel file
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub name: String,
+    pub data: Vec<f32>,
+}
+
+impl Image {
+    /// Split the unclamped pixels into R, G and B channels. A non-empty layer is prepended to
+    /// the names, as in `albedo.R`.
+    pub fn to_channels(&self, layer: &str) -> Vec<Channel> {
+        let prefix = if layer.is_empty() {
+            String::new()
+        } else {
+            format!("{layer}.")
+        };
+        let channel = |name: &str, component: fn(&Color) -> f64| Channel {
+            name: format!("{prefix}{name}"),
+            data: self.pixels.iter().map(|p| component(p) as f32).collect(),
+        };
+        vec![
+            channel("R", Color::x),
+            channel("G", Color::y),
+            channel("B", Color::z),
+        ]
+    }
+}
+
+/// Write the image as an ASCII (P3) PPM
+pub fn write_ppm<W: Write>(image: &Image, out: &mut W) -> io::Result<()> {
+    writeln!(out, "P3\n{} {}\n255", image.width(), image.height())?;
+    for pixel_color in image.pixels() {
+        write_color(out, pixel_color)?;
+    }
+    out.flush()
+}
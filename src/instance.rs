@@ -0,0 +1,122 @@
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::ray::Ray;
+use super::transform::{AnimatedTransform, Transform};
+use super::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Places a shared object in the world through a transform. Rays are moved into the object's
+/// space and hits moved back, so the object itself is never copied and can be instanced any
+/// number of times. Animated instances move the ray by the transform at the ray's time.
+pub struct Instance {
+    object: Arc<dyn Hittable + Sync + Send>,
+    transform: Transform,
+    motion: Option<AnimatedTransform>,
+    bbox: Aabb,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hittable + Sync + Send>, transform: Transform) -> Self {
+        let bbox = transform.bounding_box(&object.bounding_box());
+        Instance {
+            object,
+            transform,
+            motion: None,
+            bbox,
+        }
+    }
+
+    /// Instance moving with a transform that changes over time. Its bounding box covers the
+    /// whole motion. It can't be sampled as a light, light sampling has no time to place it at.
+    pub fn new_animated(
+        object: Arc<dyn Hittable + Sync + Send>,
+        motion: AnimatedTransform,
+    ) -> Self {
+        let bbox = motion.bounding_box(&object.bounding_box());
+        Instance {
+            object,
+            transform: motion.start(),
+            motion: Some(motion),
+            bbox,
+        }
+    }
+
+    pub fn object(&self) -> &Arc<dyn Hittable + Sync + Send> {
+        &self.object
+    }
+
+    /// Transform of the instance, the one at the first keyframe for animated instances
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    pub fn motion(&self) -> Option<&AnimatedTransform> {
+        self.motion.as_ref()
+    }
+
+    fn transform_at(&self, time: f64) -> Transform {
+        match &self.motion {
+            Some(motion) => motion.at(time),
+            None => self.transform,
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // The direction isn't normalized, so t means the same thing in both spaces
+        let transform = self.transform_at(r.time());
+        let object_ray = transform.inverse_ray(r);
+        if !self.object.hit(&object_ray, ray_t, rec) {
+            return false;
+        }
+
+        rec.p = transform.point(&rec.p);
+        rec.normal = Vec3::unit_vector(&transform.normal(&rec.normal));
+        rec.shading_normal = Vec3::unit_vector(&transform.normal(&rec.shading_normal));
+        rec.dpdu = transform.vector(&rec.dpdu);
+        rec.dpdv = transform.vector(&rec.dpdv);
+        rec.tangent = transform.vector(&rec.tangent);
+        rec.bitangent = transform.vector(&rec.bitangent);
+        rec.update_shading_frame();
+
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn transmittance(&self, r: &Ray, ray_t: Interval) -> f64 {
+        let transform = self.transform_at(r.time());
+        self.object.transmittance(&transform.inverse_ray(r), ray_t)
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.motion.is_some() {
+            return 0.0;
+        }
+
+        let object_origin = self.transform.inverse_point(origin);
+        let object_direction = Vec3::unit_vector(&self.transform.inverse_vector(direction));
+        let pdf = self.object.pdf_value(&object_origin, &object_direction);
+        if pdf == 0.0 {
+            return 0.0;
+        }
+
+        // Directions get bunched up or spread out by the linear part of the transform, by
+        // |det| / |M w|^3 for a unit direction w
+        let stretch = self.transform.vector(&object_direction).length();
+        pdf * stretch * stretch * stretch / self.transform.determinant().abs()
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        if self.motion.is_some() {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+
+        let object_origin = self.transform.inverse_point(origin);
+        self.transform.vector(&self.object.random(&object_origin))
+    }
+}
@@ -22,6 +22,24 @@ impl Interval {
         }
     }
 
+    /// Create the tightest interval enclosing both a and b
+    pub fn new_enclosing(a: &Interval, b: &Interval) -> Self {
+        Interval {
+            min: a.min.min(b.min),
+            max: a.max.max(b.max),
+        }
+    }
+
+    pub fn size(&self) -> f64 {
+        self.max - self.min
+    }
+
+    /// Pad the interval by delta, half on each side
+    pub fn expand(&self, delta: f64) -> Self {
+        let padding = delta / 2.0;
+        Interval::new_val(self.min - padding, self.max + padding)
+    }
+
     pub fn contains(&self, x: f64) -> bool {
         return self.min <= x && x <= self.max
     }
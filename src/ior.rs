@@ -0,0 +1,87 @@
+/// Wavelength, in nanometers, of the helium d line that catalogue indices are quoted at. Glass
+/// renders at this index outside spectral mode.
+pub const REFERENCE_WAVELENGTH: f64 = 587.56;
+
+/// Index of refraction of a dielectric as a function of wavelength. Anything but a constant
+/// splits white light into a rainbow when rendering spectrally.
+#[derive(Debug, Clone, Copy)]
+pub enum Ior {
+    Constant(f64),
+    /// Cauchy's equation n = a + b / lambda^2, lambda in micrometers
+    Cauchy {
+        a: f64,
+        b: f64,
+    },
+    /// Sellmeier equation n^2 = 1 + sum of b lambda^2 / (lambda^2 - c), lambda in
+    /// micrometers, the form glass catalogues list their coefficients in
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+impl Ior {
+    /// Schott N-BK7, the common optical crown glass
+    pub const BK7: Ior = Ior::Sellmeier {
+        b: [1.03961212, 0.231792344, 1.01046945],
+        c: [0.00600069867, 0.0200179144, 103.560653],
+    };
+    pub const FUSED_SILICA: Ior = Ior::Sellmeier {
+        b: [0.6961663, 0.4079426, 0.8974794],
+        c: [0.00467914826, 0.0135120631, 97.9340025],
+    };
+    /// Schott N-SF11, a dense flint glass with strong dispersion
+    pub const SF11: Ior = Ior::Sellmeier {
+        b: [1.73759695, 0.313747346, 1.89878101],
+        c: [0.013188707, 0.0623068142, 155.23629],
+    };
+    pub const DIAMOND: Ior = Ior::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030625, 0.011236, 0.0],
+    };
+    pub const WATER: Ior = Ior::Cauchy {
+        a: 1.3199,
+        b: 0.00653,
+    };
+
+    /// Index at lambda nanometers
+    pub fn at(&self, lambda: f64) -> f64 {
+        let micrometers2 = (lambda * 1e-3) * (lambda * 1e-3);
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / micrometers2,
+            Ior::Sellmeier { b, c } => {
+                let n2 = 1.0
+                    + b.iter()
+                        .zip(c)
+                        .map(|(b, c)| b * micrometers2 / (micrometers2 - c))
+                        .sum::<f64>();
+                n2.max(1.0).sqrt()
+            }
+        }
+    }
+
+    /// Index at `REFERENCE_WAVELENGTH`
+    pub fn reference(&self) -> f64 {
+        self.at(REFERENCE_WAVELENGTH)
+    }
+
+    /// Index for a ray, at its wavelength when it has one
+    pub fn for_wavelength(&self, lambda: Option<f64>) -> f64 {
+        match lambda {
+            Some(lambda) => self.at(lambda),
+            None => self.reference(),
+        }
+    }
+
+    /// True when the index changes with wavelength
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+impl Default for Ior {
+    fn default() -> Self {
+        Ior::Constant(1.5)
+    }
+}
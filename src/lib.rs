@@ -3,8 +3,46 @@ pub mod ray;
 pub mod hittable;
 pub mod hittable_list;
 pub mod sphere;
+pub mod moving_sphere;
 pub mod rtweekend;
 pub mod interval;
 pub mod camera;
 pub mod color;
+pub mod image;
+pub mod png;
+pub mod pfm;
+pub mod exr;
 pub mod material;
+pub mod microfacet;
+pub mod principled;
+pub mod aabb;
+pub mod bvh;
+pub mod flat_bvh;
+pub mod onb;
+pub mod triangle;
+pub mod load_error;
+pub mod obj;
+pub mod ply;
+pub mod stl;
+pub mod texture;
+pub mod perlin;
+pub mod transform;
+pub mod instance;
+pub mod quad;
+pub mod cuboid;
+pub mod disk;
+pub mod plane;
+pub mod cylinder;
+pub mod cone;
+pub mod torus;
+pub mod capsule;
+pub mod csg;
+pub mod sdf;
+pub mod constant_medium;
+pub mod spectrum;
+pub mod ior;
+pub mod voxel_grid;
+pub mod grid_file;
+pub mod grid_medium;
+mod byte_reader;
+mod poly;
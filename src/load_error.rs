@@ -0,0 +1,77 @@
+use std::fmt;
+use std::io;
+
+/// Error produced while loading a scene file
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// Malformed content, with the file name and the 1-based line it was found on
+    Parse {
+        file: String,
+        line: usize,
+        message: String,
+    },
+    /// Malformed binary content, with the file name and the byte offset it was found at
+    Data {
+        file: String,
+        offset: usize,
+        message: String,
+    },
+    /// File rejected by a third party decoder
+    Decode {
+        file: String,
+        message: String,
+    },
+}
+
+impl LoadError {
+    pub fn parse(file: &str, line: usize, message: impl Into<String>) -> Self {
+        LoadError::Parse {
+            file: file.to_owned(),
+            line,
+            message: message.into(),
+        }
+    }
+
+    pub fn data(file: &str, offset: usize, message: impl Into<String>) -> Self {
+        LoadError::Data {
+            file: file.to_owned(),
+            offset,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "{err}"),
+            LoadError::Parse {
+                file,
+                line,
+                message,
+            } => write!(f, "{file}:{line}: {message}"),
+            LoadError::Data {
+                file,
+                offset,
+                message,
+            } => write!(f, "{file}@{offset}: {message}"),
+            LoadError::Decode { file, message } => write!(f, "{file}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(err) => Some(err),
+            LoadError::Parse { .. } | LoadError::Data { .. } | LoadError::Decode { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
@@ -1,9 +1,18 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+
 use raytracing_rs::{
+    bvh::BvhNode,
     camera::Camera,
     color::Color,
     hittable::Hittable,
     hittable_list::HittableList,
+    exr::{save_exr, ExrOptions},
+    image::write_ppm,
+    pfm::save_pfm,
+    png::{save_png, PngOptions},
     material::{Dielectric, Lambertian, Metal},
+    plane::Plane,
     rtweekend::{random_double, random_double_range},
     sphere::Sphere,
     vec3::{Point3, Vec3},
@@ -12,13 +21,6 @@ use raytracing_rs::{
 fn main() {
     let mut world = HittableList::new();
 
-    let ground_material = Box::new(Lambertian::new(&(Color::new(0.5, 0.5, 0.5))));
-    world.add(Box::new(Sphere::new(
-        Point3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        ground_material,
-    )));
-
     for a in -11..11 {
         for b in -11..11 {
             let choose_mat = random_double();
@@ -92,5 +94,37 @@ fn main() {
     cam.defocus_angle = 0.6;
     cam.focus_dist = 10.0;
 
-    cam.render(Box::new(world) as Box<dyn Hittable + Sync + Send>);
+    // The ground is infinite, so it stays out of the hierarchy built over the spheres
+    let mut scene = HittableList::new();
+    let ground_material = Box::new(Lambertian::new(&(Color::new(0.5, 0.5, 0.5))));
+    scene.add(Box::new(Plane::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        ground_material,
+    )));
+    scene.add(Box::new(BvhNode::new(world)));
+
+    let image = cam.render(Box::new(scene) as Box<dyn Hittable + Sync + Send>);
+
+    // Write to the file given as the first argument, picking the format from its extension,
+    // or print a PPM when there is none
+    match std::env::args().nth(1) {
+        Some(path) if path.ends_with(".png") => {
+            save_png(&image, &path, PngOptions::default()).expect("failed to write image")
+        }
+        Some(path) if path.ends_with(".exr") => {
+            save_exr(&image, &path, ExrOptions::default()).expect("failed to write image")
+        }
+        Some(path) if path.ends_with(".pfm") => {
+            save_pfm(&image, &path).expect("failed to write image")
+        }
+        Some(path) => {
+            let mut out = BufWriter::new(File::create(&path).expect("failed to create image"));
+            write_ppm(&image, &mut out).expect("failed to write image");
+        }
+        None => {
+            let mut out = BufWriter::new(io::stdout().lock());
+            write_ppm(&image, &mut out).expect("failed to write image");
+        }
+    }
 }
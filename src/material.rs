@@ -1,88 +1,213 @@
 use super::color::Color;
 use super::hittable::HitRecord;
+use super::ior::Ior;
+use super::microfacet::{
+    fresnel_conductor, fresnel_dielectric, ComplexIor, Ggx, Substrate, ThinFilm,
+};
+use super::onb::Onb;
 use super::ray::Ray;
-use super::rtweekend::{partial_min, random_double};
-use super::vec3::Vec3;
+use super::rtweekend::{partial_min, random_double, PI};
+use super::texture::{SolidColor, Texture};
+use super::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Outcome of sampling a material
+#[derive(Debug, Clone, Default)]
+pub struct ScatterRecord {
+    /// Throughput of the sampled direction: the BSDF times the cosine divided by the pdf for
+    /// sampled lobes, or the reflectance for specular ones
+    pub attenuation: Color,
+    pub scattered: Ray,
+    /// Solid angle density the direction was sampled with, only meaningful for non-specular
+    pub pdf: f64,
+    /// True when the direction can't be evaluated with `eval`/`scattering_pdf`, such as
+    /// mirrors and glass, so it is never combined with light sampling
+    pub is_specular: bool,
+    /// True when the direction was picked for the wavelength of the incoming ray alone, as
+    /// glass with dispersion does, so other wavelengths traced along must be dropped
+    pub dispersive: bool,
+}
 
 pub trait Material {
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool;
+    ///
+    /// Sample an outgoing direction
+    /// * `r_in` - Incoming ray
+    /// * `rec` - Hit record of the surface point
+    /// * `srec` - Scatter record to fill in
+    /// # Returns
+    /// Return false if the ray is absorbed
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool;
+
+    /// BSDF times the cosine with the normal for light arriving along scattered, black for
+    /// specular materials
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> Color {
+        Color::default()
+    }
+
+    /// Density with which `scatter` would pick the direction of scattered
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
+    /// Light given off at surface coordinates u, v of point p, black for anything that
+    /// isn't a light
+    fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        Color::default()
+    }
 }
 
-#[derive(Debug, Clone, Default)]
+/// Cosine of the angle between the normal and the scattered direction, clamped at zero
+fn cosine_term(rec: &HitRecord, scattered: &Ray) -> f64 {
+    let cosine = Vec3::dot(
+        &rec.shading_normal,
+        &Vec3::unit_vector(&scattered.direction()),
+    );
+    if cosine < 0.0 {
+        0.0
+    } else {
+        cosine
+    }
+}
+
+/// Solid color texture, the albedo of materials built from a plain `Color`
+fn solid(c: &Color) -> Arc<dyn Texture + Sync + Send> {
+    Arc::new(SolidColor::new(c))
+}
+
+/// Cosine weighted direction around the normal, shared by the diffuse materials
+fn scatter_diffuse(albedo: Color, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+    // Offsetting a unit sphere by the normal gives cosine weighted directions
+    let mut scatter_direction = rec.shading_normal + Vec3::random_unit_vector();
+
+    if scatter_direction.near_zero() {
+        scatter_direction = rec.shading_normal;
+    }
+
+    srec.scattered = Ray::new_time(&(rec.p), &scatter_direction, r_in.time());
+    srec.attenuation = albedo;
+    srec.pdf = cosine_term(rec, &srec.scattered) / PI;
+    srec.is_specular = false;
+    true
+}
+
+#[derive(Clone)]
 pub struct Lambertian {
-    albedo: Color,
+    albedo: Arc<dyn Texture + Sync + Send>,
 }
 impl Lambertian {
     pub fn new(a: &Color) -> Self {
-        Self {
-            albedo: a.to_owned(),
-        }
+        Self { albedo: solid(a) }
+    }
+
+    pub fn new_texture(albedo: Arc<dyn Texture + Sync + Send>) -> Self {
+        Self { albedo }
+    }
+}
+impl Default for Lambertian {
+    fn default() -> Self {
+        Lambertian::new(&Color::default())
     }
 }
 impl Material for Lambertian {
-    fn scatter(
-        &self,
-        _r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        scatter_diffuse(self.albedo.value(rec.u, rec.v, &rec.p), r_in, rec, srec)
+    }
 
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
-        }
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.albedo.value(rec.u, rec.v, &rec.p) * (cosine_term(rec, scattered) / PI)
+    }
 
-        *scattered = Ray::new(&(rec.p), &scatter_direction);
-        *attenuation = self.albedo.to_owned();
-        return true;
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        cosine_term(rec, scattered) / PI
     }
 }
 
+/// Diffuse material taking its albedo from the interpolated vertex color of the hit, for
+/// meshes that carry per-vertex colors. Hits without a vertex color use `fallback`.
 #[derive(Debug, Clone, Default)]
+pub struct VertexColor {
+    fallback: Color,
+}
+impl VertexColor {
+    pub fn new(fallback: &Color) -> Self {
+        Self {
+            fallback: fallback.to_owned(),
+        }
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        rec.vertex_color.unwrap_or(self.fallback)
+    }
+}
+impl Material for VertexColor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        scatter_diffuse(self.albedo(rec), r_in, rec, srec)
+    }
+
+    fn eval(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.albedo(rec) * (cosine_term(rec, scattered) / PI)
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        cosine_term(rec, scattered) / PI
+    }
+}
+
+#[derive(Clone)]
 pub struct Metal {
-    albedo: Color,
+    albedo: Arc<dyn Texture + Sync + Send>,
     fuzz: f64,
 }
 impl Metal {
     pub fn new(a: &Color, f: f64) -> Self {
+        Metal::new_texture(solid(a), f)
+    }
+
+    pub fn new_texture(albedo: Arc<dyn Texture + Sync + Send>, f: f64) -> Self {
         Self {
-            albedo: a.to_owned(),
+            albedo,
             fuzz: if f < 1.0 { f } else { 1.0 },
         }
     }
 }
+impl Default for Metal {
+    fn default() -> Self {
+        Metal::new(&Color::default(), 0.0)
+    }
+}
 impl Material for Metal {
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        let reflected = Vec3::reflect(&(Vec3::unit_vector(&(r_in.direction()))), &(rec.normal));
-        *scattered = Ray::new(
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        let reflected = Vec3::reflect(
+            &(Vec3::unit_vector(&(r_in.direction()))),
+            &(rec.shading_normal),
+        );
+        srec.scattered = Ray::new_time(
             &(rec.p),
             &(reflected + (Vec3::random_unit_vector() * self.fuzz)),
+            r_in.time(),
         );
-        *attenuation = self.albedo;
-        return Vec3::dot(&(scattered.direction()), &(rec.normal)) > 0.0;
+        srec.attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        srec.pdf = 0.0;
+        srec.is_specular = true;
+        Vec3::dot(&(srec.scattered.direction()), &(rec.shading_normal)) > 0.0
     }
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct Dielectric {
-    ir: f64,
+    ior: Ior,
 }
 impl Dielectric {
     pub fn new(ir: f64) -> Self {
-        Self { ir }
+        Self {
+            ior: Ior::Constant(ir),
+        }
+    }
+
+    /// Glass whose index varies with wavelength, splitting light into colors when rendering
+    /// spectrally
+    pub fn new_dispersive(ior: Ior) -> Self {
+        Self { ior }
     }
 
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -92,22 +217,16 @@ impl Dielectric {
     }
 }
 impl Material for Dielectric {
-    fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        *attenuation = Color::new(1.0, 1.0, 1.0);
-        let refraction_ratio = if rec.front_face {
-            1.0 / self.ir
-        } else {
-            self.ir
-        };
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        srec.attenuation = Color::new(1.0, 1.0, 1.0);
+        srec.pdf = 0.0;
+        srec.is_specular = true;
+        srec.dispersive = self.ior.is_dispersive();
+        let ir = self.ior.for_wavelength(r_in.wavelength());
+        let refraction_ratio = if rec.front_face { 1.0 / ir } else { ir };
 
         let unit_direction = Vec3::unit_vector(&(r_in.direction()));
-        let cos_theta = partial_min(Vec3::dot(&(-unit_direction), &(rec.normal)), 1.0);
+        let cos_theta = partial_min(Vec3::dot(&(-unit_direction), &(rec.shading_normal)), 1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = (refraction_ratio * sin_theta) > 1.0;
@@ -115,12 +234,1069 @@ impl Material for Dielectric {
 
         if cannot_refract || Dielectric::reflectance(cos_theta, refraction_ratio) > random_double()
         {
-            direction = Vec3::reflect(&unit_direction, &(rec.normal));
+            direction = Vec3::reflect(&unit_direction, &(rec.shading_normal));
         } else {
-            direction = Vec3::refract(&unit_direction, &(rec.normal), refraction_ratio);
+            direction = Vec3::refract(&unit_direction, &(rec.shading_normal), refraction_ratio);
         }
 
-        *scattered = Ray::new(&(rec.p), &direction);
+        srec.scattered = Ray::new_time(&(rec.p), &direction, r_in.time());
         return true;
     }
 }
+
+/// Mean of the three channels
+fn average(c: &Color) -> f64 {
+    (c.x() + c.y() + c.z()) / 3.0
+}
+
+/// Shading frame of rec, with the direction back along r_in in it
+pub(crate) fn local_frame(r_in: &Ray, rec: &HitRecord) -> (Onb, Vec3) {
+    let frame = rec.shading_frame();
+    let wo = frame.to_local(&-Vec3::unit_vector(&r_in.direction()));
+    (frame, wo)
+}
+
+/// Metal with a rough surface made of GGX distributed mirror microfacets, reflecting with the
+/// Fresnel equations of its complex index of refraction. A roughness of zero gives a perfect
+/// mirror. Only single scattering between microfacets is modelled, so very rough metal is
+/// slightly darker than it should be, but never brighter. A thin film on top, like the oxide
+/// of anodized metal, gives it iridescent colors.
+#[derive(Debug, Clone, Copy)]
+pub struct RoughConductor {
+    ior: ComplexIor,
+    distribution: Ggx,
+    film: Option<ThinFilm>,
+}
+impl RoughConductor {
+    ///
+    /// * `ior` - Index of refraction, such as `ComplexIor::GOLD`
+    /// * `roughness` - Perceptual roughness in [0, 1]
+    pub fn new(ior: ComplexIor, roughness: f64) -> Self {
+        Self {
+            ior,
+            distribution: Ggx::from_roughness(roughness),
+            film: None,
+        }
+    }
+
+    /// Metal under a thin film. The film only colors the reflection, so rendering
+    /// spectrally its color is spread over every wavelength traced along the path.
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    fn fresnel(&self, cos_theta_i: f64) -> Color {
+        match &self.film {
+            Some(film) => film.reflectance_rgb(cos_theta_i, &Substrate::Conductor(self.ior)),
+            None => fresnel_conductor(cos_theta_i, &self.ior.eta, &self.ior.k),
+        }
+    }
+
+    /// BSDF times cosine for directions in the local frame
+    fn eval_local(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color::default();
+        }
+        let h = Vec3::unit_vector(&(*wo + *wi));
+        let fresnel = self.fresnel(Vec3::dot(wo, &h));
+        fresnel * (self.distribution.d(&h) * self.distribution.g(wo, wi) / (4.0 * wo.z()))
+    }
+
+    fn pdf_local(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let h = Vec3::unit_vector(&(*wo + *wi));
+        self.distribution.visible_normal_pdf(wo, &h) / (4.0 * Vec3::dot(wo, &h))
+    }
+}
+impl Default for RoughConductor {
+    fn default() -> Self {
+        RoughConductor::new(ComplexIor::ALUMINUM, 0.0)
+    }
+}
+impl Material for RoughConductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        let (frame, wo) = local_frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return false;
+        }
+        if self.distribution.is_smooth() {
+            let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+            srec.scattered = Ray::new_time(&rec.p, &frame.local(&wi), r_in.time());
+            srec.attenuation = self.fresnel(wo.z());
+            srec.pdf = 0.0;
+            srec.is_specular = true;
+            return true;
+        }
+
+        let h = self.distribution.sample_visible_normal(&wo);
+        let wi = Vec3::reflect(&-wo, &h);
+        let pdf = self.pdf_local(&wo, &wi);
+        if pdf <= 0.0 {
+            return false;
+        }
+
+        srec.scattered = Ray::new_time(&rec.p, &frame.local(&wi), r_in.time());
+        srec.attenuation = self.eval_local(&wo, &wi) / pdf;
+        srec.pdf = pdf;
+        srec.is_specular = false;
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        if self.distribution.is_smooth() {
+            return Color::default();
+        }
+        let (frame, wo) = local_frame(r_in, rec);
+        let wi = frame.to_local(&Vec3::unit_vector(&scattered.direction()));
+        self.eval_local(&wo, &wi)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let (frame, wo) = local_frame(r_in, rec);
+        let wi = frame.to_local(&Vec3::unit_vector(&scattered.direction()));
+        self.pdf_local(&wo, &wi)
+    }
+}
+
+/// Glass with a rough surface made of GGX distributed microfacets, each reflecting or
+/// refracting with the exact Fresnel equations. A roughness of zero gives smooth glass.
+/// Radiance is scaled by the squared ratio of indices when crossing the surface. A thin film
+/// on the outside, like a soap bubble's, tints reflection and transmission in opposite colors.
+#[derive(Debug, Clone, Copy)]
+pub struct RoughDielectric {
+    ior: Ior,
+    distribution: Ggx,
+    film: Option<ThinFilm>,
+}
+impl RoughDielectric {
+    ///
+    /// * `ir` - Index of refraction of the inside relative to the outside
+    /// * `roughness` - Perceptual roughness in [0, 1]
+    pub fn new(ir: f64, roughness: f64) -> Self {
+        RoughDielectric::new_dispersive(Ior::Constant(ir), roughness)
+    }
+
+    /// Rough glass whose index varies with wavelength
+    pub fn new_dispersive(ior: Ior, roughness: f64) -> Self {
+        Self {
+            ior,
+            distribution: Ggx::from_roughness(roughness),
+            film: None,
+        }
+    }
+
+    /// Glass under a thin film. The film only colors the light, so rendering spectrally its
+    /// color is spread over every wavelength traced along the path, unless the glass is
+    /// dispersive and only the hero wavelength is left.
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    /// Index of the side the normal points away from, relative to the side it points to, at
+    /// the wavelength of r_in
+    fn relative_ior(&self, r_in: &Ray, rec: &HitRecord) -> f64 {
+        let ir = self.ior.for_wavelength(r_in.wavelength());
+        if rec.front_face {
+            ir
+        } else {
+            1.0 / ir
+        }
+    }
+
+    /// Reflectance of a microfacet seen at cos_o from the side of r_in, eta being the
+    /// relative index `relative_ior` gives
+    fn reflectance(&self, cos_o: f64, eta: f64, r_in: &Ray, rec: &HitRecord) -> Color {
+        let film = match &self.film {
+            Some(film) => film,
+            None => {
+                let fresnel = fresnel_dielectric(cos_o, eta);
+                return Color::new(fresnel, fresnel, fresnel);
+            }
+        };
+
+        // The film is on the outside, so look at it from there
+        let cos_outside = if rec.front_face {
+            cos_o
+        } else {
+            let sin2 = (1.0 - cos_o * cos_o).max(0.0) / (eta * eta);
+            if sin2 >= 1.0 {
+                return Color::new(1.0, 1.0, 1.0);
+            }
+            (1.0 - sin2).sqrt()
+        };
+        // Dispersive glass leaves only the hero wavelength on the path, so the film can be
+        // evaluated at it. Otherwise its color is spread over every wavelength traced along.
+        let lambda = r_in.wavelength().filter(|_| self.ior.is_dispersive());
+        film.reflectance_for(cos_outside, lambda, &Substrate::Dielectric(self.ior))
+    }
+
+    /// Microfacet normal turning wo into wi, None for directions no microfacet connects
+    pub(crate) fn half_vector(wo: &Vec3, wi: &Vec3, eta: f64) -> Option<Vec3> {
+        let reflect = wi.z() > 0.0;
+        let h = if reflect { *wo + *wi } else { *wo + *wi * eta };
+        if h.near_zero() {
+            return None;
+        }
+        let h = Vec3::unit_vector(&h);
+        let h = if h.z() < 0.0 { -h } else { h };
+
+        // Microfacets seen from behind by either direction don't contribute
+        let wi_side = if reflect { 1.0 } else { -1.0 };
+        if Vec3::dot(&h, wo) <= 0.0 || Vec3::dot(&h, wi) * wi_side <= 0.0 {
+            return None;
+        }
+        Some(h)
+    }
+
+    /// BSDF times cosine and sampling density, for directions in the local frame. Reflection
+    /// is picked with the average of the reflectance over the channels.
+    fn eval_pdf_local(&self, wo: &Vec3, wi: &Vec3, r_in: &Ray, rec: &HitRecord) -> (Color, f64) {
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return (Color::default(), 0.0);
+        }
+        let eta = self.relative_ior(r_in, rec);
+        let h = match RoughDielectric::half_vector(wo, wi, eta) {
+            Some(h) => h,
+            None => return (Color::default(), 0.0),
+        };
+
+        let cos_o = Vec3::dot(wo, &h);
+        let cos_i = Vec3::dot(wi, &h);
+        let fresnel = self.reflectance(cos_o, eta, r_in, rec);
+        let reflect_probability = average(&fresnel);
+        let d = self.distribution.d(&h);
+        let g = self.distribution.g(wo, wi);
+        let visible = self.distribution.visible_normal_pdf(wo, &h);
+
+        if wi.z() > 0.0 {
+            let f = fresnel * (d * g / (4.0 * wo.z()));
+            let pdf = reflect_probability * visible / (4.0 * cos_o);
+            return (f, pdf);
+        }
+
+        let denom = (cos_i + cos_o / eta) * (cos_i + cos_o / eta);
+        let transmitted = Color::new(1.0, 1.0, 1.0) - fresnel;
+        let f = transmitted * (d * g * (cos_i * cos_o).abs() / (wo.z() * denom * eta * eta));
+        let pdf = (1.0 - reflect_probability) * visible * cos_i.abs() / denom;
+        (f, pdf)
+    }
+}
+impl Default for RoughDielectric {
+    fn default() -> Self {
+        RoughDielectric::new(1.5, 0.0)
+    }
+}
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        let (frame, wo) = local_frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return false;
+        }
+        let eta = self.relative_ior(r_in, rec);
+        srec.dispersive = self.ior.is_dispersive();
+
+        let h = if self.distribution.is_smooth() {
+            Vec3::new(0.0, 0.0, 1.0)
+        } else {
+            self.distribution.sample_visible_normal(&wo)
+        };
+        let cos_o = Vec3::dot(&wo, &h);
+        let fresnel = self.reflectance(cos_o, eta, r_in, rec);
+        let reflect_probability = average(&fresnel);
+
+        let reflect = random_double() < reflect_probability;
+        let wi = if reflect {
+            Vec3::reflect(&-wo, &h)
+        } else {
+            // Refract around the microfacet, fresnel is one under total internal reflection
+            let sin2_t = (1.0 - cos_o * cos_o).max(0.0) / (eta * eta);
+            let cos_t = (1.0 - sin2_t).sqrt();
+            -wo / eta + h * (cos_o / eta - cos_t)
+        };
+        // Rough microfacets can send either lobe to the wrong side of the surface
+        if (wi.z() > 0.0) != reflect {
+            return false;
+        }
+        srec.scattered = Ray::new_time(&rec.p, &frame.local(&wi), r_in.time());
+
+        if self.distribution.is_smooth() {
+            srec.attenuation = if reflect {
+                fresnel / reflect_probability
+            } else {
+                (Color::new(1.0, 1.0, 1.0) - fresnel) / ((1.0 - reflect_probability) * eta * eta)
+            };
+            srec.pdf = 0.0;
+            srec.is_specular = true;
+            return true;
+        }
+
+        let (f, pdf) = self.eval_pdf_local(&wo, &wi, r_in, rec);
+        if pdf <= 0.0 {
+            return false;
+        }
+        srec.attenuation = f / pdf;
+        srec.pdf = pdf;
+        srec.is_specular = false;
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        if self.distribution.is_smooth() {
+            return Color::default();
+        }
+        let (frame, wo) = local_frame(r_in, rec);
+        let wi = frame.to_local(&Vec3::unit_vector(&scattered.direction()));
+        self.eval_pdf_local(&wo, &wi, r_in, rec).0
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let (frame, wo) = local_frame(r_in, rec);
+        let wi = frame.to_local(&Vec3::unit_vector(&scattered.direction()));
+        self.eval_pdf_local(&wo, &wi, r_in, rec).1
+    }
+}
+
+/// Emissive material turning whatever it is applied to into an area light. It emits equally
+/// from both sides and does not scatter light.
+#[derive(Clone)]
+pub struct DiffuseLight {
+    emit: Arc<dyn Texture + Sync + Send>,
+}
+impl DiffuseLight {
+    pub fn new(emit: &Color) -> Self {
+        Self { emit: solid(emit) }
+    }
+
+    pub fn new_texture(emit: Arc<dyn Texture + Sync + Send>) -> Self {
+        Self { emit }
+    }
+}
+impl Default for DiffuseLight {
+    fn default() -> Self {
+        DiffuseLight::new(&Color::default())
+    }
+}
+impl Material for DiffuseLight {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord, _srec: &mut ScatterRecord) -> bool {
+        false
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.emit.value(u, v, p)
+    }
+}
+
+/// Phase function of participating media, scattering equally in every direction
+#[derive(Clone)]
+pub struct Isotropic {
+    albedo: Arc<dyn Texture + Sync + Send>,
+}
+impl Isotropic {
+    pub fn new(albedo: &Color) -> Self {
+        Self {
+            albedo: solid(albedo),
+        }
+    }
+
+    pub fn new_texture(albedo: Arc<dyn Texture + Sync + Send>) -> Self {
+        Self { albedo }
+    }
+}
+impl Default for Isotropic {
+    fn default() -> Self {
+        Isotropic::new(&Color::default())
+    }
+}
+impl Material for Isotropic {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        srec.scattered = Ray::new_time(&rec.p, &Vec3::random_unit_vector(), r_in.time());
+        srec.attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        srec.pdf = self.scattering_pdf(r_in, rec, &srec.scattered);
+        srec.is_specular = false;
+        true
+    }
+
+    /// There is no surface to take a cosine with, this is the phase function alone
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.albedo.value(rec.u, rec.v, &rec.p) * self.scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+/// Blend of two materials, `b` taking over from `a` as the weight goes from zero to one.
+/// Each scatter picks one of the two, then weighs a non-specular direction with the density
+/// of both, so a mask between two materials reflects exactly the blend of their energy.
+#[derive(Clone)]
+pub struct Mix {
+    a: Arc<Box<dyn Material + Sync + Send>>,
+    b: Arc<Box<dyn Material + Sync + Send>>,
+    weight: Arc<dyn Texture + Sync + Send>,
+}
+impl Mix {
+    pub fn new(
+        a: Box<dyn Material + Sync + Send>,
+        b: Box<dyn Material + Sync + Send>,
+        weight: f64,
+    ) -> Self {
+        Mix::new_texture(a, b, Arc::new(SolidColor::new_scalar(weight)))
+    }
+
+    /// Blend driven by a texture, such as a dirt mask, using the average of its channels
+    pub fn new_texture(
+        a: Box<dyn Material + Sync + Send>,
+        b: Box<dyn Material + Sync + Send>,
+        weight: Arc<dyn Texture + Sync + Send>,
+    ) -> Self {
+        Self {
+            a: Arc::new(a),
+            b: Arc::new(b),
+            weight,
+        }
+    }
+
+    fn weight(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        let w = self.weight.value(u, v, p);
+        ((w.x() + w.y() + w.z()) / 3.0).clamp(0.0, 1.0)
+    }
+}
+impl Material for Mix {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        let weight = self.weight(rec.u, rec.v, &rec.p);
+        let chosen = if random_double() < weight {
+            &self.b
+        } else {
+            &self.a
+        };
+        if !chosen.scatter(r_in, rec, srec) {
+            return false;
+        }
+        // Picking the material already weighs specular directions by the blend
+        if srec.is_specular {
+            return true;
+        }
+
+        let pdf = (1.0 - weight) * self.a.scattering_pdf(r_in, rec, &srec.scattered)
+            + weight * self.b.scattering_pdf(r_in, rec, &srec.scattered);
+        if pdf <= 0.0 {
+            return false;
+        }
+        srec.attenuation = self.eval(r_in, rec, &srec.scattered) / pdf;
+        srec.pdf = pdf;
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let weight = self.weight(rec.u, rec.v, &rec.p);
+        self.a.eval(r_in, rec, scattered) * (1.0 - weight)
+            + self.b.eval(r_in, rec, scattered) * weight
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let weight = self.weight(rec.u, rec.v, &rec.p);
+        (1.0 - weight) * self.a.scattering_pdf(r_in, rec, scattered)
+            + weight * self.b.scattering_pdf(r_in, rec, scattered)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let weight = self.weight(u, v, p);
+        self.a.emitted(u, v, p) * (1.0 - weight) + self.b.emitted(u, v, p) * weight
+    }
+}
+
+/// Bounces under a `Layered` coating after which the light is given up as absorbed
+const LAYERED_MAX_BOUNCES: usize = 32;
+
+/// Smooth dielectric coating, such as varnish or a clearcoat, over any base material. Light
+/// either reflects off the coating, or refracts through it and scatters off the base until
+/// it refracts back out, being reflected back down by the underside of the coating in
+/// between. Directions are bent by the coating on the way in and out, so a coated diffuse
+/// base keeps its cosine falloff but gets the darker, more saturated look of varnish.
+///
+/// Only light scattering once off the base is available to `eval`, light that bounced under
+/// the coating comes out of `scatter` marked specular, like the reflection off the top.
+///
+/// Only the outside of the object is coated, hits from inside go straight to the base.
+#[derive(Clone)]
+pub struct Layered {
+    base: Arc<Box<dyn Material + Sync + Send>>,
+    ir: f64,
+    /// Fraction of light kept by one pass straight through the coating
+    tint: Color,
+}
+impl Layered {
+    ///
+    /// * `base` - Material under the coating
+    /// * `ir` - Index of refraction of the coating, relative to the outside
+    pub fn new(base: Box<dyn Material + Sync + Send>, ir: f64) -> Self {
+        Self {
+            base: Arc::new(base),
+            ir,
+            tint: Color::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Colored coating, tint being what one pass straight through it lets through. Slanted
+    /// passes travel further and absorb more.
+    pub fn with_tint(mut self, tint: &Color) -> Self {
+        self.tint = *tint;
+        self
+    }
+
+    /// Direction in the coating matching the outside direction w in the local frame, both
+    /// pointing away from the surface
+    fn refract_in(&self, w: &Vec3) -> Vec3 {
+        let x = w.x() / self.ir;
+        let y = w.y() / self.ir;
+        let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+        Vec3::new(x, y, z.copysign(w.z()))
+    }
+
+    /// Outside direction matching w in the coating, None under total internal reflection
+    fn refract_out(&self, w: &Vec3) -> Option<Vec3> {
+        let x = w.x() * self.ir;
+        let y = w.y() * self.ir;
+        let cos2 = 1.0 - x * x - y * y;
+        if cos2 <= 0.0 {
+            return None;
+        }
+        Some(Vec3::new(x, y, cos2.sqrt()))
+    }
+
+    /// Light kept crossing the coating along a path of the given length, in thicknesses
+    fn absorption(&self, length: f64) -> Color {
+        Color::new(
+            self.tint.x().powf(length),
+            self.tint.y().powf(length),
+            self.tint.z().powf(length),
+        )
+    }
+
+    /// Ray handed to the base, keeping the time and wavelength of r_in so a dispersive base
+    /// still sees the wavelength it is traced at
+    fn inner_ray(r_in: &Ray, p: &Point3, direction: &Vec3) -> Ray {
+        let ray = Ray::new_time(p, direction, r_in.time());
+        match r_in.wavelength() {
+            Some(lambda) => ray.with_wavelength(lambda),
+            None => ray,
+        }
+    }
+
+    /// Length of one pass through the coating along a direction with inner cosine w.z()
+    fn pass_length(w: &Vec3) -> f64 {
+        1.0 / w.z().abs().max(1e-4)
+    }
+
+    /// BSDF times cosine and sampling density of light scattering once off the base,
+    /// leaving out the specular reflection off the top and the light bouncing around under
+    /// the coating, which `scatter` returns as specular
+    fn eval_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> (Color, f64) {
+        let (frame, wo) = local_frame(r_in, rec);
+        let wi = frame.to_local(&Vec3::unit_vector(&scattered.direction()));
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return (Color::default(), 0.0);
+        }
+
+        let wo_inner = self.refract_in(&wo);
+        let inner_in = Layered::inner_ray(r_in, &rec.p, &frame.local(&-wo_inner));
+        let enter = 1.0 - fresnel_dielectric(wo.z(), self.ir);
+
+        // Light the base sends below the surface doesn't cross the coating again
+        if wi.z() < 0.0 {
+            let f = self.base.eval(&inner_in, rec, scattered);
+            let pdf = self.base.scattering_pdf(&inner_in, rec, scattered);
+            let absorption = self.absorption(Layered::pass_length(&wo_inner));
+            return (f * absorption * enter, pdf * enter);
+        }
+
+        let wi_inner = self.refract_in(&wi);
+        let inner_scattered = Layered::inner_ray(r_in, &rec.p, &frame.local(&wi_inner));
+        let f = self.base.eval(&inner_in, rec, &inner_scattered);
+        let pdf = self.base.scattering_pdf(&inner_in, rec, &inner_scattered);
+
+        // Solid angle shrinks by cos_i / (eta^2 cos_i') refracting out, and the radiance
+        // gained refracting in is given back
+        let exit = 1.0 - fresnel_dielectric(wi.z(), self.ir);
+        let jacobian = wi.z() / (self.ir * self.ir * wi_inner.z());
+        let length = Layered::pass_length(&wo_inner) + Layered::pass_length(&wi_inner);
+        (
+            f * self.absorption(length) * (enter * exit * jacobian),
+            pdf * enter * exit * jacobian,
+        )
+    }
+}
+impl Material for Layered {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        if !rec.front_face {
+            return self.base.scatter(r_in, rec, srec);
+        }
+        let (frame, wo) = local_frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return false;
+        }
+
+        if random_double() < fresnel_dielectric(wo.z(), self.ir) {
+            let wi = Vec3::new(-wo.x(), -wo.y(), wo.z());
+            srec.scattered = Ray::new_time(&rec.p, &frame.local(&wi), r_in.time());
+            srec.attenuation = Color::new(1.0, 1.0, 1.0);
+            srec.pdf = 0.0;
+            srec.is_specular = true;
+            return true;
+        }
+
+        // Walk between the base and the underside of the coating until the light gets out,
+        // every Fresnel choice being made with the probability it stands for
+        let mut wo_inner = self.refract_in(&wo);
+        let mut length = Layered::pass_length(&wo_inner);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut is_specular = false;
+        for bounce in 0..LAYERED_MAX_BOUNCES {
+            let inner_in = Layered::inner_ray(r_in, &rec.p, &frame.local(&-wo_inner));
+            if !self.base.scatter(&inner_in, rec, srec) {
+                return false;
+            }
+            throughput = throughput * srec.attenuation;
+            is_specular |= srec.is_specular || bounce > 0;
+
+            let wi_inner = frame.to_local(&Vec3::unit_vector(&srec.scattered.direction()));
+            if wi_inner.z() < 0.0 {
+                srec.attenuation = throughput * self.absorption(length);
+                break;
+            }
+
+            length += Layered::pass_length(&wi_inner);
+            if let Some(wi) = self.refract_out(&wi_inner) {
+                if random_double() >= fresnel_dielectric(wi.z(), self.ir) {
+                    srec.scattered = Ray::new_time(&rec.p, &frame.local(&wi), r_in.time());
+                    srec.attenuation = throughput * self.absorption(length);
+                    break;
+                }
+            }
+
+            // Reflected back down onto the base
+            if bounce + 1 == LAYERED_MAX_BOUNCES {
+                return false;
+            }
+            wo_inner = Vec3::new(-wi_inner.x(), -wi_inner.y(), wi_inner.z());
+            length += Layered::pass_length(&wo_inner);
+        }
+
+        srec.is_specular = is_specular;
+        srec.pdf = if is_specular {
+            0.0
+        } else {
+            self.scattering_pdf(r_in, rec, &srec.scattered)
+        };
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        if !rec.front_face {
+            return self.base.eval(r_in, rec, scattered);
+        }
+        self.eval_pdf(r_in, rec, scattered).0
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        if !rec.front_face {
+            return self.base.scattering_pdf(r_in, rec, scattered);
+        }
+        self.eval_pdf(r_in, rec, scattered).1
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.base.emitted(u, v, p)
+    }
+}
+
+/// Tangent space normal map over any material. The map's colors, remapped from [0, 1] to
+/// [-1, 1], give the shading normal in the frame of the hit's tangent, bitangent and shading
+/// normal, green pointing towards increasing v as in OpenGL. Normal maps hold data rather
+/// than colors, load them with `ImageTexture::load_linear`.
+#[derive(Clone)]
+pub struct NormalMapped {
+    base: Arc<Box<dyn Material + Sync + Send>>,
+    map: Arc<dyn Texture + Sync + Send>,
+    strength: f64,
+}
+impl NormalMapped {
+    pub fn new(base: Box<dyn Material + Sync + Send>, map: Arc<dyn Texture + Sync + Send>) -> Self {
+        Self {
+            base: Arc::new(base),
+            map,
+            strength: 1.0,
+        }
+    }
+
+    /// Scale the tilt of the mapped normals, zero flattening the map and values above one
+    /// exaggerating it
+    pub fn with_strength(mut self, strength: f64) -> Self {
+        self.strength = strength;
+        self
+    }
+
+    /// Copy of rec with the shading frame turned to the mapped normal
+    fn perturbed(&self, rec: &HitRecord) -> HitRecord {
+        let mut perturbed = rec.clone();
+        let c = self.map.value(rec.u, rec.v, &rec.p) * 2.0 - Color::new(1.0, 1.0, 1.0);
+        let local = Vec3::new(c.x() * self.strength, c.y() * self.strength, c.z());
+        let normal = rec.shading_frame().local(&local);
+        // Normals tilted past the surface would shade its other side, those are left alone
+        if local.z() > 0.0 && Vec3::dot(&normal, &rec.normal) > 0.0 {
+            perturbed.set_shading_normal(&normal);
+        }
+        perturbed
+    }
+}
+impl Material for NormalMapped {
+    // Directions on different sides of the mapped and the geometric normal would leak light
+    // through the surface, they are dropped like the camera drops them for interpolated
+    // normals
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        let rec = self.perturbed(rec);
+        self.base.scatter(r_in, &rec, srec) && rec.same_side(&srec.scattered.direction())
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let rec = self.perturbed(rec);
+        if !rec.same_side(&scattered.direction()) {
+            return Color::default();
+        }
+        self.base.eval(r_in, &rec, scattered)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let rec = self.perturbed(rec);
+        if !rec.same_side(&scattered.direction()) {
+            return 0.0;
+        }
+        self.base.scattering_pdf(r_in, &rec, scattered)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.base.emitted(u, v, p)
+    }
+}
+
+/// Step in u and v over which `BumpMapped` differentiates its height texture
+const BUMP_DELTA: f64 = 0.0005;
+
+/// Bump map over any material. The surface is shaded as if it were pushed out along its
+/// shading normal by the average of the height texture times scale, while the geometry
+/// stays where it is. The slope of the height comes from the hit's dpdu and dpdv, so hits
+/// on surfaces without them are shaded unperturbed.
+#[derive(Clone)]
+pub struct BumpMapped {
+    base: Arc<Box<dyn Material + Sync + Send>>,
+    height: Arc<dyn Texture + Sync + Send>,
+    scale: f64,
+}
+impl BumpMapped {
+    ///
+    /// * `base` - Material being bumped
+    /// * `height` - Displacement texture, in [0, 1] for image maps
+    /// * `scale` - World space distance a height of one displaces by
+    pub fn new(
+        base: Box<dyn Material + Sync + Send>,
+        height: Arc<dyn Texture + Sync + Send>,
+        scale: f64,
+    ) -> Self {
+        Self {
+            base: Arc::new(base),
+            height,
+            scale,
+        }
+    }
+
+    fn displacement(&self, u: f64, v: f64, p: &Point3) -> f64 {
+        average(&self.height.value(u, v, p)) * self.scale
+    }
+
+    /// Copy of rec with the shading frame of the displaced surface
+    fn perturbed(&self, rec: &HitRecord) -> HitRecord {
+        let mut perturbed = rec.clone();
+        if rec.dpdu.near_zero() || rec.dpdv.near_zero() {
+            return perturbed;
+        }
+
+        let displacement = self.displacement(rec.u, rec.v, &rec.p);
+        let displacement_u =
+            self.displacement(rec.u + BUMP_DELTA, rec.v, &(rec.p + rec.dpdu * BUMP_DELTA));
+        let displacement_v =
+            self.displacement(rec.u, rec.v + BUMP_DELTA, &(rec.p + rec.dpdv * BUMP_DELTA));
+
+        let n = rec.shading_normal;
+        let dpdu = rec.dpdu + n * ((displacement_u - displacement) / BUMP_DELTA);
+        let dpdv = rec.dpdv + n * ((displacement_v - displacement) / BUMP_DELTA);
+        let mut normal = Vec3::cross(&dpdu, &dpdv);
+        // The cross product points to whichever side the handedness of the UVs gives
+        if Vec3::dot(&Vec3::cross(&rec.dpdu, &rec.dpdv), &n) < 0.0 {
+            normal = -normal;
+        }
+        if normal.near_zero() || Vec3::dot(&normal, &rec.normal) <= 0.0 {
+            return perturbed;
+        }
+
+        perturbed.set_shading_normal(&normal);
+        perturbed.set_derivatives(&dpdu, &dpdv);
+        perturbed
+    }
+}
+impl Material for BumpMapped {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        let rec = self.perturbed(rec);
+        self.base.scatter(r_in, &rec, srec) && rec.same_side(&srec.scattered.direction())
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let rec = self.perturbed(rec);
+        if !rec.same_side(&scattered.direction()) {
+            return Color::default();
+        }
+        self.base.eval(r_in, &rec, scattered)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let rec = self.perturbed(rec);
+        if !rec.same_side(&scattered.direction()) {
+            return 0.0;
+        }
+        self.base.scattering_pdf(r_in, &rec, scattered)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.base.emitted(u, v, p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Monte Carlo noise allowed on the albedo estimates
+    const TOLERANCE: f64 = 0.015;
+    const SAMPLES: usize = 100_000;
+
+    /// Conductor reflecting everything at every angle
+    fn perfect_reflector() -> ComplexIor {
+        ComplexIor::new(&Color::new(1e-3, 1e-3, 1e-3), &Color::new(1e3, 1e3, 1e3))
+    }
+
+    /// Ray arriving at theta degrees from the normal of a surface through the origin facing
+    /// +y, hitting its front face or, with the surface flipped, its back face
+    fn hit(theta: f64, front_face: bool) -> (Ray, HitRecord) {
+        let theta = theta.to_radians();
+        let r = Ray::new(
+            &Point3::new(-theta.sin(), theta.cos(), 0.0),
+            &Vec3::new(theta.sin(), -theta.cos(), 0.0),
+        );
+        let outward_normal = if front_face {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(0.0, -1.0, 0.0)
+        };
+        let mut rec = HitRecord::default();
+        rec.set_face_normal(&r, &outward_normal);
+        (r, rec)
+    }
+
+    /// Mean throughput of `scatter`, absorbed samples counting as black. Transmitted samples
+    /// are multiplied by transmission_scale, which turns radiance into flux when it undoes
+    /// the scaling by the squared ratio of indices.
+    fn scatter_albedo(m: &dyn Material, r: &Ray, rec: &HitRecord, transmission_scale: f64) -> f64 {
+        let mut sum = 0.0;
+        for _ in 0..SAMPLES {
+            let mut srec = ScatterRecord::default();
+            if !m.scatter(r, rec, &mut srec) {
+                continue;
+            }
+            let transmitted = Vec3::dot(&srec.scattered.direction(), &rec.normal) < 0.0;
+            let scale = if transmitted { transmission_scale } else { 1.0 };
+            sum += srec.attenuation.x() * scale;
+        }
+        sum / SAMPLES as f64
+    }
+
+    /// Integral of `eval` over the sphere by midpoint quadrature in spherical coordinates
+    /// around the normal, fine enough to resolve the lobes at grazing angles. The incoming
+    /// ray lies in the xy plane, so only half the azimuths are needed.
+    fn eval_albedo(m: &dyn Material, r: &Ray, rec: &HitRecord) -> f64 {
+        let (theta_steps, phi_steps) = (512, 512);
+        let (d_theta, d_phi) = (PI / theta_steps as f64, PI / phi_steps as f64);
+        let mut sum = 0.0;
+        for i in 0..theta_steps {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..phi_steps {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let direction = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let scattered = Ray::new(&rec.p, &direction);
+                sum += m.eval(r, rec, &scattered).x() * theta.sin();
+            }
+        }
+        2.0 * sum * d_theta * d_phi
+    }
+
+    #[test]
+    fn rough_conductor_white_furnace() {
+        let (r, rec) = hit(30.0, true);
+        let smooth = RoughConductor::new(perfect_reflector(), 0.0);
+        let albedo = scatter_albedo(&smooth, &r, &rec, 1.0);
+        assert!((albedo - 1.0).abs() < 1e-3, "smooth mirror albedo {albedo}");
+
+        for roughness in [0.3, 0.6, 1.0] {
+            let m = RoughConductor::new(perfect_reflector(), roughness);
+            for theta in [0.0, 45.0, 80.0] {
+                let (r, rec) = hit(theta, true);
+                let sampled = scatter_albedo(&m, &r, &rec, 1.0);
+                let single_scattering = eval_albedo(&m, &r, &rec);
+                assert!(
+                    sampled <= 1.0 + TOLERANCE,
+                    "roughness {roughness} at {theta} degrees gains energy: {sampled}"
+                );
+                assert!(
+                    (sampled - single_scattering).abs() < TOLERANCE,
+                    "roughness {roughness} at {theta} degrees: sampled {sampled}, single \
+                     scattering albedo {single_scattering}"
+                );
+            }
+        }
+
+        // With alpha = 1 the visible normals seen head on are cosine distributed, and the
+        // height correlated masking leaves 1 - ln 2 of the light
+        let (r, rec) = hit(0.0, true);
+        let sampled = scatter_albedo(
+            &RoughConductor::new(perfect_reflector(), 1.0),
+            &r,
+            &rec,
+            1.0,
+        );
+        let expected = 1.0 - 2.0_f64.ln();
+        assert!(
+            (sampled - expected).abs() < TOLERANCE,
+            "albedo {sampled}, expected {expected}"
+        );
+    }
+
+    #[test]
+    fn rough_dielectric_white_furnace() {
+        let ir: f64 = 1.5;
+        for roughness in [0.0, 0.3, 0.6, 1.0] {
+            let m = RoughDielectric::new(ir, roughness);
+            for theta in [0.0, 45.0, 80.0] {
+                for front_face in [true, false] {
+                    let (r, rec) = hit(theta, front_face);
+                    let flux_scale = if front_face { ir * ir } else { 1.0 / (ir * ir) };
+                    let flux = scatter_albedo(&m, &r, &rec, flux_scale);
+                    assert!(
+                        flux <= 1.0 + TOLERANCE,
+                        "roughness {roughness} at {theta} degrees, front face {front_face} \
+                         gains energy: {flux}"
+                    );
+                    if roughness == 0.0 {
+                        // Smooth glass only loses light to nothing, each choice being exact
+                        assert!((flux - 1.0).abs() < TOLERANCE, "smooth glass flux {flux}");
+                        continue;
+                    }
+
+                    let sampled = scatter_albedo(&m, &r, &rec, 1.0);
+                    let expected = eval_albedo(&m, &r, &rec);
+                    assert!(
+                        (sampled - expected).abs() < TOLERANCE,
+                        "roughness {roughness} at {theta} degrees, front face {front_face}: \
+                         sampled {sampled}, eval integrates to {expected}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn attenuation_matches_eval_over_pdf() {
+        let materials: Vec<Box<dyn Material>> = vec![
+            Box::new(RoughConductor::new(ComplexIor::GOLD, 0.5)),
+            Box::new(RoughConductor::new(ComplexIor::COPPER, 0.2)),
+            Box::new(RoughDielectric::new(1.5, 0.5)),
+            Box::new(RoughDielectric::new(1.33, 0.2)),
+        ];
+        for m in materials.iter() {
+            for theta in [0.0, 45.0, 80.0] {
+                for front_face in [true, false] {
+                    let (r, rec) = hit(theta, front_face);
+                    for _ in 0..1000 {
+                        let mut srec = ScatterRecord::default();
+                        if !m.scatter(&r, &rec, &mut srec) {
+                            continue;
+                        }
+                        assert!(!srec.is_specular);
+
+                        let pdf = m.scattering_pdf(&r, &rec, &srec.scattered);
+                        assert!(
+                            (pdf - srec.pdf).abs() <= 1e-9 * pdf.max(1.0),
+                            "pdf {pdf}, sampled with {}",
+                            srec.pdf
+                        );
+                        let expected = m.eval(&r, &rec, &srec.scattered) / pdf;
+                        let error = expected - srec.attenuation;
+                        assert!(
+                            error.length() <= 1e-9 * expected.length().max(1.0),
+                            "attenuation {:?}, eval / pdf {:?}",
+                            srec.attenuation,
+                            expected
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Diffuse base remembering the wavelengths of the rays it is handed
+    struct WavelengthProbe {
+        seen: Arc<std::sync::Mutex<Vec<Option<f64>>>>,
+    }
+
+    impl Material for WavelengthProbe {
+        fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+            self.seen.lock().unwrap().push(r_in.wavelength());
+            scatter_diffuse(Color::new(1.0, 1.0, 1.0), r_in, rec, srec)
+        }
+
+        fn eval(&self, r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> Color {
+            self.seen.lock().unwrap().push(r_in.wavelength());
+            Color::default()
+        }
+    }
+
+    #[test]
+    fn layered_passes_wavelength_to_base() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let probe = WavelengthProbe { seen: seen.clone() };
+        let m = Layered::new(Box::new(probe), 1.5);
+        let (r, rec) = hit(30.0, true);
+        let r = r.with_wavelength(450.0);
+        for _ in 0..100 {
+            let mut srec = ScatterRecord::default();
+            m.scatter(&r, &rec, &mut srec);
+        }
+        m.eval(&r, &rec, &Ray::new(&rec.p, &Vec3::new(0.0, 1.0, 0.0)));
+
+        let seen = seen.lock().unwrap();
+        assert!(!seen.is_empty());
+        assert!(seen.iter().all(|lambda| *lambda == Some(450.0)), "{seen:?}");
+    }
+}
@@ -0,0 +1,357 @@
+use super::color::Color;
+use super::ior::Ior;
+use super::rtweekend::{random_double, PI};
+use super::spectrum::reflectance_to_rgb;
+use super::vec3::Vec3;
+
+/// Below this alpha a surface is treated as perfectly smooth, the distribution becomes too
+/// peaked to evaluate
+pub const SMOOTH_ALPHA: f64 = 1e-3;
+
+/// Trowbridge-Reitz (GGX) distribution of microfacet normals, with Smith height-correlated
+/// masking-shadowing. Directions are in a local frame whose z axis is the surface normal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ggx {
+    pub alpha: f64,
+}
+
+impl Ggx {
+    /// Distribution for a perceptual roughness in [0, 1], alpha being its square
+    pub fn from_roughness(roughness: f64) -> Self {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Ggx {
+            alpha: roughness * roughness,
+        }
+    }
+
+    /// True when the surface should be handled as a perfect mirror or refractor
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < SMOOTH_ALPHA
+    }
+
+    /// Density of microfacets facing h, per unit of projected area
+    pub fn d(&self, h: &Vec3) -> f64 {
+        let a2 = self.alpha * self.alpha;
+        let cos2 = h.z() * h.z();
+        let denom = cos2 * (a2 - 1.0) + 1.0;
+        a2 / (PI * denom * denom)
+    }
+
+    /// Smith auxiliary function, the projected area of back facing microfacets relative to
+    /// the macro surface when seen from w
+    pub fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2 = w.z() * w.z();
+        if cos2 == 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        0.5 * (-1.0 + (1.0 + self.alpha * self.alpha * tan2).sqrt())
+    }
+
+    /// Fraction of the microfacets visible from w
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of the microfacets visible from both wo and wi
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of the normals visible from w, over solid angle of h
+    pub fn visible_normal_pdf(&self, w: &Vec3, h: &Vec3) -> f64 {
+        if w.z() == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / w.z().abs() * self.d(h) * Vec3::dot(w, h).abs()
+    }
+
+    /// Microfacet normal drawn from the normals visible from w, which must be above the
+    /// surface, following Heitz, "Sampling the GGX Distribution of Visible Normals" (2018)
+    pub fn sample_visible_normal(&self, w: &Vec3) -> Vec3 {
+        // Stretch the view so the distribution becomes a hemisphere
+        let vh = Vec3::unit_vector(&Vec3::new(self.alpha * w.x(), self.alpha * w.y(), w.z()));
+        let length_squared = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if length_squared > 0.0 {
+            Vec3::new(-vh.y(), vh.x(), 0.0) / length_squared.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vec3::cross(&vh, &t1);
+
+        // Uniform point on a disk, squeezed onto the part of the hemisphere that's visible
+        let r = random_double().sqrt();
+        let phi = 2.0 * PI * random_double();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+        // Unstretch
+        Vec3::unit_vector(&Vec3::new(
+            self.alpha * nh.x(),
+            self.alpha * nh.y(),
+            nh.z().max(1e-6),
+        ))
+    }
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric interface
+/// * `cos_theta_i` - Cosine between the incident direction and the normal, negative when
+///   arriving from the inside
+/// * `eta` - Index of refraction of the inside relative to the outside
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let (cos_i, eta) = if cos_theta_i < 0.0 {
+        (-cos_theta_i.max(-1.0), 1.0 / eta)
+    } else {
+        (cos_theta_i.min(1.0), eta)
+    };
+
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+/// Unpolarized Fresnel reflectance of a conductor, per color channel, for light arriving
+/// from a dielectric of index one
+/// * `eta` - Real part of the complex index of refraction
+/// * `k` - Absorption coefficient, the imaginary part
+pub fn fresnel_conductor(cos_theta_i: f64, eta: &Color, k: &Color) -> Color {
+    let channel = |eta: f64, k: f64| fresnel_complex(cos_theta_i, Complex::new(eta, k));
+    Color::new(
+        channel(eta.x(), k.x()),
+        channel(eta.y(), k.y()),
+        channel(eta.z(), k.z()),
+    )
+}
+
+fn fresnel_complex(cos_theta_i: f64, eta: Complex) -> f64 {
+    let cos_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin2_i = 1.0 - cos_i * cos_i;
+    let sin2_t = Complex::new(sin2_i, 0.0) / (eta * eta);
+    let cos_t = (Complex::new(1.0, 0.0) - sin2_t).sqrt();
+    let cos_i = Complex::new(cos_i, 0.0);
+
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parallel.norm() + r_perpendicular.norm())
+}
+
+/// Complex index of refraction of a metal, sampled at red, green and blue wavelengths
+#[derive(Debug, Clone, Copy)]
+pub struct ComplexIor {
+    pub eta: Color,
+    pub k: Color,
+}
+
+impl ComplexIor {
+    pub const GOLD: ComplexIor = ComplexIor {
+        eta: Color::new(0.143, 0.374, 1.442),
+        k: Color::new(3.983, 2.386, 1.603),
+    };
+    pub const SILVER: ComplexIor = ComplexIor {
+        eta: Color::new(0.155, 0.117, 0.138),
+        k: Color::new(4.828, 3.122, 2.147),
+    };
+    pub const COPPER: ComplexIor = ComplexIor {
+        eta: Color::new(0.200, 0.924, 1.102),
+        k: Color::new(3.912, 2.452, 2.142),
+    };
+    pub const ALUMINUM: ComplexIor = ComplexIor {
+        eta: Color::new(1.657, 0.880, 0.521),
+        k: Color::new(9.224, 6.270, 4.837),
+    };
+    pub const IRON: ComplexIor = ComplexIor {
+        eta: Color::new(2.911, 2.950, 2.585),
+        k: Color::new(3.089, 2.932, 2.767),
+    };
+    pub const CHROMIUM: ComplexIor = ComplexIor {
+        eta: Color::new(3.107, 3.181, 2.323),
+        k: Color::new(3.331, 3.329, 3.135),
+    };
+
+    pub fn new(eta: &Color, k: &Color) -> Self {
+        ComplexIor { eta: *eta, k: *k }
+    }
+
+    /// Reflectance looking straight at the metal
+    pub fn normal_reflectance(&self) -> Color {
+        fresnel_conductor(1.0, &self.eta, &self.k)
+    }
+
+    /// Index and absorption at lambda nanometers, interpolated between the blue, green and red
+    /// samples taken as 450, 550 and 650 nm
+    pub fn at(&self, lambda: f64) -> (f64, f64) {
+        let t = ((lambda - 450.0) / 100.0).clamp(0.0, 2.0);
+        let lerp = |c: &Color| {
+            if t < 1.0 {
+                c.z() + (c.y() - c.z()) * t
+            } else {
+                c.y() + (c.x() - c.y()) * (t - 1.0)
+            }
+        };
+        (lerp(&self.eta), lerp(&self.k))
+    }
+}
+
+/// What a thin film lies on
+#[derive(Debug, Clone, Copy)]
+pub enum Substrate {
+    Dielectric(Ior),
+    Conductor(ComplexIor),
+}
+
+impl Substrate {
+    fn ior(&self, lambda: f64) -> Complex {
+        match self {
+            Substrate::Dielectric(ior) => Complex::new(ior.at(lambda), 0.0),
+            Substrate::Conductor(ior) => {
+                let (eta, k) = ior.at(lambda);
+                Complex::new(eta, k)
+            }
+        }
+    }
+}
+
+/// Transparent film a fraction of a micrometer thick on top of a surface, like soap, oil or
+/// the oxide of anodized metal. Light reflecting off its top and bottom interferes, so the
+/// reflectance swings with wavelength and angle into iridescent colors.
+#[derive(Debug, Clone, Copy)]
+pub struct ThinFilm {
+    /// Thickness in nanometers
+    pub thickness: f64,
+    pub ior: f64,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f64, ior: f64) -> Self {
+        ThinFilm { thickness, ior }
+    }
+
+    /// Unpolarized reflectance at lambda nanometers of the film over the substrate, for light
+    /// arriving from a medium of index one, summing every reflection inside the film (Airy)
+    pub fn reflectance(&self, cos_theta_i: f64, lambda: f64, substrate: &Substrate) -> f64 {
+        let cos_i = cos_theta_i.clamp(0.0, 1.0);
+        let sin2_i = Complex::new(1.0 - cos_i * cos_i, 0.0);
+        let one = Complex::new(1.0, 0.0);
+        let n1 = one;
+        let n2 = Complex::new(self.ior, 0.0);
+        let n3 = substrate.ior(lambda);
+        let c1 = Complex::new(cos_i, 0.0);
+        let c2 = (one - sin2_i / (n2 * n2)).sqrt();
+        let c3 = (one - sin2_i / (n3 * n3)).sqrt();
+
+        let r_perpendicular = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+            (na * ca - nb * cb) / (na * ca + nb * cb)
+        };
+        let r_parallel = |na: Complex, ca: Complex, nb: Complex, cb: Complex| {
+            (nb * ca - na * cb) / (nb * ca + na * cb)
+        };
+        // Round trip through the film, complex when the wave in it is evanescent
+        let phase = Complex::new(4.0 * PI * self.thickness / lambda, 0.0) * n2 * c2;
+        let shift = phase.exp_i();
+        let airy =
+            |r12: Complex, r23: Complex| ((r12 + r23 * shift) / (one + r12 * r23 * shift)).norm();
+
+        let perpendicular = airy(
+            r_perpendicular(n1, c1, n2, c2),
+            r_perpendicular(n2, c2, n3, c3),
+        );
+        let parallel = airy(r_parallel(n1, c1, n2, c2), r_parallel(n2, c2, n3, c3));
+        (0.5 * (perpendicular + parallel)).clamp(0.0, 1.0)
+    }
+
+    /// Reflectance as linear sRGB, integrated over the visible spectrum, for rendering
+    /// without wavelengths
+    pub fn reflectance_rgb(&self, cos_theta_i: f64, substrate: &Substrate) -> Color {
+        reflectance_to_rgb(|lambda| self.reflectance(cos_theta_i, lambda, substrate))
+    }
+
+    /// Reflectance at the wavelength of a spectral ray, as a gray color, or as RGB without one
+    pub fn reflectance_for(
+        &self,
+        cos_theta_i: f64,
+        lambda: Option<f64>,
+        substrate: &Substrate,
+    ) -> Color {
+        match lambda {
+            Some(lambda) => {
+                let r = self.reflectance(cos_theta_i, lambda, substrate);
+                Color::new(r, r, r)
+            }
+            None => self.reflectance_rgb(cos_theta_i, substrate),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Self {
+        Complex { re, im }
+    }
+
+    /// Squared magnitude
+    fn norm(&self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    /// e^(i self)
+    fn exp_i(&self) -> Self {
+        let magnitude = (-self.im).exp();
+        Complex::new(magnitude * self.re.cos(), magnitude * self.re.sin())
+    }
+
+    /// Principal square root
+    fn sqrt(&self) -> Self {
+        let magnitude = self.norm().sqrt();
+        let re = (0.5 * (magnitude + self.re)).max(0.0).sqrt();
+        let im = (0.5 * (magnitude - self.re)).max(0.0).sqrt();
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+}
+
+impl std::ops::Add for Complex {
+    type Output = Complex;
+    fn add(self, rhs: Complex) -> Complex {
+        Complex::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl std::ops::Sub for Complex {
+    type Output = Complex;
+    fn sub(self, rhs: Complex) -> Complex {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl std::ops::Mul for Complex {
+    type Output = Complex;
+    fn mul(self, rhs: Complex) -> Complex {
+        Complex::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl std::ops::Div for Complex {
+    type Output = Complex;
+    fn div(self, rhs: Complex) -> Complex {
+        let scale = 1.0 / rhs.norm();
+        Complex::new(
+            scale * (self.re * rhs.re + self.im * rhs.im),
+            scale * (self.im * rhs.re - self.re * rhs.im),
+        )
+    }
+}
@@ -0,0 +1,96 @@
+use super::aabb::{self, Aabb};
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::Material;
+use super::ray::Ray;
+use super::sphere::hit_sphere;
+use super::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Sphere whose center moves through keyframes, in a straight line between consecutive ones.
+/// Before the first keyframe and after the last the sphere holds still. Its bounding box
+/// covers the whole motion, so it can go in a BVH like any other object.
+///
+/// A moving sphere can't be sampled as a light, light sampling has no time to place it at.
+pub struct MovingSphere {
+    /// Times and centers, sorted by time
+    keyframes: Vec<(f64, Point3)>,
+    radius: f64,
+    mat: Arc<Box<dyn Material + Sync + Send>>,
+    bbox: Aabb,
+}
+
+impl MovingSphere {
+    /// Sphere going from center0 at time0 to center1 at time1
+    pub fn new(
+        center0: Point3,
+        center1: Point3,
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        m: Box<dyn Material + Sync + Send>,
+    ) -> Self {
+        MovingSphere::new_keyframed(vec![(time0, center0), (time1, center1)], radius, m)
+    }
+
+    ///
+    /// Sphere following a path of centers
+    /// * `keyframes` - Time and center pairs, in any order, at least one
+    pub fn new_keyframed(
+        mut keyframes: Vec<(f64, Point3)>,
+        radius: f64,
+        m: Box<dyn Material + Sync + Send>,
+    ) -> Self {
+        assert!(!keyframes.is_empty(), "a moving sphere needs a keyframe");
+        keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        // Motion is linear between keyframes, so the boxes at the keyframes enclose all of it
+        let rvec = Vec3::new(radius, radius, radius);
+        let bbox = keyframes.iter().fold(aabb::EMPTY, |bbox, (_, center)| {
+            Aabb::new_boxes(
+                &bbox,
+                &Aabb::new_points(&(*center - rvec), &(*center + rvec)),
+            )
+        });
+
+        MovingSphere {
+            keyframes,
+            radius,
+            mat: Arc::new(m),
+            bbox,
+        }
+    }
+
+    /// Center of the sphere at the given time
+    pub fn center(&self, time: f64) -> Point3 {
+        let next = self.keyframes.partition_point(|(t, _)| *t <= time);
+        if next == 0 {
+            return self.keyframes[0].1;
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes[next - 1].1;
+        }
+
+        let (time0, center0) = self.keyframes[next - 1];
+        let (time1, center1) = self.keyframes[next];
+        let s = (time - time0) / (time1 - time0);
+        center0 + (center1 - center0) * s
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        hit_sphere(
+            &self.center(r.time()),
+            self.radius,
+            &self.mat,
+            r,
+            ray_t,
+            rec,
+        )
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
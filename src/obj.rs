@@ -0,0 +1,684 @@
+use super::color::Color;
+use super::hittable_list::HittableList;
+use super::load_error::LoadError;
+use super::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use super::triangle::{MeshData, TriangleMesh};
+use super::vec3::{Point3, Vec3};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Material description from an MTL file
+#[derive(Debug, Clone)]
+pub struct MtlMaterial {
+    pub name: String,
+    /// Diffuse color
+    pub kd: Color,
+    /// Specular color
+    pub ks: Color,
+    /// Emissive color
+    pub ke: Color,
+    /// Specular exponent, 0 to 1000
+    pub ns: f64,
+    /// Index of refraction
+    pub ni: f64,
+    /// Opacity, 1 being fully opaque
+    pub d: f64,
+    pub illum: i32,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            name: String::new(),
+            kd: Color::new(0.8, 0.8, 0.8),
+            ks: Color::default(),
+            ke: Color::default(),
+            ns: 0.0,
+            ni: 1.0,
+            d: 1.0,
+            illum: 2,
+        }
+    }
+}
+
+impl MtlMaterial {
+    /// Pick the crate material closest to this description. Emissive materials become lights,
+    /// transparent ones become glass, materials dominated by their specular color become metal
+    /// and everything else is diffuse.
+    pub fn to_material(&self) -> Box<dyn Material + Sync + Send> {
+        let max_ks = self.ks.x().max(self.ks.y()).max(self.ks.z());
+        let max_kd = self.kd.x().max(self.kd.y()).max(self.kd.z());
+        let max_ke = self.ke.x().max(self.ke.y()).max(self.ke.z());
+
+        if max_ke > 0.0 {
+            Box::new(DiffuseLight::new(&self.ke))
+        } else if self.d < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            let ir = if self.ni > 1.0 { self.ni } else { 1.5 };
+            Box::new(Dielectric::new(ir))
+        } else if max_ks > 0.0 && (self.illum == 3 || max_ks >= max_kd) {
+            // Map the Phong exponent onto fuzz, 0 being a perfect mirror
+            let fuzz = (2.0 / (self.ns.max(0.0) + 2.0)).sqrt();
+            Box::new(Metal::new(&self.ks, fuzz))
+        } else {
+            Box::new(Lambertian::new(&self.kd))
+        }
+    }
+}
+
+/// One group of faces sharing a material
+pub struct ObjMesh {
+    pub name: String,
+    pub material: Option<String>,
+    pub mesh: TriangleMesh,
+}
+
+pub struct ObjModel {
+    pub meshes: Vec<ObjMesh>,
+    /// Problems the file could be loaded despite, such as a missing MTL library or an
+    /// undefined material replaced by the default one
+    pub warnings: Vec<String>,
+}
+
+impl ObjModel {
+    pub fn into_hittable_list(self) -> HittableList {
+        let mut list = HittableList::new();
+        for obj_mesh in self.meshes {
+            list.add(Box::new(obj_mesh.mesh));
+        }
+        list
+    }
+}
+
+/// Load an OBJ file along with the MTL libraries it references. Libraries that can't be
+/// read are skipped and reported in the model's warnings.
+pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<ObjModel, LoadError> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    let mtl_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    parse_obj(BufReader::new(file), &path.display().to_string(), mtl_dir)
+}
+
+pub fn load_mtl<P: AsRef<Path>>(path: P) -> Result<Vec<MtlMaterial>, LoadError> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    parse_mtl(BufReader::new(file), &path.display().to_string())
+}
+
+///
+/// Parse OBJ content. Polygons are triangulated as fans, and faces are split into one mesh
+/// per group and material.
+/// * `reader` - OBJ content
+/// * `file` - Name used in error messages
+/// * `mtl_dir` - Directory that `mtllib` paths are relative to
+pub fn parse_obj<R: BufRead>(reader: R, file: &str, mtl_dir: &Path) -> Result<ObjModel, LoadError> {
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut texcoords: Vec<(f64, f64)> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut mtl_materials: HashMap<String, MtlMaterial> = HashMap::new();
+
+    let mut builders: Vec<MeshBuilder> = Vec::new();
+    let mut builder_lookup: HashMap<(String, Option<String>), usize> = HashMap::new();
+    let mut group = String::from("default");
+    let mut material: Option<String> = None;
+    let mut warnings: Vec<String> = Vec::new();
+
+    for statement in statements(reader) {
+        let (line, text) = statement?;
+        let mut tokens = text.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                let v = parse_floats(&args, 3, 4, file, line)?;
+                positions.push(Point3::new(v[0], v[1], v[2]));
+            }
+            "vt" => {
+                let vt = parse_floats(&args, 1, 3, file, line)?;
+                texcoords.push((vt[0], vt.get(1).copied().unwrap_or(0.0)));
+            }
+            "vn" => {
+                let vn = parse_floats(&args, 3, 3, file, line)?;
+                normals.push(Vec3::new(vn[0], vn[1], vn[2]));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(LoadError::parse(
+                        file,
+                        line,
+                        format!("face needs at least 3 vertices, found {}", args.len()),
+                    ));
+                }
+
+                let key = (group.clone(), material.clone());
+                let builder_index = *builder_lookup.entry(key).or_insert_with(|| {
+                    builders.push(MeshBuilder::new(&group, material.clone()));
+                    builders.len() - 1
+                });
+                let builder = &mut builders[builder_index];
+
+                let mut face = Vec::with_capacity(args.len());
+                for arg in args.iter() {
+                    let vertex = parse_face_vertex(
+                        arg,
+                        (positions.len(), texcoords.len(), normals.len()),
+                        file,
+                        line,
+                    )?;
+                    face.push(builder.vertex(vertex, &positions, &texcoords, &normals));
+                }
+                for i in 1..face.len() - 1 {
+                    builder.indices.push([face[0], face[i], face[i + 1]]);
+                }
+            }
+            "g" | "o" => {
+                group = if args.is_empty() {
+                    String::from("default")
+                } else {
+                    args.join(" ")
+                };
+            }
+            "usemtl" => {
+                if args.is_empty() {
+                    return Err(LoadError::parse(file, line, "usemtl needs a material name"));
+                }
+                material = Some(args.join(" "));
+            }
+            "mtllib" => {
+                for lib in args.iter() {
+                    let lib_path = mtl_dir.join(lib);
+                    match load_mtl(&lib_path) {
+                        Ok(mtls) => {
+                            for mtl in mtls {
+                                mtl_materials.insert(mtl.name.clone(), mtl);
+                            }
+                        }
+                        Err(LoadError::Io(err)) => {
+                            warnings.push(format!(
+                                "{file}:{line}: skipping {}: {err}",
+                                lib_path.display()
+                            ));
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+            // Smoothing groups, lines, points, freeform geometry and display attributes are
+            // not rendered
+            _ => {}
+        }
+    }
+
+    let default_material: Arc<Box<dyn Material + Sync + Send>> =
+        Arc::new(MtlMaterial::default().to_material());
+    let mut shared_materials: HashMap<String, Arc<Box<dyn Material + Sync + Send>>> =
+        HashMap::new();
+
+    let meshes = builders
+        .into_iter()
+        .filter(|builder| !builder.indices.is_empty())
+        .map(|builder| {
+            let mat = match &builder.material {
+                Some(name) => match mtl_materials.get(name) {
+                    Some(mtl) => shared_materials
+                        .entry(name.clone())
+                        .or_insert_with(|| Arc::new(mtl.to_material()))
+                        .clone(),
+                    None => {
+                        warnings.push(format!(
+                            "{file}: material '{name}' is not defined, using default"
+                        ));
+                        default_material.clone()
+                    }
+                },
+                None => default_material.clone(),
+            };
+            builder.build(mat)
+        })
+        .collect();
+
+    Ok(ObjModel { meshes, warnings })
+}
+
+///
+/// Parse MTL content
+/// * `reader` - MTL content
+/// * `file` - Name used in error messages
+pub fn parse_mtl<R: BufRead>(reader: R, file: &str) -> Result<Vec<MtlMaterial>, LoadError> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for statement in statements(reader) {
+        let (line, text) = statement?;
+        let mut tokens = text.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if args.is_empty() {
+                return Err(LoadError::parse(file, line, "newmtl needs a material name"));
+            }
+            materials.push(MtlMaterial {
+                name: args.join(" "),
+                ..Default::default()
+            });
+            continue;
+        }
+
+        let current = match materials.last_mut() {
+            Some(current) => current,
+            None => {
+                return Err(LoadError::parse(
+                    file,
+                    line,
+                    format!("'{keyword}' before any newmtl"),
+                ))
+            }
+        };
+
+        match keyword {
+            "Kd" => current.kd = parse_color(&args, file, line)?,
+            "Ks" => current.ks = parse_color(&args, file, line)?,
+            "Ke" => current.ke = parse_color(&args, file, line)?,
+            "Ns" => current.ns = parse_floats(&args, 1, 1, file, line)?[0],
+            "Ni" => current.ni = parse_floats(&args, 1, 1, file, line)?[0],
+            "d" => current.d = parse_floats(&args, 1, 1, file, line)?[0],
+            "Tr" => current.d = 1.0 - parse_floats(&args, 1, 1, file, line)?[0],
+            "illum" => {
+                current.illum = args
+                    .first()
+                    .and_then(|a| a.parse::<i32>().ok())
+                    .ok_or_else(|| LoadError::parse(file, line, "illum needs an integer"))?
+            }
+            // Everything else (ambient, texture maps, transmission filter...) has no
+            // counterpart in the crate materials
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+/// Faces collected for one group and material, with vertices deduplicated on their
+/// position/texcoord/normal index triple
+struct MeshBuilder {
+    name: String,
+    material: Option<String>,
+    data: MeshData,
+    indices: Vec<[usize; 3]>,
+    vertex_lookup: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    missing_uvs: bool,
+    missing_normals: bool,
+}
+
+impl MeshBuilder {
+    fn new(name: &str, material: Option<String>) -> Self {
+        MeshBuilder {
+            name: name.to_owned(),
+            material,
+            data: MeshData::default(),
+            indices: Vec::new(),
+            vertex_lookup: HashMap::new(),
+            missing_uvs: false,
+            missing_normals: false,
+        }
+    }
+
+    fn vertex(
+        &mut self,
+        key: (usize, Option<usize>, Option<usize>),
+        positions: &[Point3],
+        texcoords: &[(f64, f64)],
+        normals: &[Vec3],
+    ) -> usize {
+        if let Some(&index) = self.vertex_lookup.get(&key) {
+            return index;
+        }
+
+        let (v, vt, vn) = key;
+        self.data.positions.push(positions[v]);
+        self.data
+            .uvs
+            .push(vt.map_or((0.0, 0.0), |vt| texcoords[vt]));
+        self.data
+            .normals
+            .push(vn.map_or(Vec3::default(), |vn| normals[vn]));
+        self.missing_uvs |= vt.is_none();
+        self.missing_normals |= vn.is_none();
+
+        let index = self.data.positions.len() - 1;
+        self.vertex_lookup.insert(key, index);
+        index
+    }
+
+    fn build(mut self, mat: Arc<Box<dyn Material + Sync + Send>>) -> ObjMesh {
+        // Per-vertex attributes are all or nothing for a mesh
+        if self.missing_uvs {
+            self.data.uvs.clear();
+        }
+        if self.missing_normals {
+            self.data.normals.clear();
+        }
+        self.data.indices = self.indices;
+
+        ObjMesh {
+            name: self.name,
+            material: self.material,
+            mesh: TriangleMesh::new_shared(Arc::new(self.data), mat),
+        }
+    }
+}
+
+/// Iterate over statements with comments stripped and `\` continuations joined, along with
+/// the line number each statement starts on
+fn statements<R: BufRead>(reader: R) -> impl Iterator<Item = Result<(usize, String), LoadError>> {
+    let mut lines = reader.lines().enumerate();
+    std::iter::from_fn(move || {
+        let mut statement = String::new();
+        let mut start_line = None;
+        loop {
+            let (index, line) = match lines.next() {
+                Some((index, Ok(line))) => (index, line),
+                Some((_, Err(err))) => return Some(Err(LoadError::Io(err))),
+                None => return start_line.map(|start| Ok((start, statement))),
+            };
+            start_line.get_or_insert(index + 1);
+
+            let line = match line.find('#') {
+                Some(pos) => &line[..pos],
+                None => &line[..],
+            };
+            match line.trim_end().strip_suffix('\\') {
+                Some(continued) => {
+                    statement.push_str(continued);
+                    statement.push(' ');
+                }
+                None => {
+                    statement.push_str(line);
+                    return start_line.map(|start| Ok((start, statement)));
+                }
+            }
+        }
+    })
+}
+
+fn parse_floats(
+    args: &[&str],
+    min_count: usize,
+    max_count: usize,
+    file: &str,
+    line: usize,
+) -> Result<Vec<f64>, LoadError> {
+    if args.len() < min_count || args.len() > max_count {
+        let expected = if min_count == max_count {
+            format!("{min_count}")
+        } else {
+            format!("{min_count} to {max_count}")
+        };
+        return Err(LoadError::parse(
+            file,
+            line,
+            format!("expected {expected} numbers, found {}", args.len()),
+        ));
+    }
+
+    args.iter()
+        .map(|arg| {
+            arg.parse::<f64>()
+                .map_err(|_| LoadError::parse(file, line, format!("invalid number '{arg}'")))
+        })
+        .collect()
+}
+
+fn parse_color(args: &[&str], file: &str, line: usize) -> Result<Color, LoadError> {
+    if args.first() == Some(&"spectral") || args.first() == Some(&"xyz") {
+        return Err(LoadError::parse(
+            file,
+            line,
+            format!("unsupported color format '{}'", args[0]),
+        ));
+    }
+    // A single value means a grey
+    let c = parse_floats(args, 1, 3, file, line)?;
+    match c.len() {
+        3 => Ok(Color::new(c[0], c[1], c[2])),
+        1 => Ok(Color::new(c[0], c[0], c[0])),
+        _ => Err(LoadError::parse(
+            file,
+            line,
+            "expected 1 or 3 color components",
+        )),
+    }
+}
+
+/// Parse a `v`, `v/vt`, `v//vn` or `v/vt/vn` face vertex into zero-based indices
+fn parse_face_vertex(
+    arg: &str,
+    (position_count, texcoord_count, normal_count): (usize, usize, usize),
+    file: &str,
+    line: usize,
+) -> Result<(usize, Option<usize>, Option<usize>), LoadError> {
+    let mut parts = arg.split('/');
+    let v = match parts.next() {
+        Some(v) if !v.is_empty() => resolve_index(v, position_count, "vertex", file, line)?,
+        _ => {
+            return Err(LoadError::parse(
+                file,
+                line,
+                format!("invalid face vertex '{arg}'"),
+            ))
+        }
+    };
+    let vt = match parts.next() {
+        Some(vt) if !vt.is_empty() => Some(resolve_index(
+            vt,
+            texcoord_count,
+            "texture coordinate",
+            file,
+            line,
+        )?),
+        _ => None,
+    };
+    let vn = match parts.next() {
+        Some(vn) if !vn.is_empty() => Some(resolve_index(vn, normal_count, "normal", file, line)?),
+        _ => None,
+    };
+    if parts.next().is_some() {
+        return Err(LoadError::parse(
+            file,
+            line,
+            format!("invalid face vertex '{arg}'"),
+        ));
+    }
+    Ok((v, vt, vn))
+}
+
+/// Turn a 1-based index, or a negative index counting back from the last element, into a
+/// zero-based one
+fn resolve_index(
+    token: &str,
+    count: usize,
+    what: &str,
+    file: &str,
+    line: usize,
+) -> Result<usize, LoadError> {
+    let index = token
+        .parse::<i64>()
+        .map_err(|_| LoadError::parse(file, line, format!("invalid {what} index '{token}'")))?;
+
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        count as i64 + index
+    };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(LoadError::parse(
+            file,
+            line,
+            format!("{what} index {index} out of range, {count} defined so far"),
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<ObjModel, LoadError> {
+        parse_obj(text.as_bytes(), "test.obj", Path::new("/nonexistent"))
+    }
+
+    fn error_line(err: LoadError) -> usize {
+        match err {
+            LoadError::Parse { line, .. } => line,
+            other => panic!("expected a parse error, got {other}"),
+        }
+    }
+
+    #[test]
+    fn faces_are_split_by_group_and_material() {
+        let model = parse(
+            "# a quad and a triangle\n\
+             v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             g floor\n\
+             f 1/1 2/2 3/3 4/4\n\
+             usemtl red\n\
+             f -4/-4 -3/-3 \\\n  -1/-1\n\
+             g wall\n\
+             f 1 2 3\n",
+        )
+        .unwrap();
+
+        let summary: Vec<(&str, Option<&str>, usize)> = model
+            .meshes
+            .iter()
+            .map(|m| {
+                (
+                    m.name.as_str(),
+                    m.material.as_deref(),
+                    m.mesh.triangle_count(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("floor", None, 2),
+                ("floor", Some("red"), 1),
+                ("wall", Some("red"), 1)
+            ]
+        );
+
+        // The quad becomes a fan sharing its four vertices, with their texture coordinates
+        let quad = model.meshes[0].mesh.data();
+        assert_eq!(quad.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(quad.positions.len(), 4);
+        assert_eq!(quad.uvs[2], (1.0, 1.0));
+        assert!(quad.normals.is_empty());
+
+        // Negative indices count back from the latest vertex
+        let triangle = model.meshes[1].mesh.data();
+        assert_eq!(triangle.positions[2].y(), 1.0);
+        assert_eq!(triangle.positions[2].x(), 0.0);
+
+        // Faces without texture coordinates drop them for the whole mesh
+        assert!(model.meshes[2].mesh.data().uvs.is_empty());
+    }
+
+    #[test]
+    fn normals_are_kept_per_vertex() {
+        let model = parse("v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\n").unwrap();
+        let data = model.meshes[0].mesh.data();
+        assert_eq!(data.normals.len(), 3);
+        assert_eq!(data.normals[1].z(), 1.0);
+    }
+
+    #[test]
+    fn missing_materials_are_warnings() {
+        let model =
+            parse("mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl gold\nf 1 2 3\n").unwrap();
+        assert_eq!(model.meshes.len(), 1);
+        assert_eq!(model.warnings.len(), 2);
+        assert!(model.warnings[0].starts_with("test.obj:1: skipping"));
+        assert!(model.warnings[1].contains("'gold' is not defined"));
+    }
+
+    #[test]
+    fn errors_point_at_the_line() {
+        let bad_index = "v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 1 2 4\n";
+        assert_eq!(error_line(parse(bad_index).err().unwrap()), 5);
+        assert_eq!(error_line(parse("v 0 0 0\nf 0 1 1\n").err().unwrap()), 2);
+        assert_eq!(error_line(parse("v 0 0 0\nf 1 1\n").err().unwrap()), 2);
+        assert_eq!(error_line(parse("v 0 zero 0\n").err().unwrap()), 1);
+        assert_eq!(error_line(parse("v 0 0 0\nf 1/1 1 1\n").err().unwrap()), 2);
+        assert_eq!(
+            error_line(parse("v 0 0 0\nf 1/1/1/1 1 1\n").err().unwrap()),
+            2
+        );
+    }
+
+    #[test]
+    fn mtl_values() {
+        let materials = parse_mtl(
+            "newmtl glass\n\
+             Kd 0.1 0.2 0.3\n\
+             Ks 0.5\n\
+             Ni 1.45 # crown\n\
+             Tr 0.75\n\
+             illum 7\n\
+             map_Kd glass.png\n\
+             newmtl lamp\n\
+             Ke 4 4 4\n"
+                .as_bytes(),
+            "test.mtl",
+        )
+        .unwrap();
+
+        assert_eq!(materials.len(), 2);
+        let glass = &materials[0];
+        assert_eq!(glass.name, "glass");
+        assert_eq!(glass.kd.z(), 0.3);
+        assert_eq!(glass.ks.y(), 0.5);
+        assert_eq!(glass.ni, 1.45);
+        assert_eq!(glass.d, 0.25);
+        assert_eq!(glass.illum, 7);
+        assert_eq!(materials[1].ke.x(), 4.0);
+        assert_eq!(materials[1].d, 1.0);
+
+        let orphan = parse_mtl("Kd 1 1 1\n".as_bytes(), "test.mtl");
+        assert_eq!(error_line(orphan.err().unwrap()), 1);
+        let spectral = parse_mtl("newmtl a\nKd spectral a.spd\n".as_bytes(), "test.mtl");
+        assert_eq!(error_line(spectral.err().unwrap()), 2);
+    }
+
+    #[test]
+    fn mtl_libraries_load_next_to_the_obj() {
+        let dir = std::env::temp_dir().join(format!("obj_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("scene.mtl"),
+            "newmtl gold\nKs 1 0.8 0.3\nillum 3\n",
+        )
+        .unwrap();
+        let model = parse_obj(
+            "mtllib scene.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl gold\nf 1 2 3\n".as_bytes(),
+            "scene.obj",
+            &dir,
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let model = model.unwrap();
+        assert!(model.warnings.is_empty());
+        assert_eq!(model.meshes[0].material.as_deref(), Some("gold"));
+    }
+}
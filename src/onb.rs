@@ -0,0 +1,37 @@
+use super::vec3::Vec3;
+
+/// Orthonormal basis, w being the axis it was built around
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn new_from_w(n: &Vec3) -> Self {
+        let w = Vec3::unit_vector(n);
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = Vec3::unit_vector(&Vec3::cross(&w, &a));
+        let u = Vec3::cross(&w, &v);
+        Onb { u, v, w }
+    }
+
+    /// Vector given in basis coordinates, expressed in world coordinates
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        (self.u * a.x()) + (self.v * a.y()) + (self.w * a.z())
+    }
+
+    /// World vector expressed in basis coordinates
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(
+            Vec3::dot(a, &self.u),
+            Vec3::dot(a, &self.v),
+            Vec3::dot(a, &self.w),
+        )
+    }
+}
@@ -0,0 +1,117 @@
+use super::image::{Channel, Image};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub fn save_pfm<P: AsRef<Path>>(image: &Image, path: P) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_pfm(image, &mut out)
+}
+
+/// Write the unclamped linear pixels as a little endian 32-bit float RGB PFM
+pub fn write_pfm<W: Write>(image: &Image, out: &mut W) -> io::Result<()> {
+    let channels = image.to_channels("");
+    write_pfm_channels(image.width(), image.height(), &channels, out)
+}
+
+///
+/// Write one channel as a greyscale PFM, or three channels as an RGB PFM. PFM has no channel
+/// names, use OpenEXR to keep more buffers in one file.
+/// * `channels` - Planes of width * height values, stored top row first
+pub fn write_pfm_channels<W: Write>(
+    width: usize,
+    height: usize,
+    channels: &[Channel],
+    out: &mut W,
+) -> io::Result<()> {
+    let magic = match channels.len() {
+        1 => "Pf",
+        3 => "PF",
+        n => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("PFM holds 1 or 3 channels, got {n}"),
+            ))
+        }
+    };
+    if channels.iter().any(|c| c.data.len() != width * height) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "channel size does not match the image size",
+        ));
+    }
+
+    // A negative scale marks little endian data
+    write!(out, "{magic}\n{width} {height}\n-1.0\n")?;
+
+    // Rows are stored from the bottom up
+    let mut row = Vec::with_capacity(width * channels.len() * 4);
+    for y in (0..height).rev() {
+        row.clear();
+        for x in 0..width {
+            for channel in channels {
+                row.extend_from_slice(&channel.data[y * width + x].to_le_bytes());
+            }
+        }
+        out.write_all(&row)?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    fn floats(data: &[u8]) -> Vec<f32> {
+        data.chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn rows_are_stored_bottom_up() {
+        let mut image = Image::new(2, 3);
+        for y in 0..3 {
+            for x in 0..2 {
+                let v = (10 * y + x) as f64;
+                image.set_pixel(x, y, Color::new(v, v + 0.25, -v), 1);
+            }
+        }
+        let mut file = Vec::new();
+        write_pfm(&image, &mut file).unwrap();
+
+        let header = b"PF\n2 3\n-1.0\n";
+        assert_eq!(&file[..header.len()], header);
+        let rows = [
+            [20.0, 20.25, -20.0, 21.0, 21.25, -21.0],
+            [10.0, 10.25, -10.0, 11.0, 11.25, -11.0],
+            [0.0, 0.25, -0.0, 1.0, 1.25, -1.0],
+        ];
+        assert_eq!(floats(&file[header.len()..]), rows.concat());
+    }
+
+    #[test]
+    fn single_channels_are_greyscale() {
+        let channel = Channel {
+            name: String::from("Z"),
+            data: vec![1.0, 2.0, 3.0, 4.0],
+        };
+        let mut file = Vec::new();
+        write_pfm_channels(2, 2, &[channel], &mut file).unwrap();
+        let header = b"Pf\n2 2\n-1.0\n";
+        assert_eq!(&file[..header.len()], header);
+        assert_eq!(floats(&file[header.len()..]), [3.0, 4.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn rejects_other_channel_counts() {
+        let channel = || Channel {
+            name: String::from("Y"),
+            data: vec![0.0],
+        };
+        let mut out = Vec::new();
+        assert!(write_pfm_channels(1, 1, &[channel(), channel()], &mut out).is_err());
+        assert!(write_pfm_channels(2, 1, &[channel()], &mut out).is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use super::aabb::{self, Aabb};
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::Material;
+use super::onb::Onb;
+use super::ray::Ray;
+use super::triangle::BBOX_PADDING;
+use super::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Infinite plane through point, facing along normal. u and v are distances from point
+/// along two directions in the plane, so textures repeat every world unit.
+///
+/// The bounding box is unbounded, so planes are best added next to a `BvhNode` or
+/// `FlatBvh` rather than inside one.
+pub struct Plane {
+    point: Point3,
+    mat: Arc<Box<dyn Material + Sync + Send>>,
+    frame: Onb,
+    bbox: Aabb,
+}
+
+impl Plane {
+    pub fn new(point: Point3, normal: Vec3, m: Box<dyn Material + Sync + Send>) -> Self {
+        let frame = Onb::new_from_w(&normal);
+
+        // Planes facing along an axis are only infinite in the other two
+        let w = frame.w;
+        let thin_axis = (0..3).find(|&axis| w.axis(axis).abs() == 1.0);
+        let bbox = match thin_axis {
+            Some(axis) => {
+                let slab =
+                    Interval::new_val(point.axis(axis), point.axis(axis)).expand(BBOX_PADDING);
+                let mut axes = [aabb::UNIVERSE.x, aabb::UNIVERSE.y, aabb::UNIVERSE.z];
+                axes[axis] = slab;
+                Aabb::new(axes[0], axes[1], axes[2])
+            }
+            None => aabb::UNIVERSE,
+        };
+
+        Plane {
+            point,
+            mat: Arc::new(m),
+            frame,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let denom = Vec3::dot(&self.frame.w, &r.direction());
+        if denom.abs() < 1e-8 {
+            return false;
+        }
+
+        let t = Vec3::dot(&self.frame.w, &(self.point - r.origin())) / denom;
+        if !ray_t.surrounds(t) {
+            return false;
+        }
+
+        rec.t = t;
+        rec.p = r.at(t);
+        let local = self.frame.to_local(&(rec.p - self.point));
+        rec.u = local.x();
+        rec.v = local.y();
+        rec.mat = self.mat.clone();
+        rec.vertex_color = None;
+        rec.set_face_normal(r, &self.frame.w);
+        rec.set_derivatives(&self.frame.u, &self.frame.v);
+
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
@@ -0,0 +1,561 @@
+use super::byte_reader::ByteReader;
+use super::color::Color;
+use super::load_error::LoadError;
+use super::material::Material;
+use super::triangle::{MeshData, TriangleMesh};
+use super::vec3::{Point3, Vec3};
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "char" | "int8" => Some(ScalarType::Int8),
+            "uchar" | "uint8" => Some(ScalarType::UInt8),
+            "short" | "int16" => Some(ScalarType::Int16),
+            "ushort" | "uint16" => Some(ScalarType::UInt16),
+            "int" | "int32" => Some(ScalarType::Int32),
+            "uint" | "uint32" => Some(ScalarType::UInt32),
+            "float" | "float32" => Some(ScalarType::Float32),
+            "double" | "float64" => Some(ScalarType::Float64),
+            _ => None,
+        }
+    }
+
+    /// Scale bringing a color stored with this type into [0, 1]
+    fn color_scale(&self) -> f64 {
+        match self {
+            ScalarType::UInt8 => 1.0 / 255.0,
+            ScalarType::UInt16 => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar {
+        name: String,
+        ty: ScalarType,
+    },
+    List {
+        name: String,
+        count_ty: ScalarType,
+        item_ty: ScalarType,
+    },
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar { name, .. } | Property::List { name, .. } => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+/// Where property values come from, either whitespace separated text or packed binary
+trait ValueSource {
+    fn scalar(&mut self, ty: ScalarType) -> Result<f64, LoadError>;
+    fn error(&self, message: String) -> LoadError;
+}
+
+struct AsciiSource<'a, I: Iterator<Item = (usize, &'a str)>> {
+    file: &'a str,
+    tokens: I,
+    line: usize,
+}
+
+impl<'a, I: Iterator<Item = (usize, &'a str)>> ValueSource for AsciiSource<'a, I> {
+    fn scalar(&mut self, ty: ScalarType) -> Result<f64, LoadError> {
+        let (line, token) = match self.tokens.next() {
+            Some(next) => next,
+            None => {
+                return Err(LoadError::parse(
+                    self.file,
+                    self.line,
+                    "unexpected end of file",
+                ))
+            }
+        };
+        self.line = line;
+
+        let value = match ty {
+            ScalarType::Float32 | ScalarType::Float64 => token.parse::<f64>().ok(),
+            _ => token.parse::<i64>().ok().map(|v| v as f64),
+        };
+        value.ok_or_else(|| LoadError::parse(self.file, line, format!("invalid number '{token}'")))
+    }
+
+    fn error(&self, message: String) -> LoadError {
+        LoadError::parse(self.file, self.line, message)
+    }
+}
+
+impl ValueSource for ByteReader<'_> {
+    fn scalar(&mut self, ty: ScalarType) -> Result<f64, LoadError> {
+        Ok(match ty {
+            ScalarType::Int8 => self.i8()? as f64,
+            ScalarType::UInt8 => self.u8()? as f64,
+            ScalarType::Int16 => self.i16()? as f64,
+            ScalarType::UInt16 => self.u16()? as f64,
+            ScalarType::Int32 => self.i32()? as f64,
+            ScalarType::UInt32 => self.u32()? as f64,
+            ScalarType::Float32 => self.f32()? as f64,
+            ScalarType::Float64 => self.f64()?,
+        })
+    }
+
+    fn error(&self, message: String) -> LoadError {
+        ByteReader::error(self, message)
+    }
+}
+
+/// Load an ASCII or binary PLY file as a triangle mesh using the material m. Vertex normals,
+/// texture coordinates and colors are kept when the file has them, the colors can be
+/// rendered with `VertexColor`.
+pub fn load_ply<P: AsRef<Path>>(
+    path: P,
+    m: Box<dyn Material + Sync + Send>,
+) -> Result<TriangleMesh, LoadError> {
+    let path = path.as_ref();
+    let data = fs::read(path)?;
+    parse_ply(&data, &path.display().to_string(), m)
+}
+
+pub fn parse_ply(
+    data: &[u8],
+    file: &str,
+    m: Box<dyn Material + Sync + Send>,
+) -> Result<TriangleMesh, LoadError> {
+    let (format, elements, header_end, header_lines) = parse_header(data, file)?;
+
+    let mesh = match format {
+        Format::Ascii => {
+            let text = std::str::from_utf8(&data[header_end..])
+                .map_err(|_| LoadError::data(file, header_end, "ASCII PLY is not valid text"))?;
+            let tokens = text.lines().enumerate().flat_map(move |(index, line)| {
+                line.split_whitespace()
+                    .map(move |token| (header_lines + index + 1, token))
+            });
+            let mut source = AsciiSource {
+                file,
+                tokens,
+                line: header_lines,
+            };
+            read_elements(&elements, &mut source)?
+        }
+        Format::BinaryLittleEndian | Format::BinaryBigEndian => {
+            let mut source =
+                ByteReader::new(file, data, header_end, format == Format::BinaryBigEndian);
+            read_elements(&elements, &mut source)?
+        }
+    };
+
+    Ok(TriangleMesh::new(mesh, m))
+}
+
+/// Parse the text header, returning the format, element layout, byte offset of the body and
+/// the number of header lines
+fn parse_header(
+    data: &[u8],
+    file: &str,
+) -> Result<(Format, Vec<Element>, usize, usize), LoadError> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut pos = 0;
+    let mut line_number = 0;
+
+    loop {
+        let line_end = match data[pos..].iter().position(|&b| b == b'\n') {
+            Some(end) => pos + end,
+            None => {
+                return Err(LoadError::parse(
+                    file,
+                    line_number + 1,
+                    "missing end_header",
+                ))
+            }
+        };
+        line_number += 1;
+        let line = String::from_utf8_lossy(&data[pos..line_end]);
+        pos = line_end + 1;
+
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if line_number == 1 {
+            if tokens != ["ply"] {
+                return Err(LoadError::parse(file, 1, "not a PLY file"));
+            }
+            continue;
+        }
+
+        match tokens.first().copied() {
+            Some("format") => {
+                format = Some(match tokens.get(1).copied() {
+                    Some("ascii") => Format::Ascii,
+                    Some("binary_little_endian") => Format::BinaryLittleEndian,
+                    Some("binary_big_endian") => Format::BinaryBigEndian,
+                    _ => {
+                        return Err(LoadError::parse(
+                            file,
+                            line_number,
+                            format!("unknown format '{}'", tokens[1..].join(" ")),
+                        ))
+                    }
+                });
+            }
+            Some("element") => match (tokens.get(1), tokens.get(2).and_then(|c| c.parse().ok())) {
+                (Some(name), Some(count)) if tokens.len() == 3 => elements.push(Element {
+                    name: name.to_string(),
+                    count,
+                    properties: Vec::new(),
+                }),
+                _ => return Err(LoadError::parse(file, line_number, "invalid element")),
+            },
+            Some("property") => {
+                let element = elements.last_mut().ok_or_else(|| {
+                    LoadError::parse(file, line_number, "property before any element")
+                })?;
+                let invalid = || LoadError::parse(file, line_number, "invalid property");
+                let property = if tokens.get(1) == Some(&"list") {
+                    if tokens.len() != 5 {
+                        return Err(invalid());
+                    }
+                    Property::List {
+                        count_ty: ScalarType::from_name(tokens[2]).ok_or_else(invalid)?,
+                        item_ty: ScalarType::from_name(tokens[3]).ok_or_else(invalid)?,
+                        name: tokens[4].to_owned(),
+                    }
+                } else {
+                    if tokens.len() != 3 {
+                        return Err(invalid());
+                    }
+                    Property::Scalar {
+                        ty: ScalarType::from_name(tokens[1]).ok_or_else(invalid)?,
+                        name: tokens[2].to_owned(),
+                    }
+                };
+                element.properties.push(property);
+            }
+            Some("end_header") => break,
+            Some("comment") | Some("obj_info") | None => {}
+            Some(keyword) => {
+                return Err(LoadError::parse(
+                    file,
+                    line_number,
+                    format!("unknown header keyword '{keyword}'"),
+                ))
+            }
+        }
+    }
+
+    let format =
+        format.ok_or_else(|| LoadError::parse(file, line_number, "header has no format line"))?;
+    Ok((format, elements, pos, line_number))
+}
+
+fn read_elements(
+    elements: &[Element],
+    source: &mut dyn ValueSource,
+) -> Result<MeshData, LoadError> {
+    let mut mesh = MeshData::default();
+
+    for element in elements {
+        match element.name.as_str() {
+            "vertex" => read_vertices(element, source, &mut mesh)?,
+            "face" => read_faces(element, source, &mut mesh)?,
+            // Edges, materials and anything else are read past and dropped
+            _ => {
+                for _ in 0..element.count {
+                    for property in element.properties.iter() {
+                        read_property(property, source)?;
+                    }
+                }
+            }
+        }
+    }
+
+    for tri in mesh.indices.iter() {
+        if let Some(&i) = tri.iter().find(|&&i| i >= mesh.positions.len()) {
+            return Err(source.error(format!(
+                "face refers to vertex {i} but only {} are defined",
+                mesh.positions.len()
+            )));
+        }
+    }
+    Ok(mesh)
+}
+
+fn read_property(property: &Property, source: &mut dyn ValueSource) -> Result<Vec<f64>, LoadError> {
+    match property {
+        Property::Scalar { ty, .. } => Ok(vec![source.scalar(*ty)?]),
+        Property::List {
+            count_ty, item_ty, ..
+        } => {
+            let count = source.scalar(*count_ty)?;
+            if count < 0.0 {
+                return Err(source.error(format!("negative list length {count}")));
+            }
+            (0..count as usize)
+                .map(|_| source.scalar(*item_ty))
+                .collect()
+        }
+    }
+}
+
+fn read_vertices(
+    element: &Element,
+    source: &mut dyn ValueSource,
+    mesh: &mut MeshData,
+) -> Result<(), LoadError> {
+    let find = |names: &[&str]| {
+        element
+            .properties
+            .iter()
+            .position(|p| names.contains(&p.name()))
+    };
+    let find_all =
+        |names: &[&[&str]]| -> Option<Vec<usize>> { names.iter().map(|n| find(n)).collect() };
+
+    let position = find_all(&[&["x"], &["y"], &["z"]])
+        .ok_or_else(|| source.error(String::from("vertex element needs x, y and z properties")))?;
+    let normal = find_all(&[&["nx"], &["ny"], &["nz"]]);
+    let uv = find_all(&[
+        &["u", "s", "texture_u", "texture_s"],
+        &["v", "t", "texture_v", "texture_t"],
+    ]);
+    let color = find_all(&[
+        &["red", "diffuse_red"],
+        &["green", "diffuse_green"],
+        &["blue", "diffuse_blue"],
+    ]);
+    let color_scale: Vec<f64> = color
+        .iter()
+        .flatten()
+        .map(|&i| match &element.properties[i] {
+            Property::Scalar { ty, .. } => ty.color_scale(),
+            Property::List { .. } => 1.0,
+        })
+        .collect();
+
+    let mut values = vec![0.0; element.properties.len()];
+    for _ in 0..element.count {
+        for (value, property) in values.iter_mut().zip(element.properties.iter()) {
+            *value = read_property(property, source)?
+                .first()
+                .copied()
+                .unwrap_or(0.0);
+        }
+
+        mesh.positions.push(Point3::new(
+            values[position[0]],
+            values[position[1]],
+            values[position[2]],
+        ));
+        if let Some(n) = &normal {
+            mesh.normals
+                .push(Vec3::new(values[n[0]], values[n[1]], values[n[2]]));
+        }
+        if let Some(t) = &uv {
+            mesh.uvs.push((values[t[0]], values[t[1]]));
+        }
+        if let Some(c) = &color {
+            mesh.colors.push(Color::new(
+                values[c[0]] * color_scale[0],
+                values[c[1]] * color_scale[1],
+                values[c[2]] * color_scale[2],
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn read_faces(
+    element: &Element,
+    source: &mut dyn ValueSource,
+    mesh: &mut MeshData,
+) -> Result<(), LoadError> {
+    let indices_property = element
+        .properties
+        .iter()
+        .position(|p| {
+            matches!(p, Property::List { .. })
+                && (p.name() == "vertex_indices" || p.name() == "vertex_index")
+        })
+        .ok_or_else(|| source.error(String::from("face element needs a vertex_indices list")))?;
+
+    for _ in 0..element.count {
+        for (i, property) in element.properties.iter().enumerate() {
+            let values = read_property(property, source)?;
+            if i != indices_property {
+                continue;
+            }
+            if values.len() < 3 {
+                return Err(source.error(format!(
+                    "face needs at least 3 vertices, found {}",
+                    values.len()
+                )));
+            }
+            if let Some(v) = values.iter().find(|&&v| v < 0.0) {
+                return Err(source.error(format!("negative vertex index {v}")));
+            }
+
+            // Triangulate polygons as fans
+            for j in 1..values.len() - 1 {
+                mesh.indices.push([
+                    values[0] as usize,
+                    values[j] as usize,
+                    values[j + 1] as usize,
+                ]);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    const HEADER: &str = "comment a unit square\n\
+        element vertex 4\n\
+        property float x\n\
+        property float y\n\
+        property float z\n\
+        property float nx\n\
+        property float ny\n\
+        property float nz\n\
+        property float s\n\
+        property float t\n\
+        property uchar red\n\
+        property uchar green\n\
+        property uchar blue\n\
+        element face 1\n\
+        property uchar flags\n\
+        property list uchar int vertex_indices\n\
+        element edge 1\n\
+        property int vertex1\n\
+        property int vertex2\n\
+        end_header\n";
+
+    /// x, y, z, nx, ny, nz, s, t, then red, green and blue
+    const VERTICES: [[f64; 11]; 4] = [
+        [0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 255.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 255.0, 0.0],
+        [1.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0, 0.0, 255.0],
+        [0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 51.0, 51.0, 51.0],
+    ];
+
+    fn parse(data: &[u8]) -> Result<MeshData, LoadError> {
+        let m = Box::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5)));
+        parse_ply(data, "test.ply", m).map(|mesh| (**mesh.data()).clone())
+    }
+
+    fn ascii() -> Vec<u8> {
+        let mut text = format!("ply\nformat ascii 1.0\n{HEADER}");
+        for v in VERTICES {
+            let values: Vec<String> = v.iter().map(|x| x.to_string()).collect();
+            text += &(values.join(" ") + "\n");
+        }
+        text += "7 4 0 1 2 3\n0 1\n";
+        text.into_bytes()
+    }
+
+    fn binary(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian {
+            "binary_big_endian"
+        } else {
+            "binary_little_endian"
+        };
+        let mut data = format!("ply\nformat {format} 1.0\n{HEADER}").into_bytes();
+        let f32_bytes = |v: f32| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+        let i32_bytes = |v: i32| {
+            if big_endian {
+                v.to_be_bytes()
+            } else {
+                v.to_le_bytes()
+            }
+        };
+        for v in VERTICES {
+            for x in &v[..8] {
+                data.extend_from_slice(&f32_bytes(*x as f32));
+            }
+            data.extend(v[8..].iter().map(|&c| c as u8));
+        }
+        data.extend_from_slice(&[7, 4]);
+        for i in 0..4 {
+            data.extend_from_slice(&i32_bytes(i));
+        }
+        data.extend_from_slice(&i32_bytes(0));
+        data.extend_from_slice(&i32_bytes(1));
+        data
+    }
+
+    #[test]
+    fn every_format_reads_the_same_mesh() {
+        for data in [ascii(), binary(false), binary(true)] {
+            let mesh = parse(&data).unwrap();
+            assert_eq!(mesh.positions.len(), 4);
+            assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+            assert_eq!(mesh.positions[2].x(), 1.0);
+            assert_eq!(mesh.positions[2].y(), 1.0);
+            assert_eq!(mesh.normals[3].z(), 1.0);
+            assert_eq!(mesh.uvs[1], (1.0, 0.0));
+            assert_eq!(mesh.colors[1].y(), 1.0);
+            assert!((mesh.colors[3].x() - 0.2).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let missing_end = b"ply\nformat ascii 1.0\nelement vertex 0\n";
+        match parse(missing_end).err().unwrap() {
+            LoadError::Parse { line, .. } => assert_eq!(line, 4),
+            other => panic!("unexpected error {other}"),
+        }
+        assert!(parse(b"plx\nformat ascii 1.0\nend_header\n").is_err());
+        assert!(parse(b"ply\nformat binary_middle_endian 1.0\nend_header\n").is_err());
+
+        // Out of range indices, and binary data cut short
+        let mut bad_index = ascii();
+        let at = bad_index.len() - "7 4 0 1 2 3\n0 1\n".len();
+        bad_index.truncate(at);
+        bad_index.extend_from_slice(b"7 3 0 1 9\n0 1\n");
+        assert!(parse(&bad_index).is_err());
+        let mut short = binary(false);
+        short.truncate(short.len() - 10);
+        assert!(matches!(parse(&short), Err(LoadError::Data { .. })));
+    }
+}
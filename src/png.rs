@@ -0,0 +1,330 @@
+use super::color::linear_to_srgb;
+use super::image::Image;
+use super::rtweekend::random_double;
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+const COMPRESSION_LEVEL: u8 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PngOptions {
+    pub bit_depth: BitDepth,
+    /// Add triangular noise of one quantization step before rounding, which trades banding in
+    /// smooth gradients for fine grain
+    pub dither: bool,
+}
+
+impl Default for PngOptions {
+    fn default() -> Self {
+        PngOptions {
+            bit_depth: BitDepth::Eight,
+            dither: true,
+        }
+    }
+}
+
+pub fn save_png<P: AsRef<Path>>(image: &Image, path: P, options: PngOptions) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_png(image, &mut out, options)
+}
+
+/// Write the image as an sRGB encoded RGB PNG
+pub fn write_png<W: Write>(image: &Image, out: &mut W, options: PngOptions) -> io::Result<()> {
+    let width = image.width() as u32;
+    let height = image.height() as u32;
+    let (depth, max_value) = match options.bit_depth {
+        BitDepth::Eight => (8u8, 255.0),
+        BitDepth::Sixteen => (16u8, 65535.0),
+    };
+
+    out.write_all(&SIGNATURE)?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // Truecolor, deflate, adaptive filtering, no interlacing
+    ihdr.extend_from_slice(&[depth, 2, 0, 0, 0]);
+    write_chunk(out, b"IHDR", &ihdr)?;
+
+    // Pixels follow the sRGB transfer function, perceptual rendering intent
+    write_chunk(out, b"sRGB", &[0])?;
+
+    let bytes_per_pixel = 3 * (depth as usize) / 8;
+    let stride = bytes_per_pixel * image.width();
+    let mut raw = Vec::with_capacity((stride + 1) * image.height());
+    let mut previous = vec![0u8; stride];
+    let mut row = Vec::with_capacity(stride);
+
+    for y in 0..image.height() {
+        row.clear();
+        for x in 0..image.width() {
+            let pixel = image.pixel(x, y);
+            for component in [pixel.x(), pixel.y(), pixel.z()] {
+                let value = quantize(linear_to_srgb(component), max_value, options.dither);
+                match options.bit_depth {
+                    BitDepth::Eight => row.push(value as u8),
+                    BitDepth::Sixteen => row.extend_from_slice(&(value as u16).to_be_bytes()),
+                }
+            }
+        }
+        filter_row(&row, &previous, bytes_per_pixel, &mut raw);
+        std::mem::swap(&mut row, &mut previous);
+    }
+
+    write_chunk(out, b"IDAT", &compress_to_vec_zlib(&raw, COMPRESSION_LEVEL))?;
+    write_chunk(out, b"IEND", &[])?;
+    out.flush()
+}
+
+/// Map an encoded value in [0, 1] to an integer in [0, max_value]
+fn quantize(encoded: f64, max_value: f64, dither: bool) -> u32 {
+    let noise = if dither {
+        random_double() - random_double()
+    } else {
+        0.0
+    };
+    let value = (encoded * max_value + noise).round();
+    if value.is_nan() {
+        return 0;
+    }
+    value.clamp(0.0, max_value) as u32
+}
+
+/// Append the row with whichever PNG filter gives the smallest sum of absolute residuals, a
+/// cheap estimate of what will compress best
+fn filter_row(row: &[u8], previous: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    let left = |i: usize| if i >= bpp { row[i - bpp] } else { 0 };
+    let up_left = |i: usize| if i >= bpp { previous[i - bpp] } else { 0 };
+
+    let predictors: [&dyn Fn(usize) -> u8; 5] = [
+        &|_| 0,
+        &left,
+        &|i| previous[i],
+        &|i| ((left(i) as u16 + previous[i] as u16) / 2) as u8,
+        &|i| paeth(left(i), previous[i], up_left(i)),
+    ];
+
+    let mut best_filter = 0;
+    let mut best_score = u64::MAX;
+    for (filter, predictor) in predictors.iter().enumerate() {
+        let score: u64 = (0..row.len())
+            .map(|i| (row[i].wrapping_sub(predictor(i)) as i8).unsigned_abs() as u64)
+            .sum();
+        if score < best_score {
+            best_score = score;
+            best_filter = filter;
+        }
+    }
+
+    out.push(best_filter as u8);
+    let predictor = predictors[best_filter];
+    out.extend((0..row.len()).map(|i| row[i].wrapping_sub(predictor(i))));
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let crc = crc32(crc32(0xffff_ffff, kind), data) ^ 0xffff_ffff;
+    out.write_all(&crc.to_be_bytes())
+}
+
+/// Update a running CRC-32 (ISO 3309, as used by PNG) with data
+pub(crate) fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+    /// Chunks of a PNG as (kind, data), checking every CRC on the way
+    fn chunks(file: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(file[..8], SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut at = 8;
+        while at < file.len() {
+            let length = u32::from_be_bytes(file[at..at + 4].try_into().unwrap()) as usize;
+            let kind: [u8; 4] = file[at + 4..at + 8].try_into().unwrap();
+            let data = &file[at + 8..at + 8 + length];
+            let crc =
+                u32::from_be_bytes(file[at + 8 + length..at + 12 + length].try_into().unwrap());
+            assert_eq!(crc, crc32(crc32(0xffff_ffff, &kind), data) ^ 0xffff_ffff);
+            chunks.push((kind, data.to_vec()));
+            at += 12 + length;
+        }
+        chunks
+    }
+
+    /// Reverse the filter of every row, the way a decoder does
+    fn unfilter(filtered: &[u8], stride: usize, bpp: usize) -> Vec<u8> {
+        let mut rows = Vec::new();
+        let mut previous = vec![0u8; stride];
+        for line in filtered.chunks(stride + 1) {
+            let mut row = vec![0u8; stride];
+            for i in 0..stride {
+                let a = if i >= bpp { row[i - bpp] } else { 0 };
+                let b = previous[i];
+                let c = if i >= bpp { previous[i - bpp] } else { 0 };
+                let prediction = match line[0] {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    4 => paeth(a, b, c),
+                    other => panic!("unknown filter {other}"),
+                };
+                row[i] = line[i + 1].wrapping_add(prediction);
+            }
+            rows.extend_from_slice(&row);
+            previous = row;
+        }
+        rows
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc32(0xffff_ffff, b"123456789") ^ 0xffff_ffff, 0xcbf4_3926);
+        // Every PNG ends with the same empty IEND chunk
+        assert_eq!(crc32(0xffff_ffff, b"IEND") ^ 0xffff_ffff, 0xae42_6082);
+    }
+
+    #[test]
+    fn paeth_picks_the_closest_neighbour() {
+        assert_eq!(paeth(10, 20, 10), 20);
+        assert_eq!(paeth(20, 10, 10), 20);
+        assert_eq!(paeth(10, 10, 20), 10);
+        assert_eq!(paeth(100, 200, 150), 150);
+        // Ties go to a first, then to b
+        assert_eq!(paeth(3, 3, 5), 3);
+        assert_eq!(paeth(0, 9, 3), 9);
+    }
+
+    #[test]
+    fn filtered_rows_round_trip() {
+        let bpp = 3;
+        let stride = 3 * 17;
+        let mut rows = Vec::new();
+        let mut filtered = Vec::new();
+        let mut previous = vec![0u8; stride];
+        let mut used = [false; 5];
+        for y in 0..40 {
+            let row: Vec<u8> = (0..stride)
+                .map(|i| match y % 4 {
+                    0 => (random_double() * 256.0) as u8,
+                    1 => (i * 5) as u8,
+                    2 => previous[i].wrapping_add(3),
+                    _ => ((i / bpp) * 7 + y) as u8,
+                })
+                .collect();
+            filter_row(&row, &previous, bpp, &mut filtered);
+            used[filtered[filtered.len() - stride - 1] as usize] = true;
+            rows.extend_from_slice(&row);
+            previous = row;
+        }
+        assert_eq!(unfilter(&filtered, stride, bpp), rows);
+        assert!(used.iter().filter(|&&u| u).count() >= 3);
+    }
+
+    #[test]
+    fn pixels_survive_both_depths() {
+        let (width, height) = (7, 5);
+        let mut image = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let v = x as f64 / (width - 1) as f64;
+                let color = Color::new(v, 1.0 - v, y as f64 * 0.5 - 0.5);
+                image.set_pixel(x, y, color, 1);
+            }
+        }
+
+        for (bit_depth, max_value) in [(BitDepth::Eight, 255.0), (BitDepth::Sixteen, 65535.0)] {
+            let mut file = Vec::new();
+            let options = PngOptions {
+                bit_depth,
+                dither: false,
+            };
+            write_png(&image, &mut file, options).unwrap();
+
+            let chunks = chunks(&file);
+            let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+            assert_eq!(kinds, vec![b"IHDR", b"sRGB", b"IDAT", b"IEND"]);
+            let ihdr = &chunks[0].1;
+            assert_eq!(
+                u32::from_be_bytes(ihdr[0..4].try_into().unwrap()),
+                width as u32
+            );
+            assert_eq!(
+                u32::from_be_bytes(ihdr[4..8].try_into().unwrap()),
+                height as u32
+            );
+
+            let bpp = if bit_depth == BitDepth::Eight { 3 } else { 6 };
+            let raw = decompress_to_vec_zlib(&chunks[2].1).unwrap();
+            let pixels = unfilter(&raw, bpp * width, bpp);
+            let samples: Vec<u32> = if bit_depth == BitDepth::Eight {
+                pixels.iter().map(|&b| b as u32).collect()
+            } else {
+                pixels
+                    .chunks(2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]) as u32)
+                    .collect()
+            };
+
+            let expected: Vec<u32> = image
+                .pixels()
+                .iter()
+                .flat_map(|p| [p.x(), p.y(), p.z()])
+                .map(|c| (linear_to_srgb(c).clamp(0.0, 1.0) * max_value).round() as u32)
+                .collect();
+            assert_eq!(samples, expected);
+        }
+    }
+
+    #[test]
+    fn quantize_clamps() {
+        assert_eq!(quantize(f64::NAN, 255.0, false), 0);
+        assert_eq!(quantize(-0.5, 255.0, false), 0);
+        assert_eq!(quantize(2.0, 255.0, false), 255);
+        assert_eq!(quantize(0.5, 65535.0, false), 32768);
+        for _ in 0..100 {
+            let dithered = quantize(0.5, 255.0, true);
+            assert!((127..=129).contains(&dithered));
+        }
+    }
+}
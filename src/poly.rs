@@ -0,0 +1,230 @@
+//! Real roots of low degree polynomials, for the analytic primitives. Coefficients are
+//! given from the highest degree term down and roots are returned in no particular order.
+
+use super::rtweekend::PI;
+
+/// Values this close to zero are treated as zero
+const EPSILON: f64 = 1e-9;
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < EPSILON
+}
+
+/// Roots of a x^2 + b x + c, written to avoid the cancellation of the textbook formula.
+/// Degenerates to the linear equation when a is zero.
+pub(crate) fn solve_quadratic(a: f64, b: f64, c: f64) -> ([f64; 2], usize) {
+    if a == 0.0 {
+        if b == 0.0 {
+            return ([0.0; 2], 0);
+        }
+        return ([-c / b, 0.0], 1);
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return ([0.0; 2], 0);
+    }
+
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    if q == 0.0 {
+        // b and the discriminant are both zero, a double root at zero
+        return ([0.0, 0.0], 2);
+    }
+    ([q / a, c / q], 2)
+}
+
+/// Roots of x^3 + a x^2 + b x + c
+fn solve_normalized_cubic(a: f64, b: f64, c: f64) -> ([f64; 3], usize) {
+    // Substitute x = y - a/3 to eliminate the quadratic term: y^3 + 3 p y + 2 q = 0
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + c) / 2.0;
+
+    let cb_p = p * p * p;
+    let d = q * q + cb_p;
+
+    let mut roots = [0.0; 3];
+    let count = if is_zero(d) {
+        if is_zero(q) {
+            // One triple root
+            1
+        } else {
+            // One single and one double root
+            let u = (-q).cbrt();
+            roots[0] = 2.0 * u;
+            roots[1] = -u;
+            2
+        }
+    } else if d < 0.0 {
+        // Three real roots, found through the trigonometric form
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        roots[0] = t * phi.cos();
+        roots[1] = -t * (phi + PI / 3.0).cos();
+        roots[2] = -t * (phi - PI / 3.0).cos();
+        3
+    } else {
+        let sqrt_d = d.sqrt();
+        roots[0] = (sqrt_d - q).cbrt() - (sqrt_d + q).cbrt();
+        1
+    };
+
+    for root in roots.iter_mut().take(count) {
+        *root -= a / 3.0;
+    }
+    (roots, count)
+}
+
+/// Roots of a x^4 + b x^3 + c x^2 + d x + e, using Ferrari's method and a few Newton steps on
+/// the original polynomial to win back the precision the resolvent loses
+pub(crate) fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> ([f64; 4], usize) {
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+
+    // Substitute x = y - b/4 to eliminate the cubic term: y^4 + p y^2 + q y + r = 0
+    let sq_b = b * b;
+    let p = -3.0 / 8.0 * sq_b + c;
+    let q = sq_b * b / 8.0 - b * c / 2.0 + d;
+    let r = -3.0 / 256.0 * sq_b * sq_b + sq_b * c / 16.0 - b * d / 4.0 + e;
+
+    let mut roots = [0.0; 4];
+    let mut count = 0;
+    if is_zero(r) {
+        // No constant term: y (y^3 + p y + q) = 0
+        let (cubic, n) = solve_normalized_cubic(0.0, p, q);
+        roots[..n].copy_from_slice(&cubic[..n]);
+        roots[n] = 0.0;
+        count = n + 1;
+    } else {
+        // One real root of the resolvent cubic splits the quartic into two quadratics
+        let (cubic, _) = solve_normalized_cubic(-p / 2.0, -r, r * p / 2.0 - q * q / 8.0);
+        let z = cubic[0];
+
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if is_zero(u) {
+            0.0
+        } else if u > 0.0 {
+            u.sqrt()
+        } else {
+            return (roots, 0);
+        };
+        let v = if is_zero(v) {
+            0.0
+        } else if v > 0.0 {
+            v.sqrt()
+        } else {
+            return (roots, 0);
+        };
+
+        let v = if q < 0.0 { -v } else { v };
+        for (linear, constant) in [(v, z - u), (-v, z + u)] {
+            let (quadratic, n) = solve_quadratic(1.0, linear, constant);
+            roots[count..count + n].copy_from_slice(&quadratic[..n]);
+            count += n;
+        }
+    }
+
+    for root in roots.iter_mut().take(count) {
+        let mut x = *root - b / 4.0;
+        for _ in 0..2 {
+            let f = (((x + b) * x + c) * x + d) * x + e;
+            let df = ((4.0 * x + 3.0 * b) * x + 2.0 * c) * x + d;
+            if df == 0.0 {
+                break;
+            }
+            x -= f / df;
+        }
+        *root = x;
+    }
+    (roots, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Found roots, sorted, which must match expected to within tolerance
+    fn assert_roots(found: &[f64], expected: &[f64], tolerance: f64) {
+        let mut found = found.to_vec();
+        found.sort_by(f64::total_cmp);
+        assert_eq!(found.len(), expected.len(), "{found:?} vs {expected:?}");
+        for (a, b) in found.iter().zip(expected) {
+            assert!((a - b).abs() < tolerance, "{found:?} vs {expected:?}");
+        }
+    }
+
+    /// Coefficients of a (x - r0)(x - r1)(x - r2)(x - r3)
+    fn quartic_from_roots(a: f64, r: [f64; 4]) -> [f64; 5] {
+        let mut c = [1.0, 0.0, 0.0, 0.0, 0.0];
+        for (degree, root) in r.iter().enumerate() {
+            for i in (1..=degree + 1).rev() {
+                c[i] -= root * c[i - 1];
+            }
+        }
+        c.map(|x| x * a)
+    }
+
+    #[test]
+    fn quadratic_keeps_small_roots_accurate() {
+        let (roots, n) = solve_quadratic(1.0, -3.0, 2.0);
+        assert_roots(&roots[..n], &[1.0, 2.0], 1e-12);
+
+        // The textbook formula loses the small root to cancellation
+        let (roots, n) = solve_quadratic(1.0, -1e8, 1.0);
+        assert_roots(&roots[..n], &[1e-8, 1e8], 1e-20);
+
+        assert_eq!(solve_quadratic(1.0, 0.0, 1.0).1, 0);
+        let (roots, n) = solve_quadratic(0.0, 2.0, -4.0);
+        assert_roots(&roots[..n], &[2.0], 1e-12);
+    }
+
+    #[test]
+    fn cubic_root_counts() {
+        // (x - 1)(x - 2)(x - 3)
+        let (roots, n) = solve_normalized_cubic(-6.0, 11.0, -6.0);
+        assert_roots(&roots[..n], &[1.0, 2.0, 3.0], 1e-9);
+        // (x - 2)(x^2 + 1)
+        let (roots, n) = solve_normalized_cubic(-2.0, 1.0, -2.0);
+        assert_roots(&roots[..n], &[2.0], 1e-9);
+        // (x + 1)^2 (x - 2)
+        let (roots, n) = solve_normalized_cubic(0.0, -3.0, -2.0);
+        assert_roots(&roots[..n], &[-1.0, 2.0], 1e-9);
+        // (x - 1)^3
+        let (roots, n) = solve_normalized_cubic(-3.0, 3.0, -1.0);
+        assert_roots(&roots[..n], &[1.0], 1e-9);
+    }
+
+    #[test]
+    fn quartic_real_roots() {
+        for (a, expected) in [
+            (1.0, [-4.0, -1.0, 0.5, 3.0]),
+            (-2.5, [1.0, 2.0, 3.0, 4.0]),
+            (1.0, [-7.25, -0.125, 0.0, 11.0]),
+            (0.01, [0.3, 0.31, 5.0, 9.5]),
+        ] {
+            let c = quartic_from_roots(a, expected);
+            let (roots, n) = solve_quartic(c[0], c[1], c[2], c[3], c[4]);
+            assert_roots(&roots[..n], &expected, 1e-6);
+        }
+    }
+
+    #[test]
+    fn quartic_complex_pairs_are_dropped() {
+        // (x^2 + 1)(x - 2)(x + 5)
+        let (roots, n) = solve_quartic(1.0, 3.0, -9.0, 3.0, -10.0);
+        assert_roots(&roots[..n], &[-5.0, 2.0], 1e-9);
+        // (x^2 + 1)(x^2 + 4)
+        assert_eq!(solve_quartic(1.0, 0.0, 5.0, 0.0, 4.0).1, 0);
+    }
+
+    #[test]
+    fn quartic_double_roots() {
+        // (x - 1)^2 (x + 2)(x - 3), the double root may come back once or twice
+        let c = quartic_from_roots(1.0, [1.0, 1.0, -2.0, 3.0]);
+        let (roots, n) = solve_quartic(c[0], c[1], c[2], c[3], c[4]);
+        let mut found = roots[..n].to_vec();
+        found.sort_by(f64::total_cmp);
+        found.dedup_by(|a, b| (*a - *b).abs() < 1e-4);
+        assert_roots(&found, &[-2.0, 1.0, 3.0], 1e-4);
+    }
+}
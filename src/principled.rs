@@ -0,0 +1,339 @@
+use super::color::Color;
+use super::hittable::HitRecord;
+use super::material::{local_frame, Material, RoughDielectric, ScatterRecord};
+use super::microfacet::{fresnel_dielectric, Ggx, SMOOTH_ALPHA};
+use super::ray::Ray;
+use super::rtweekend::{random_double, PI};
+use super::texture::{SolidColor, Texture};
+use super::vec3::Vec3;
+use std::sync::Arc;
+
+/// Disney style principled BSDF, one material covering plastics, metals, glass, skin and
+/// fabric through a handful of artist friendly parameters. Every parameter is a texture,
+/// scalar parameters take the average of the texture's channels and are clamped to [0, 1].
+///
+/// The lobes are a Burley diffuse blended with Burley's subsurface approximation, a sheen
+/// at grazing angles, GGX specular reflection tinted by the base color as the material
+/// turns metallic, a GGX clearcoat and rough transmission through the base color. Each
+/// scatter samples one lobe and weighs the direction with the density of all of them, so
+/// `eval` and `scattering_pdf` describe exactly what `scatter` does.
+///
+/// Parameters are public, set the ones that matter after `new`:
+/// ```ignore
+/// let mut gold = Principled::new(&Color::new(1.0, 0.78, 0.34));
+/// gold.metallic = Arc::new(SolidColor::new_scalar(1.0));
+/// gold.roughness = Arc::new(SolidColor::new_scalar(0.3));
+/// ```
+#[derive(Clone)]
+pub struct Principled {
+    pub base_color: Arc<dyn Texture + Sync + Send>,
+    /// Zero for dielectrics, one for metals
+    pub metallic: Arc<dyn Texture + Sync + Send>,
+    /// Perceptual roughness of the specular and transmission lobes
+    pub roughness: Arc<dyn Texture + Sync + Send>,
+    /// Strength of dielectric reflection, 0.5 is a reflectance of 4% looking straight on
+    pub specular: Arc<dyn Texture + Sync + Send>,
+    /// Strength of the grazing retroreflection of cloth
+    pub sheen: Arc<dyn Texture + Sync + Send>,
+    /// Strength of a clear varnish layer on top
+    pub clearcoat: Arc<dyn Texture + Sync + Send>,
+    pub clearcoat_roughness: Arc<dyn Texture + Sync + Send>,
+    /// Zero for opaque, one for glass
+    pub transmission: Arc<dyn Texture + Sync + Send>,
+    /// Blend from the diffuse lobe towards a flatter, subsurface looking one
+    pub subsurface: Arc<dyn Texture + Sync + Send>,
+    /// Index of refraction used by transmission
+    pub ior: f64,
+}
+
+impl Principled {
+    pub fn new(base_color: &Color) -> Self {
+        Principled::new_texture(Arc::new(SolidColor::new(base_color)))
+    }
+
+    pub fn new_texture(base_color: Arc<dyn Texture + Sync + Send>) -> Self {
+        let scalar = |value: f64| -> Arc<dyn Texture + Sync + Send> {
+            Arc::new(SolidColor::new_scalar(value))
+        };
+        Principled {
+            base_color,
+            metallic: scalar(0.0),
+            roughness: scalar(0.5),
+            specular: scalar(0.5),
+            sheen: scalar(0.0),
+            clearcoat: scalar(0.0),
+            clearcoat_roughness: scalar(0.03),
+            transmission: scalar(0.0),
+            subsurface: scalar(0.0),
+            ior: 1.45,
+        }
+    }
+
+    /// Parameters at the hit point
+    fn params(&self, rec: &HitRecord) -> Params {
+        let scalar = |texture: &Arc<dyn Texture + Sync + Send>| {
+            let c = texture.value(rec.u, rec.v, &rec.p);
+            ((c.x() + c.y() + c.z()) / 3.0).clamp(0.0, 1.0)
+        };
+        // Perfectly smooth lobes can't be evaluated, keep them slightly rough
+        let distribution = |roughness: f64| {
+            let alpha = Ggx::from_roughness(roughness).alpha;
+            Ggx {
+                alpha: alpha.max(SMOOTH_ALPHA),
+            }
+        };
+
+        let metallic = scalar(&self.metallic);
+        let transmission = scalar(&self.transmission);
+        let roughness = scalar(&self.roughness);
+        Params {
+            base_color: self.base_color.value(rec.u, rec.v, &rec.p),
+            roughness,
+            specular: scalar(&self.specular),
+            sheen: scalar(&self.sheen),
+            clearcoat: scalar(&self.clearcoat),
+            subsurface: scalar(&self.subsurface),
+            metal: metallic,
+            opaque: (1.0 - metallic) * (1.0 - transmission),
+            glass: (1.0 - metallic) * transmission,
+            distribution: distribution(roughness),
+            clearcoat_distribution: distribution(scalar(&self.clearcoat_roughness)),
+            eta: if rec.front_face {
+                self.ior
+            } else {
+                1.0 / self.ior
+            },
+        }
+    }
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Principled::new(&Color::new(0.8, 0.8, 0.8))
+    }
+}
+
+/// Principled parameters evaluated at one point
+struct Params {
+    base_color: Color,
+    roughness: f64,
+    specular: f64,
+    sheen: f64,
+    clearcoat: f64,
+    subsurface: f64,
+    /// Weights of the metallic, opaque dielectric and transmissive parts, summing to one
+    metal: f64,
+    opaque: f64,
+    glass: f64,
+    distribution: Ggx,
+    clearcoat_distribution: Ggx,
+    /// Index of the side the normal points away from, relative to the side it points to
+    eta: f64,
+}
+
+/// Lobe `scatter` draws a direction from
+#[derive(Debug, Clone, Copy)]
+enum Lobe {
+    Diffuse,
+    Specular,
+    Clearcoat,
+    Transmission,
+}
+
+const LOBES: [Lobe; 4] = [
+    Lobe::Diffuse,
+    Lobe::Specular,
+    Lobe::Clearcoat,
+    Lobe::Transmission,
+];
+
+/// Schlick's approximation of the Fresnel factor, (1 - cos)^5
+fn schlick_weight(cos: f64) -> f64 {
+    let m = (1.0 - cos).clamp(0.0, 1.0);
+    let m2 = m * m;
+    m2 * m2 * m
+}
+
+fn schlick(f0: &Color, cos: f64) -> Color {
+    *f0 + (Color::new(1.0, 1.0, 1.0) - *f0) * schlick_weight(cos)
+}
+
+impl Params {
+    /// Probability of sampling each lobe, in the order of `LOBES`
+    fn lobe_probabilities(&self) -> [f64; 4] {
+        let weights = [
+            self.opaque,
+            self.metal + 0.5 * self.opaque,
+            0.25 * self.clearcoat,
+            self.glass,
+        ];
+        let total: f64 = weights.iter().sum();
+        weights.map(|w| w / total)
+    }
+
+    /// BSDF times cosine and sampling density, for directions in the local frame
+    fn eval_pdf_local(&self, wo: &Vec3, wi: &Vec3) -> (Color, f64) {
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return (Color::default(), 0.0);
+        }
+        let [p_diffuse, p_specular, p_clearcoat, p_transmission] = self.lobe_probabilities();
+
+        if wi.z() < 0.0 {
+            let h = match RoughDielectric::half_vector(wo, wi, self.eta) {
+                Some(h) => h,
+                None => return (Color::default(), 0.0),
+            };
+            let (f, pdf) = self.transmission_local(wo, wi, &h);
+            return (self.base_color * (self.glass * f), p_transmission * pdf);
+        }
+
+        let h = Vec3::unit_vector(&(*wo + *wi));
+        let cos_d = Vec3::dot(wi, &h);
+        let mut f = Color::default();
+        let mut pdf = 0.0;
+
+        // Burley diffuse with retroreflection at grazing angles, and the subsurface
+        // approximation flattening it out
+        let fl = schlick_weight(wi.z());
+        let fv = schlick_weight(wo.z());
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fd = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+        let fss90 = self.roughness * cos_d * cos_d;
+        let fss = (1.0 + (fss90 - 1.0) * fl) * (1.0 + (fss90 - 1.0) * fv);
+        let ss = 1.25 * (fss * (1.0 / (wi.z() + wo.z()) - 0.5) + 0.5);
+        let diffuse = (fd + (ss - fd) * self.subsurface) / PI;
+        let sheen = self.sheen * schlick_weight(cos_d);
+        f = f + self.base_color * (self.opaque * diffuse * wi.z());
+        f = f + Color::new(1.0, 1.0, 1.0) * (self.opaque * sheen * wi.z());
+        pdf += p_diffuse * wi.z() / PI;
+
+        // Specular reflection, colored by the base color as the material turns metallic
+        let distribution = &self.distribution;
+        let microfacet = distribution.d(&h) * distribution.g(wo, wi) / (4.0 * wo.z());
+        let dielectric_f0 = Color::new(1.0, 1.0, 1.0) * (0.08 * self.specular);
+        let fresnel = schlick(&dielectric_f0, cos_d) * self.opaque
+            + schlick(&self.base_color, cos_d) * self.metal;
+        f = f + fresnel * microfacet;
+        let reflection_pdf = distribution.visible_normal_pdf(wo, &h) / (4.0 * cos_d);
+        pdf += p_specular * reflection_pdf;
+
+        // Reflection off the transmissive part
+        let (glass_f, glass_pdf) = self.transmission_local(wo, wi, &h);
+        f = f + Color::new(1.0, 1.0, 1.0) * (self.glass * glass_f);
+        pdf += p_transmission * glass_pdf;
+
+        // Clearcoat, a fixed index of 1.5 over everything else
+        let coat = &self.clearcoat_distribution;
+        let coat_f = 0.25 * self.clearcoat * (0.04 + 0.96 * schlick_weight(cos_d));
+        f = f + Color::new(1.0, 1.0, 1.0) * (coat_f * coat.d(&h) * coat.g(wo, wi) / (4.0 * wo.z()));
+        pdf += p_clearcoat * coat.visible_normal_pdf(wo, &h) / (4.0 * cos_d);
+
+        (f, pdf)
+    }
+
+    /// Rough dielectric BSDF times cosine and density for microfacet normal h, reflecting
+    /// or refracting with the exact Fresnel equations
+    fn transmission_local(&self, wo: &Vec3, wi: &Vec3, h: &Vec3) -> (f64, f64) {
+        let eta = self.eta;
+        let cos_o = Vec3::dot(wo, h);
+        let cos_i = Vec3::dot(wi, h);
+        let fresnel = fresnel_dielectric(cos_o, eta);
+        let d = self.distribution.d(h);
+        let g = self.distribution.g(wo, wi);
+        let visible = self.distribution.visible_normal_pdf(wo, h);
+
+        if wi.z() > 0.0 {
+            let f = fresnel * d * g / (4.0 * wo.z());
+            let pdf = fresnel * visible / (4.0 * cos_o);
+            return (f, pdf);
+        }
+
+        let denom = (cos_i + cos_o / eta) * (cos_i + cos_o / eta);
+        let f = (1.0 - fresnel) * d * g * (cos_i * cos_o).abs() / (wo.z() * denom * eta * eta);
+        let pdf = (1.0 - fresnel) * visible * cos_i.abs() / denom;
+        (f, pdf)
+    }
+
+    /// Direction drawn from one lobe, None when the sample leaves the lobe's hemisphere
+    fn sample_local(&self, lobe: Lobe, wo: &Vec3) -> Option<Vec3> {
+        let wi = match lobe {
+            Lobe::Diffuse => {
+                // Offsetting a unit sphere by the normal gives cosine weighted directions
+                let wi = Vec3::new(0.0, 0.0, 1.0) + Vec3::random_unit_vector();
+                if wi.near_zero() {
+                    Vec3::new(0.0, 0.0, 1.0)
+                } else {
+                    Vec3::unit_vector(&wi)
+                }
+            }
+            Lobe::Specular => Vec3::reflect(&-*wo, &self.distribution.sample_visible_normal(wo)),
+            Lobe::Clearcoat => Vec3::reflect(
+                &-*wo,
+                &self.clearcoat_distribution.sample_visible_normal(wo),
+            ),
+            Lobe::Transmission => {
+                let h = self.distribution.sample_visible_normal(wo);
+                let cos_o = Vec3::dot(wo, &h);
+                if random_double() < fresnel_dielectric(cos_o, self.eta) {
+                    let wi = Vec3::reflect(&-*wo, &h);
+                    return (wi.z() > 0.0).then_some(wi);
+                }
+                let sin2_t = (1.0 - cos_o * cos_o).max(0.0) / (self.eta * self.eta);
+                let cos_t = (1.0 - sin2_t).sqrt();
+                let wi = -*wo / self.eta + h * (cos_o / self.eta - cos_t);
+                return (wi.z() < 0.0).then_some(wi);
+            }
+        };
+        (wi.z() > 0.0).then_some(wi)
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        let (frame, wo) = local_frame(r_in, rec);
+        if wo.z() <= 0.0 {
+            return false;
+        }
+        let params = self.params(rec);
+
+        let mut u = random_double();
+        let mut lobe = Lobe::Diffuse;
+        for (candidate, probability) in LOBES.iter().zip(params.lobe_probabilities()) {
+            if probability <= 0.0 {
+                continue;
+            }
+            lobe = *candidate;
+            if u < probability {
+                break;
+            }
+            u -= probability;
+        }
+
+        let wi = match params.sample_local(lobe, &wo) {
+            Some(wi) => wi,
+            None => return false,
+        };
+        let (f, pdf) = params.eval_pdf_local(&wo, &wi);
+        if pdf <= 0.0 {
+            return false;
+        }
+
+        srec.scattered = Ray::new_time(&rec.p, &frame.local(&wi), r_in.time());
+        srec.attenuation = f / pdf;
+        srec.pdf = pdf;
+        srec.is_specular = false;
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        let (frame, wo) = local_frame(r_in, rec);
+        let wi = frame.to_local(&Vec3::unit_vector(&scattered.direction()));
+        self.params(rec).eval_pdf_local(&wo, &wi).0
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let (frame, wo) = local_frame(r_in, rec);
+        let wi = frame.to_local(&Vec3::unit_vector(&scattered.direction()));
+        self.params(rec).eval_pdf_local(&wo, &wi).1
+    }
+}
@@ -0,0 +1,124 @@
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::Material;
+use super::ray::Ray;
+use super::rtweekend::{random_double, INFINITY};
+use super::triangle::{area_to_solid_angle_pdf, BBOX_PADDING};
+use super::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Parallelogram spanned by the edges u and v from the corner q. Surface coordinates run
+/// from 0 to 1 along each edge.
+pub struct Quad {
+    q: Point3,
+    u: Vec3,
+    v: Vec3,
+    mat: Arc<Box<dyn Material + Sync + Send>>,
+    bbox: Aabb,
+    normal: Vec3,
+    /// Plane constant, dot(normal, p) for every point p of the plane
+    d: f64,
+    /// n / dot(n, n) with n = u x v, used to get the planar coordinates of a hit
+    w: Vec3,
+    area: f64,
+}
+
+impl Quad {
+    pub fn new(q: Point3, u: Vec3, v: Vec3, m: Box<dyn Material + Sync + Send>) -> Self {
+        Quad::new_shared(q, u, v, Arc::new(m))
+    }
+
+    /// Build a quad with a material that may also be used by other objects
+    pub fn new_shared(
+        q: Point3,
+        u: Vec3,
+        v: Vec3,
+        mat: Arc<Box<dyn Material + Sync + Send>>,
+    ) -> Self {
+        let n = Vec3::cross(&u, &v);
+        let normal = Vec3::unit_vector(&n);
+        let bbox = Aabb::new_boxes(
+            &Aabb::new_points(&q, &(q + u + v)),
+            &Aabb::new_points(&(q + u), &(q + v)),
+        )
+        .pad(BBOX_PADDING);
+
+        Quad {
+            q,
+            u,
+            v,
+            mat,
+            bbox,
+            normal,
+            d: Vec3::dot(&normal, &q),
+            w: n / Vec3::dot(&n, &n),
+            area: n.length(),
+        }
+    }
+
+    /// Ray parameter and planar coordinates of the hit, if the ray hits inside the quad
+    fn intersect(&self, r: &Ray, ray_t: Interval) -> Option<(f64, f64, f64)> {
+        let denom = Vec3::dot(&self.normal, &r.direction());
+
+        // Rays parallel to the plane never hit it
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = (self.d - Vec3::dot(&self.normal, &r.origin())) / denom;
+        if !ray_t.surrounds(t) {
+            return None;
+        }
+
+        let planar_hitpt_vector = r.at(t) - self.q;
+        let alpha = Vec3::dot(&self.w, &Vec3::cross(&planar_hitpt_vector, &self.v));
+        let beta = Vec3::dot(&self.w, &Vec3::cross(&self.u, &planar_hitpt_vector));
+
+        let unit_interval = Interval::new_val(0.0, 1.0);
+        if !unit_interval.contains(alpha) || !unit_interval.contains(beta) {
+            return None;
+        }
+
+        Some((t, alpha, beta))
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let (t, alpha, beta) = match self.intersect(r, ray_t) {
+            Some(hit) => hit,
+            None => return false,
+        };
+
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.u = alpha;
+        rec.v = beta;
+        rec.mat = self.mat.clone();
+        rec.vertex_color = None;
+        rec.set_face_normal(r, &self.normal);
+        rec.set_derivatives(&self.u, &self.v);
+
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        match self.intersect(
+            &Ray::new(origin, direction),
+            Interval::new_val(0.001, INFINITY),
+        ) {
+            Some((t, _, _)) => area_to_solid_angle_pdf(t, direction, &self.normal, self.area),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        let p = self.q + (self.u * random_double()) + (self.v * random_double());
+        p - origin
+    }
+}
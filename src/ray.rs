@@ -4,22 +4,45 @@ use super::vec3::{Point3, Vec3};
 pub struct Ray {
     orig: Point3,
     dir: Vec3,
+    /// Moment within the camera shutter the ray exists at, used by moving objects
+    tm: f64,
+    /// Wavelength in nanometers the ray carries when rendering spectrally
+    lambda: Option<f64>,
 }
 
 impl Ray {
     pub fn new(origin: &Point3, direction: &Vec3) -> Self {
+        Ray::new_time(origin, direction, 0.0)
+    }
+
+    pub fn new_time(origin: &Point3, direction: &Vec3, time: f64) -> Self {
         Ray {
             orig: origin.to_owned(),
             dir: direction.to_owned(),
+            tm: time,
+            lambda: None,
         }
     }
 
+    /// Same ray carrying light of lambda nanometers
+    pub fn with_wavelength(mut self, lambda: f64) -> Self {
+        self.lambda = Some(lambda);
+        self
+    }
+
     pub fn origin(&self) -> Point3 {
         self.orig.to_owned()
     }
     pub fn direction(&self) -> Vec3 {
         self.dir.to_owned()
     }
+    pub fn time(&self) -> f64 {
+        self.tm
+    }
+    /// Wavelength in nanometers, None outside spectral rendering
+    pub fn wavelength(&self) -> Option<f64> {
+        self.lambda
+    }
 
     pub fn at(&self, t: f64) -> Point3 {
         self.orig + self.dir * t
@@ -0,0 +1,496 @@
+use super::aabb::{self, Aabb};
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::Material;
+use super::ray::Ray;
+use super::rtweekend::PI;
+use super::sphere::sphere_derivatives;
+use super::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Signed distance field: distance from p to the closest surface, negative inside
+pub trait Sdf {
+    fn distance(&self, p: &Point3) -> f64;
+}
+
+impl<F> Sdf for F
+where
+    F: Fn(&Point3) -> f64,
+{
+    fn distance(&self, p: &Point3) -> f64 {
+        self(p)
+    }
+}
+
+/// Composable distance field. Shapes are centered on the origin and placed with the
+/// operators, for example `SdfNode::sphere(1.0).translate(&offset)`.
+#[derive(Debug, Clone)]
+pub enum SdfNode {
+    Sphere {
+        radius: f64,
+    },
+    /// Box with the given half size along each axis
+    Box {
+        half_extents: Vec3,
+    },
+    /// Ring lying in the xz plane
+    Torus {
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    Translate {
+        offset: Vec3,
+        node: Box<SdfNode>,
+    },
+    Scale {
+        factor: f64,
+        node: Box<SdfNode>,
+    },
+    Union(Box<SdfNode>, Box<SdfNode>),
+    Intersection(Box<SdfNode>, Box<SdfNode>),
+    /// Inside the first node but not the second
+    Subtraction(Box<SdfNode>, Box<SdfNode>),
+    /// Union blending the two surfaces together where they are within k of each other
+    SmoothUnion {
+        a: Box<SdfNode>,
+        b: Box<SdfNode>,
+        k: f64,
+    },
+    /// Infinite copies of the node, one per period along each axis. A zero period leaves
+    /// that axis alone.
+    Repeat {
+        period: Vec3,
+        node: Box<SdfNode>,
+    },
+    /// Rotate slices of the node around the y axis by rate radians per unit of height. The
+    /// result is not an exact distance any more, lower `SdfHittable::step_scale` to match.
+    Twist {
+        rate: f64,
+        node: Box<SdfNode>,
+    },
+}
+
+impl SdfNode {
+    pub fn sphere(radius: f64) -> Self {
+        SdfNode::Sphere { radius }
+    }
+
+    pub fn cuboid(half_extents: &Vec3) -> Self {
+        SdfNode::Box {
+            half_extents: half_extents.to_owned(),
+        }
+    }
+
+    pub fn torus(major_radius: f64, minor_radius: f64) -> Self {
+        SdfNode::Torus {
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    pub fn translate(self, offset: &Vec3) -> Self {
+        SdfNode::Translate {
+            offset: offset.to_owned(),
+            node: Box::new(self),
+        }
+    }
+
+    pub fn scale(self, factor: f64) -> Self {
+        SdfNode::Scale {
+            factor,
+            node: Box::new(self),
+        }
+    }
+
+    pub fn union(self, other: SdfNode) -> Self {
+        SdfNode::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersection(self, other: SdfNode) -> Self {
+        SdfNode::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn subtraction(self, other: SdfNode) -> Self {
+        SdfNode::Subtraction(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: SdfNode, k: f64) -> Self {
+        SdfNode::SmoothUnion {
+            a: Box::new(self),
+            b: Box::new(other),
+            k,
+        }
+    }
+
+    pub fn repeat(self, period: &Vec3) -> Self {
+        SdfNode::Repeat {
+            period: period.to_owned(),
+            node: Box::new(self),
+        }
+    }
+
+    pub fn twist(self, rate: f64) -> Self {
+        SdfNode::Twist {
+            rate,
+            node: Box::new(self),
+        }
+    }
+
+    /// Box enclosing the surface, unbounded along repeated axes
+    pub fn bounding_box(&self) -> Aabb {
+        match self {
+            SdfNode::Sphere { radius } => {
+                let rvec = Vec3::new(*radius, *radius, *radius);
+                Aabb::new_points(&(-rvec), &rvec)
+            }
+            SdfNode::Box { half_extents } => Aabb::new_points(&(-half_extents), half_extents),
+            SdfNode::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = major_radius + minor_radius;
+                let extent = Vec3::new(ring, *minor_radius, ring);
+                Aabb::new_points(&(-extent), &extent)
+            }
+            SdfNode::Translate { offset, node } => {
+                let bbox = node.bounding_box();
+                let shift = |i: &Interval, d: f64| Interval::new_val(i.min + d, i.max + d);
+                Aabb::new(
+                    shift(&bbox.x, offset.x()),
+                    shift(&bbox.y, offset.y()),
+                    shift(&bbox.z, offset.z()),
+                )
+            }
+            SdfNode::Scale { factor, node } => {
+                let bbox = node.bounding_box();
+                let scale = |i: &Interval| Interval::new_val(i.min * factor, i.max * factor);
+                Aabb::new(scale(&bbox.x), scale(&bbox.y), scale(&bbox.z))
+            }
+            SdfNode::Union(a, b) => Aabb::new_boxes(&a.bounding_box(), &b.bounding_box()),
+            SdfNode::Intersection(a, b) => {
+                let (a, b) = (a.bounding_box(), b.bounding_box());
+                let overlap = |i: &Interval, j: &Interval| {
+                    Interval::new_val(i.min.max(j.min), i.max.min(j.max))
+                };
+                Aabb::new(
+                    overlap(&a.x, &b.x),
+                    overlap(&a.y, &b.y),
+                    overlap(&a.z, &b.z),
+                )
+            }
+            SdfNode::Subtraction(a, _) => a.bounding_box(),
+            SdfNode::SmoothUnion { a, b, k } => {
+                // The blend pulls the surface out by at most k / 4 on each side, and expand
+                // pads each side by half the width it is given
+                let bbox = Aabb::new_boxes(&a.bounding_box(), &b.bounding_box());
+                Aabb::new(
+                    bbox.x.expand(k / 2.0),
+                    bbox.y.expand(k / 2.0),
+                    bbox.z.expand(k / 2.0),
+                )
+            }
+            SdfNode::Repeat { period, node } => {
+                let bbox = node.bounding_box();
+                let axes: Vec<Interval> = (0..3)
+                    .map(|axis| {
+                        if period.axis(axis) > 0.0 {
+                            *aabb::UNIVERSE.axis(axis)
+                        } else {
+                            *bbox.axis(axis)
+                        }
+                    })
+                    .collect();
+                Aabb::new(axes[0], axes[1], axes[2])
+            }
+            SdfNode::Twist { node, .. } => {
+                // Any slice can end up at any angle, so take the widest circle around y
+                let bbox = node.bounding_box();
+                let reach = |i: &Interval| i.min.abs().max(i.max.abs());
+                let radius = reach(&bbox.x).hypot(reach(&bbox.z));
+                let around = Interval::new_val(-radius, radius);
+                Aabb::new(around, bbox.y, around)
+            }
+        }
+    }
+}
+
+impl Sdf for SdfNode {
+    fn distance(&self, p: &Point3) -> f64 {
+        match self {
+            SdfNode::Sphere { radius } => p.length() - radius,
+            SdfNode::Box { half_extents } => {
+                let q = Vec3::new(
+                    p.x().abs() - half_extents.x(),
+                    p.y().abs() - half_extents.y(),
+                    p.z().abs() - half_extents.z(),
+                );
+                let outside = Vec3::new(q.x().max(0.0), q.y().max(0.0), q.z().max(0.0));
+                outside.length() + q.x().max(q.y()).max(q.z()).min(0.0)
+            }
+            SdfNode::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring_distance = p.x().hypot(p.z()) - major_radius;
+                ring_distance.hypot(p.y()) - minor_radius
+            }
+            SdfNode::Translate { offset, node } => node.distance(&(*p - offset)),
+            SdfNode::Scale { factor, node } => node.distance(&(*p / *factor)) * factor,
+            SdfNode::Union(a, b) => a.distance(p).min(b.distance(p)),
+            SdfNode::Intersection(a, b) => a.distance(p).max(b.distance(p)),
+            SdfNode::Subtraction(a, b) => a.distance(p).max(-b.distance(p)),
+            SdfNode::SmoothUnion { a, b, k } => {
+                // Polynomial smooth minimum
+                let (da, db) = (a.distance(p), b.distance(p));
+                let h = (k - (da - db).abs()).max(0.0) / k;
+                da.min(db) - h * h * k * 0.25
+            }
+            SdfNode::Repeat { period, node } => {
+                let wrap = |x: f64, period: f64| {
+                    if period > 0.0 {
+                        x - period * (x / period).round()
+                    } else {
+                        x
+                    }
+                };
+                let q = Vec3::new(
+                    wrap(p.x(), period.x()),
+                    wrap(p.y(), period.y()),
+                    wrap(p.z(), period.z()),
+                );
+                node.distance(&q)
+            }
+            SdfNode::Twist { rate, node } => {
+                let (s, c) = (rate * p.y()).sin_cos();
+                let q = Vec3::new(c * p.x() - s * p.z(), p.y(), s * p.x() + c * p.z());
+                node.distance(&q)
+            }
+        }
+    }
+}
+
+/// Surface where a distance field is zero, found by sphere tracing: the ray steps forward by
+/// the distance to the closest surface, which can never overshoot it, until the distance is
+/// below `hit_epsilon`. u and v come from the direction of the normal, as on a sphere.
+pub struct SdfHittable {
+    sdf: Arc<dyn Sdf + Sync + Send>,
+    mat: Arc<Box<dyn Material + Sync + Send>>,
+    bbox: Aabb,
+    /// Steps taken before giving up on a ray
+    pub max_steps: usize,
+    /// Distance under which the ray counts as being on the surface
+    pub hit_epsilon: f64,
+    /// Fraction of the distance stepped each time, below 1 for fields that overestimate
+    /// distances such as twisted ones
+    pub step_scale: f64,
+}
+
+impl SdfHittable {
+    ///
+    /// Wrap any distance field
+    /// * `sdf` - Distance field, closures taking a `&Point3` work too
+    /// * `bbox` - Box the surface is known to be inside of, rays are only marched through it
+    /// * `m` - Material of the surface
+    pub fn new(
+        sdf: Arc<dyn Sdf + Sync + Send>,
+        bbox: Aabb,
+        m: Box<dyn Material + Sync + Send>,
+    ) -> Self {
+        SdfHittable {
+            sdf,
+            mat: Arc::new(m),
+            bbox,
+            max_steps: 256,
+            hit_epsilon: 1e-5,
+            step_scale: 1.0,
+        }
+    }
+
+    /// Wrap a node tree, using its own bounding box
+    pub fn from_node(node: SdfNode, m: Box<dyn Material + Sync + Send>) -> Self {
+        let bbox = node.bounding_box();
+        SdfHittable::new(Arc::new(node), bbox, m)
+    }
+
+    /// Gradient of the field by central differences on a tetrahedron, pointing outwards
+    fn normal(&self, p: &Point3) -> Vec3 {
+        let h = self.hit_epsilon;
+        let offsets = [
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ];
+        let gradient = offsets.iter().fold(Vec3::default(), |sum, k| {
+            sum + *k * self.sdf.distance(&(*p + *k * h))
+        });
+        Vec3::unit_vector(&gradient)
+    }
+}
+
+impl Hittable for SdfHittable {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let span = match self.bbox.clip(r, ray_t) {
+            Some(span) => span,
+            None => return false,
+        };
+
+        let direction_length = r.direction().length();
+        let mut t = span.min;
+
+        // Rays leaving a surface start right on it. Hits only count once the ray has been
+        // clear of every surface, so it doesn't find the one it starts from again. Rays
+        // entering the box from outside can't be on the surface they start from.
+        let mut clear = span.min > ray_t.min;
+        let mut found = false;
+        for _ in 0..self.max_steps {
+            let distance = self.sdf.distance(&r.at(t)).abs();
+            if distance < self.hit_epsilon {
+                if clear {
+                    found = true;
+                    break;
+                }
+            } else {
+                clear = true;
+            }
+
+            t += self.step_scale * distance.max(self.hit_epsilon) / direction_length;
+            if t > span.max {
+                return false;
+            }
+        }
+        if !found || !ray_t.surrounds(t) {
+            return false;
+        }
+
+        rec.t = t;
+        rec.p = r.at(t);
+        let outward_normal = self.normal(&rec.p);
+        rec.u = ((-outward_normal.z()).atan2(outward_normal.x()) + PI) / (2.0 * PI);
+        rec.v = (-outward_normal.y()).clamp(-1.0, 1.0).acos() / PI;
+        rec.mat = self.mat.clone();
+        rec.vertex_color = None;
+        rec.set_face_normal(r, &outward_normal);
+        // UVs come from the normal, so the tangents are those of a unit sphere
+        let (dpdu, dpdv) = sphere_derivatives(&outward_normal, 1.0);
+        rec.set_derivatives(&dpdu, &dpdv);
+
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::Lambertian;
+    use crate::rtweekend::{random_double, random_double_range, INFINITY};
+    use crate::sphere::Sphere;
+
+    fn grey() -> Box<dyn Material + Sync + Send> {
+        Box::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5)))
+    }
+
+    fn hit(object: &dyn Hittable, r: &Ray) -> Option<HitRecord> {
+        let mut rec = HitRecord::default();
+        if object.hit(r, Interval::new_val(0.001, INFINITY), &mut rec) {
+            Some(rec)
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn sphere_tracing_matches_the_analytic_sphere() {
+        let center = Point3::new(0.5, -1.0, 2.0);
+        let traced = SdfHittable::from_node(SdfNode::sphere(1.5).translate(&center), grey());
+        let exact = Sphere::new(center, 1.5, grey());
+
+        for _ in 0..1_000 {
+            // Aim inside the sphere so the rays don't graze it, and away from it for misses
+            let origin = center + Vec3::random_unit_vector() * random_double_range(4.0, 8.0);
+            let target = center + Vec3::random_unit_vector() * (1.4 * random_double());
+            let r = Ray::new(
+                &origin,
+                &((target - origin) * random_double_range(0.5, 2.0)),
+            );
+
+            let (a, b) = (hit(&traced, &r).unwrap(), hit(&exact, &r).unwrap());
+            assert!((a.t - b.t).abs() * r.direction().length() < 1e-4);
+            assert!((a.normal - b.normal).length() < 1e-3);
+            assert!(a.front_face);
+
+            let away = Ray::new(&origin, &(origin - target));
+            assert!(hit(&traced, &away).is_none());
+        }
+    }
+
+    #[test]
+    fn rays_leaving_the_surface_find_the_far_side() {
+        let traced = SdfHittable::from_node(SdfNode::sphere(1.0), grey());
+        let from_center = Ray::new(&Point3::new(0.0, 0.0, 0.0), &Vec3::new(0.0, 0.0, 1.0));
+        let rec = hit(&traced, &from_center).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-4);
+        assert!(!rec.front_face);
+
+        // Starting on the near side, as a refracted ray does
+        let through = Ray::new(&Point3::new(-1.0, 0.0, 0.0), &Vec3::new(1.0, 0.0, 0.0));
+        let rec = hit(&traced, &through).unwrap();
+        assert!((rec.p.x() - 1.0).abs() < 1e-4);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn shape_distances() {
+        let p = Point3::new(3.0, 0.0, 0.0);
+        let cuboid = SdfNode::cuboid(&Vec3::new(1.0, 2.0, 3.0));
+        assert!((cuboid.distance(&p) - 2.0).abs() < 1e-12);
+        assert!((cuboid.distance(&Point3::new(0.0, 0.0, 0.0)) + 1.0).abs() < 1e-12);
+        assert!((cuboid.distance(&Point3::new(2.0, 3.0, 0.0)) - 2f64.sqrt()).abs() < 1e-12);
+
+        let ring = SdfNode::torus(2.0, 0.5);
+        assert!((ring.distance(&p) - 0.5).abs() < 1e-12);
+        assert!((ring.distance(&Point3::new(0.0, 0.0, 0.0)) - 1.5).abs() < 1e-12);
+
+        let hollow = SdfNode::sphere(2.0).subtraction(SdfNode::sphere(1.0));
+        assert!((hollow.distance(&Point3::new(0.0, 0.0, 0.0)) - 1.0).abs() < 1e-12);
+        assert!((hollow.distance(&Point3::new(1.5, 0.0, 0.0)) + 0.5).abs() < 1e-12);
+
+        let scaled = SdfNode::sphere(1.0).scale(3.0);
+        assert!((scaled.distance(&Point3::new(0.0, 5.0, 0.0)) - 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn smooth_union_stays_in_its_box() {
+        let k = 1.0;
+        let offset = Vec3::new(1.2, 0.0, 0.0);
+        let blend = SdfNode::sphere(1.0)
+            .translate(&-offset)
+            .smooth_union(SdfNode::sphere(1.0).translate(&offset), k);
+
+        // The blend fills the gap between the spheres
+        assert!(blend.distance(&Point3::new(0.0, 0.0, 0.0)) < 0.0);
+
+        // Every point on the sides of the box is outside the surface
+        let bbox = blend.bounding_box();
+        for _ in 0..10_000 {
+            let mut at = [bbox.x, bbox.y, bbox.z].map(|i| random_double_range(i.min, i.max));
+            let axis = (3.0 * random_double()) as usize;
+            let side = bbox.axis(axis);
+            at[axis] = if random_double() < 0.5 {
+                side.min
+            } else {
+                side.max
+            };
+            let p = Point3::new(at[0], at[1], at[2]);
+            assert!(blend.distance(&p) >= 0.0, "{p:?}");
+        }
+
+        // And it is no wider than the blend needs
+        assert!(bbox.x.max <= 2.2 + k / 4.0 + 1e-12);
+    }
+}
@@ -0,0 +1,434 @@
+use super::color::Color;
+use std::sync::OnceLock;
+
+/// Wavelength range, in nanometers, spectra are integrated over
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+
+/// Piecewise gaussian with a different width on each side of its peak
+fn lobe(lambda: f64, mean: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+    let sigma = if lambda < mean {
+        sigma_below
+    } else {
+        sigma_above
+    };
+    let x = (lambda - mean) / sigma;
+    (-0.5 * x * x).exp()
+}
+
+/// CIE 1931 2 degree color matching functions at lambda nanometers, using the multi-lobe fit
+/// of Wyman, Sloan and Shirley, which stays within a few percent of the tabulated curves
+pub fn cie_xyz(lambda: f64) -> (f64, f64, f64) {
+    let x = 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8);
+    (x, y, z)
+}
+
+/// Linear sRGB (Rec. 709 primaries, D65 white) of an XYZ color
+pub fn xyz_to_linear_srgb(x: f64, y: f64, z: f64) -> Color {
+    Color::new(
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    )
+}
+
+/// Spectral radiance of a black body at lambda nanometers, from Planck's law, in W/(sr m^2 nm)
+pub fn planck(lambda: f64, kelvin: f64) -> f64 {
+    const H: f64 = 6.62607015e-34;
+    const C: f64 = 299792458.0;
+    const K: f64 = 1.380649e-23;
+
+    if kelvin <= 0.0 {
+        return 0.0;
+    }
+    let l = lambda * 1e-9;
+    let radiance = 2.0 * H * C * C / (l.powi(5) * ((H * C / (l * K * kelvin)).exp_m1()));
+    radiance * 1e-9
+}
+
+/// Unnormalized XYZ of a black body, integrating Planck's law against the matching functions
+fn blackbody_xyz(kelvin: f64) -> (f64, f64, f64) {
+    const STEP: f64 = 5.0;
+    let mut xyz = (0.0, 0.0, 0.0);
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX {
+        let radiance = planck(lambda, kelvin);
+        let (x, y, z) = cie_xyz(lambda);
+        xyz.0 += x * radiance * STEP;
+        xyz.1 += y * radiance * STEP;
+        xyz.2 += z * radiance * STEP;
+        lambda += STEP;
+    }
+    xyz
+}
+
+///
+/// Linear sRGB color of a black body at the given temperature. Brightness follows Planck's law
+/// and is relative to a 6500 K black body, whose luminance is one, so a 1500 K ember is a few
+/// thousand times dimmer than that. Colors outside the sRGB gamut are clamped.
+/// * `kelvin` - Temperature, zero or less gives black
+pub fn blackbody(kelvin: f64) -> Color {
+    let (x, y, z) = blackbody_xyz(kelvin);
+    let reference = blackbody_xyz(6500.0).1;
+    let rgb = xyz_to_linear_srgb(x, y, z) / reference;
+    Color::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0))
+}
+
+/// Number of wavelengths traced together along every spectral path
+pub const SPECTRAL_SAMPLES: usize = 4;
+
+/// Wavelengths one spectral path carries. The first is the hero wavelength, which decides
+/// directions that depend on wavelength, the others are spread evenly from it so the path
+/// sees the whole spectrum at once.
+#[derive(Debug, Clone, Copy)]
+pub struct SampledWavelengths {
+    lambda: [f64; SPECTRAL_SAMPLES],
+    pdf: [f64; SPECTRAL_SAMPLES],
+}
+
+impl SampledWavelengths {
+    /// Wavelengths drawn with a density following the sensitivity of the eye, from one
+    /// uniform number u in [0, 1)
+    pub fn sample_visible(u: f64) -> Self {
+        let mut lambda = [0.0; SPECTRAL_SAMPLES];
+        let mut pdf = [0.0; SPECTRAL_SAMPLES];
+        for (i, (lambda, pdf)) in lambda.iter_mut().zip(pdf.iter_mut()).enumerate() {
+            let up = (u + i as f64 / SPECTRAL_SAMPLES as f64).fract();
+            *lambda = sample_visible_wavelength(up);
+            *pdf = visible_wavelength_pdf(*lambda);
+        }
+        SampledWavelengths { lambda, pdf }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    pub fn lambda(&self, i: usize) -> f64 {
+        self.lambda[i]
+    }
+
+    /// Density the wavelength was sampled with, zero once it has been dropped
+    pub fn pdf(&self, i: usize) -> f64 {
+        self.pdf[i]
+    }
+
+    /// Keep the hero wavelength alone, after a scattering event only it can follow. The hero
+    /// then stands for all the samples.
+    pub fn terminate_secondary(&mut self) {
+        if self.secondary_terminated() {
+            return;
+        }
+        for pdf in self.pdf.iter_mut().skip(1) {
+            *pdf = 0.0;
+        }
+        self.pdf[0] /= SPECTRAL_SAMPLES as f64;
+    }
+
+    pub fn secondary_terminated(&self) -> bool {
+        self.pdf.iter().skip(1).all(|&pdf| pdf == 0.0)
+    }
+}
+
+/// Inverse of the cumulative density of `visible_wavelength_pdf`, the sampling strategy of
+/// pbrt-v4
+fn sample_visible_wavelength(u: f64) -> f64 {
+    538.0 - 138.888889 * (0.85691062 - 1.82750197 * u).atanh()
+}
+
+fn visible_wavelength_pdf(lambda: f64) -> f64 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.0;
+    }
+    let c = (0.0072 * (lambda - 538.0)).cosh();
+    0.0039398042 / (c * c)
+}
+
+/// Spectrum known at the wavelengths of a `SampledWavelengths`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SampledSpectrum {
+    values: [f64; SPECTRAL_SAMPLES],
+}
+
+impl SampledSpectrum {
+    pub fn new(value: f64) -> Self {
+        SampledSpectrum {
+            values: [value; SPECTRAL_SAMPLES],
+        }
+    }
+
+    /// Spectrum of an RGB color, a reflectance or an emission, at the given wavelengths
+    pub fn from_rgb(c: &Color, lambdas: &SampledWavelengths) -> Self {
+        SampledSpectrum {
+            values: lambdas.lambda.map(|lambda| rgb_to_spectrum(c, lambda)),
+        }
+    }
+
+    pub fn value(&self, i: usize) -> f64 {
+        self.values[i]
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.values.iter().all(|&v| v == 0.0)
+    }
+
+    /// Monte Carlo estimate of the XYZ color of the full spectrum, normalized so an equal
+    /// energy spectrum of one has a luminance of one
+    pub fn to_xyz(&self, lambdas: &SampledWavelengths) -> (f64, f64, f64) {
+        let mut xyz = (0.0, 0.0, 0.0);
+        for i in 0..SPECTRAL_SAMPLES {
+            let pdf = lambdas.pdf(i);
+            if pdf <= 0.0 {
+                continue;
+            }
+            let (x, y, z) = cie_xyz(lambdas.lambda(i));
+            let weight = self.values[i] / pdf;
+            xyz.0 += x * weight;
+            xyz.1 += y * weight;
+            xyz.2 += z * weight;
+        }
+        let scale = 1.0 / (SPECTRAL_SAMPLES as f64 * cie_y_integral());
+        (xyz.0 * scale, xyz.1 * scale, xyz.2 * scale)
+    }
+
+    /// Linear sRGB of the spectrum, white balanced so an equal energy spectrum of one comes
+    /// out as (1, 1, 1), matching the white of the RGB renderer
+    pub fn to_rgb(&self, lambdas: &SampledWavelengths) -> Color {
+        let (x, y, z) = self.to_xyz(lambdas);
+        xyz_to_linear_srgb(x, y, z) * equal_energy_rgb_inverse()
+    }
+}
+
+impl std::ops::Add for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn add(self, rhs: SampledSpectrum) -> SampledSpectrum {
+        let mut values = self.values;
+        for (value, rhs) in values.iter_mut().zip(rhs.values) {
+            *value += rhs;
+        }
+        SampledSpectrum { values }
+    }
+}
+
+impl std::ops::Mul for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn mul(self, rhs: SampledSpectrum) -> SampledSpectrum {
+        let mut values = self.values;
+        for (value, rhs) in values.iter_mut().zip(rhs.values) {
+            *value *= rhs;
+        }
+        SampledSpectrum { values }
+    }
+}
+
+impl std::ops::Mul<f64> for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn mul(self, rhs: f64) -> SampledSpectrum {
+        SampledSpectrum {
+            values: self.values.map(|v| v * rhs),
+        }
+    }
+}
+
+/// Integral of the luminance matching function over the traced range
+fn cie_y_integral() -> f64 {
+    static INTEGRAL: OnceLock<f64> = OnceLock::new();
+    *INTEGRAL.get_or_init(|| {
+        let mut integral = 0.0;
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            integral += cie_xyz(lambda).1;
+            lambda += 1.0;
+        }
+        integral
+    })
+}
+
+/// Per channel reciprocal of the linear sRGB of an equal energy spectrum of one, which white
+/// balances it to (1, 1, 1)
+fn equal_energy_rgb_inverse() -> Color {
+    static WHITE: OnceLock<Color> = OnceLock::new();
+    *WHITE.get_or_init(|| {
+        let mut xyz = (0.0, 0.0, 0.0);
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            let (x, y, z) = cie_xyz(lambda);
+            xyz.0 += x;
+            xyz.1 += y;
+            xyz.2 += z;
+            lambda += 1.0;
+        }
+        let scale = 1.0 / cie_y_integral();
+        let white = xyz_to_linear_srgb(xyz.0 * scale, xyz.1 * scale, xyz.2 * scale);
+        Color::new(1.0 / white.x(), 1.0 / white.y(), 1.0 / white.z())
+    })
+}
+
+/// Linear sRGB of a reflectance spectrum, integrated every 10 nm over the visible range and
+/// white balanced so a reflectance of one is white. Colors outside the sRGB gamut are clamped.
+pub fn reflectance_to_rgb<F: Fn(f64) -> f64>(reflectance: F) -> Color {
+    let mut xyz = (0.0, 0.0, 0.0);
+    let mut white = (0.0, 0.0, 0.0);
+    for step in 0..=40 {
+        let lambda = 380.0 + 10.0 * step as f64;
+        let (x, y, z) = cie_xyz(lambda);
+        let r = reflectance(lambda);
+        xyz = (xyz.0 + x * r, xyz.1 + y * r, xyz.2 + z * r);
+        white = (white.0 + x, white.1 + y, white.2 + z);
+    }
+    let rgb = xyz_to_linear_srgb(xyz.0, xyz.1, xyz.2);
+    let white = xyz_to_linear_srgb(white.0, white.1, white.2);
+    Color::new(
+        (rgb.x() / white.x()).max(0.0),
+        (rgb.y() / white.y()).max(0.0),
+        (rgb.z() / white.z()).max(0.0),
+    )
+}
+
+/// Start of the ten equal bins of the Smits basis spectra, in nanometers
+const SMITS_LAMBDA_MIN: f64 = 380.0;
+const SMITS_LAMBDA_MAX: f64 = 720.0;
+type SmitsSpectrum = [f64; 10];
+const SMITS_WHITE: SmitsSpectrum = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: SmitsSpectrum = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: SmitsSpectrum = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: SmitsSpectrum = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: SmitsSpectrum = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: SmitsSpectrum = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: SmitsSpectrum = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// Basis spectrum at lambda, interpolated between bin centers and held flat past the ends
+fn smits_value(spectrum: &SmitsSpectrum, lambda: f64) -> f64 {
+    let bin_width = (SMITS_LAMBDA_MAX - SMITS_LAMBDA_MIN) / spectrum.len() as f64;
+    let x = ((lambda - SMITS_LAMBDA_MIN) / bin_width - 0.5).clamp(0.0, (spectrum.len() - 1) as f64);
+    let i = (x as usize).min(spectrum.len() - 2);
+    let t = x - i as f64;
+    spectrum[i] + (spectrum[i + 1] - spectrum[i]) * t
+}
+
+///
+/// Value at lambda nanometers of a smooth spectrum with the given linear sRGB color, using
+/// Smits' basis of white, cyan, magenta, yellow, red, green and blue spectra. Reflectances
+/// within [0, 1] stay within about [0, 1], brighter colors scale up, so it serves emission
+/// as well.
+/// * `c` - Linear sRGB color
+/// * `lambda` - Wavelength in nanometers
+pub fn rgb_to_spectrum(c: &Color, lambda: f64) -> f64 {
+    let (r, g, b) = (c.x(), c.y(), c.z());
+    let at = |spectrum: &SmitsSpectrum| smits_value(spectrum, lambda);
+    if r <= g && r <= b {
+        let mut value = r * at(&SMITS_WHITE);
+        if g <= b {
+            value += (g - r) * at(&SMITS_CYAN) + (b - g) * at(&SMITS_BLUE);
+        } else {
+            value += (b - r) * at(&SMITS_CYAN) + (g - b) * at(&SMITS_GREEN);
+        }
+        value
+    } else if g <= r && g <= b {
+        let mut value = g * at(&SMITS_WHITE);
+        if r <= b {
+            value += (r - g) * at(&SMITS_MAGENTA) + (b - r) * at(&SMITS_BLUE);
+        } else {
+            value += (b - g) * at(&SMITS_MAGENTA) + (r - b) * at(&SMITS_RED);
+        }
+        value
+    } else {
+        let mut value = b * at(&SMITS_WHITE);
+        if r <= g {
+            value += (r - b) * at(&SMITS_YELLOW) + (g - r) * at(&SMITS_GREEN);
+        } else {
+            value += (g - b) * at(&SMITS_YELLOW) + (r - g) * at(&SMITS_RED);
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Average of to_rgb over evenly spread wavelength samples, from_rgb of c going in
+    fn round_trip(c: &Color, samples: usize) -> Color {
+        let mut sum = Color::default();
+        for i in 0..samples {
+            let lambdas = SampledWavelengths::sample_visible((i as f64 + 0.5) / samples as f64);
+            sum = sum + SampledSpectrum::from_rgb(c, &lambdas).to_rgb(&lambdas);
+        }
+        sum / samples as f64
+    }
+
+    fn assert_close(found: Color, expected: Color, tolerance: f64) {
+        let scale = expected.x().max(expected.y()).max(expected.z());
+        for axis in 0..3 {
+            assert!(
+                (found.axis(axis) - expected.axis(axis)).abs() < tolerance * scale,
+                "{found:?} vs {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn greys_round_trip() {
+        for grey in [1.0, 0.18, 5.0] {
+            let c = Color::new(grey, grey, grey);
+            assert_close(round_trip(&c, 10_000), c, 0.002);
+        }
+    }
+
+    #[test]
+    fn colors_round_trip() {
+        // Smits' spectra are smooth, so saturated primaries spread a little into the others
+        for c in [
+            Color::new(1.0, 0.0, 0.0),
+            Color::new(0.0, 1.0, 0.0),
+            Color::new(0.0, 0.0, 1.0),
+            Color::new(0.8, 0.4, 0.1),
+            Color::new(0.2, 0.5, 0.7),
+            Color::new(4.0, 2.0, 1.0),
+        ] {
+            assert_close(round_trip(&c, 10_000), c, 0.04);
+        }
+    }
+
+    #[test]
+    fn hero_alone_stands_for_every_sample() {
+        let c = Color::new(0.8, 0.4, 0.1);
+        let samples = 10_000;
+        let mut sum = Color::default();
+        for i in 0..samples {
+            let mut lambdas = SampledWavelengths::sample_visible((i as f64 + 0.5) / samples as f64);
+            lambdas.terminate_secondary();
+            assert!(lambdas.secondary_terminated());
+            sum = sum + SampledSpectrum::from_rgb(&c, &lambdas).to_rgb(&lambdas);
+        }
+        assert_close(sum / samples as f64, round_trip(&c, samples), 0.005);
+    }
+
+    #[test]
+    fn blackbody_brightness() {
+        let luminance = |c: Color| 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z();
+        assert!((luminance(blackbody(6500.0)) - 1.0).abs() < 1e-3);
+
+        let ember = blackbody(1500.0);
+        assert!(ember.x() > ember.y() && ember.y() > ember.z());
+        assert!(luminance(ember) < 1e-3);
+        assert_eq!(luminance(blackbody(0.0)), 0.0);
+    }
+}
@@ -1,7 +1,10 @@
+use super::aabb::Aabb;
 use super::hittable::{HitRecord, Hittable};
 use super::interval::Interval;
 use super::material::Material;
+use super::onb::Onb;
 use super::ray::Ray;
+use super::rtweekend::{random_double, INFINITY, PI};
 use super::vec3::{Point3, Vec3};
 use std::sync::Arc;
 
@@ -9,44 +12,146 @@ pub struct Sphere {
     center: Point3,
     radius: f64,
     mat:  Arc<Box<dyn Material + Sync + Send>>,
+    bbox: Aabb,
 }
 
 impl Sphere {
     pub fn new(center: Point3, radius: f64, m: Box<dyn Material + Sync + Send>) -> Self {
+        let rvec = Vec3::new(radius, radius, radius);
         Sphere {
             center,
             radius,
             mat: Arc::new(m),
+            bbox: Aabb::new_points(&(center - rvec), &(center + rvec)),
         }
     }
 }
 
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
-        let oc = r.origin() - self.center;
-        let a = r.direction().length_squared();
-        let half_b = Vec3::dot(&oc, &(r.direction()));
-        let c = oc.length_squared() - self.radius * self.radius;
-        let discriminant = half_b * half_b - a * c;
-        if discriminant < 0.0 {
-            return false;
+        hit_sphere(&self.center, self.radius, &self.mat, r, ray_t, rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let mut rec = HitRecord::default();
+        if !self.hit(
+            &Ray::new(origin, direction),
+            Interval::new_val(0.001, INFINITY),
+            &mut rec,
+        ) {
+            return 0.0;
+        }
+
+        let distance_squared = (self.center - origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            // From inside, points are picked uniformly over the surface
+            let to_hit = rec.p - origin;
+            let cosine = Vec3::dot(&Vec3::unit_vector(&to_hit), &rec.normal).abs();
+            let area = 4.0 * PI * radius_squared;
+            return to_hit.length_squared() / (cosine * area);
         }
 
-        let sqrtd = discriminant.sqrt();
-        let mut root = (-half_b - sqrtd) / a;
+        // From outside, directions are picked uniformly inside the cone the sphere subtends
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        let direction = self.center - origin;
+        let distance_squared = direction.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return (self.center + Vec3::random_unit_vector() * self.radius) - origin;
+        }
+
+        let uvw = Onb::new_from_w(&direction);
+        uvw.local(&Sphere::random_to_sphere(radius_squared, distance_squared))
+    }
+}
+
+/// Intersection with the sphere of the given center and radius, shared with the moving sphere
+pub(crate) fn hit_sphere(
+    center: &Point3,
+    radius: f64,
+    mat: &Arc<Box<dyn Material + Sync + Send>>,
+    r: &Ray,
+    ray_t: Interval,
+    rec: &mut HitRecord,
+) -> bool {
+    let oc = r.origin() - center;
+    let a = r.direction().length_squared();
+    let half_b = Vec3::dot(&oc, &(r.direction()));
+    let c = oc.length_squared() - radius * radius;
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return false;
+    }
+
+    let sqrtd = discriminant.sqrt();
+    let mut root = (-half_b - sqrtd) / a;
+    if !ray_t.surrounds(root) {
+        root = (-half_b + sqrtd) / a;
         if !ray_t.surrounds(root) {
-            root = (-half_b + sqrtd) / a;
-            if !ray_t.surrounds(root) {
-                return false;
-            }
+            return false;
         }
+    }
+
+    rec.t = root;
+    rec.p = r.at(rec.t);
+    rec.mat = mat.clone();
+    rec.vertex_color = None;
+    let outward_normal = (rec.p - center) / radius;
+    rec.set_face_normal(r, &outward_normal);
+    (rec.u, rec.v) = Sphere::get_sphere_uv(&outward_normal);
+    let (dpdu, dpdv) = sphere_derivatives(&outward_normal, radius);
+    rec.set_derivatives(&dpdu, &dpdv);
+
+    true
+}
+
+/// Derivatives of the point with the given unit normal on a sphere of the given radius
+/// along the u and v of `Sphere::get_sphere_uv`. dpdv vanishes at the poles.
+pub(crate) fn sphere_derivatives(n: &Vec3, radius: f64) -> (Vec3, Vec3) {
+    let dpdu = Vec3::new(n.z(), 0.0, -n.x()) * (2.0 * PI * radius);
+    let sin_theta = (n.x() * n.x() + n.z() * n.z()).sqrt();
+    if sin_theta < 1e-8 {
+        return (dpdu, Vec3::default());
+    }
+    let dpdv = Vec3::new(
+        -n.x() * n.y() / sin_theta,
+        sin_theta,
+        -n.y() * n.z() / sin_theta,
+    ) * (PI * radius);
+    (dpdu, dpdv)
+}
+
+impl Sphere {
+    /// Spherical coordinates of a point p on the unit sphere, u being the angle around the y
+    /// axis starting from -x and v the angle from -y, both remapped to [0, 1]
+    fn get_sphere_uv(p: &Point3) -> (f64, f64) {
+        let theta = (-p.y()).acos();
+        let phi = (-p.z()).atan2(p.x()) + PI;
+
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    /// Random direction around +z inside the cone subtended by a sphere of squared radius
+    /// radius_squared, distance_squared away
+    fn random_to_sphere(radius_squared: f64, distance_squared: f64) -> Vec3 {
+        let r1 = random_double();
+        let r2 = random_double();
+        let z = 1.0 + r2 * ((1.0 - radius_squared / distance_squared).sqrt() - 1.0);
 
-        rec.t = root;
-        rec.p = r.at(rec.t);
-        rec.mat = self.mat.clone();
-        let outward_normal = (rec.p - self.center) / self.radius;
-        rec.set_face_normal(r, &outward_normal);
+        let phi = 2.0 * PI * r1;
+        let x = phi.cos() * (1.0 - z * z).sqrt();
+        let y = phi.sin() * (1.0 - z * z).sqrt();
 
-        return true;
+        Vec3::new(x, y, z)
     }
 }
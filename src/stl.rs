@@ -0,0 +1,339 @@
+use super::byte_reader::ByteReader;
+use super::color::Color;
+use super::load_error::LoadError;
+use super::material::Material;
+use super::triangle::{MeshData, TriangleMesh};
+use super::vec3::{Point3, Vec3};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const BINARY_HEADER_SIZE: usize = 80;
+const BINARY_FACET_SIZE: usize = 50;
+
+/// One triangle as stored in the file, with its facet normal and optional color
+struct Facet {
+    normal: Vec3,
+    vertices: [Point3; 3],
+    color: Option<Color>,
+}
+
+/// Load an ASCII or binary STL file as a triangle mesh using the material m. Binary files
+/// using the VisCAM/SolidView color convention keep their facet colors as vertex colors.
+pub fn load_stl<P: AsRef<Path>>(
+    path: P,
+    m: Box<dyn Material + Sync + Send>,
+) -> Result<TriangleMesh, LoadError> {
+    let path = path.as_ref();
+    let data = fs::read(path)?;
+    parse_stl(&data, &path.display().to_string(), m)
+}
+
+pub fn parse_stl(
+    data: &[u8],
+    file: &str,
+    m: Box<dyn Material + Sync + Send>,
+) -> Result<TriangleMesh, LoadError> {
+    let facets = if is_binary(data) {
+        parse_binary(data, file)?
+    } else {
+        parse_ascii(data, file)?
+    };
+    Ok(TriangleMesh::new(build_mesh(&facets), m))
+}
+
+/// Binary files may also start with "solid", so trust the size implied by the facet count
+fn is_binary(data: &[u8]) -> bool {
+    if data.len() < BINARY_HEADER_SIZE + 4 {
+        return false;
+    }
+    let count_bytes = [data[80], data[81], data[82], data[83]];
+    let count = u32::from_le_bytes(count_bytes) as usize;
+    let expected = count
+        .checked_mul(BINARY_FACET_SIZE)
+        .and_then(|bytes| bytes.checked_add(BINARY_HEADER_SIZE + 4));
+    expected == Some(data.len()) || !data.starts_with(b"solid")
+}
+
+fn parse_binary(data: &[u8], file: &str) -> Result<Vec<Facet>, LoadError> {
+    let mut reader = ByteReader::new(file, data, BINARY_HEADER_SIZE, false);
+    let count = reader.u32()? as usize;
+    let fits = count
+        .checked_mul(BINARY_FACET_SIZE)
+        .and_then(|bytes| bytes.checked_add(BINARY_HEADER_SIZE + 4))
+        .is_some_and(|bytes| bytes <= data.len());
+    if !fits {
+        return Err(reader.error(format!("{count} facets need more data than the file holds")));
+    }
+
+    let mut facets = Vec::with_capacity(count);
+    for _ in 0..count {
+        let mut read_vec = || -> Result<Vec3, LoadError> {
+            Ok(Vec3::new(
+                reader.f32()? as f64,
+                reader.f32()? as f64,
+                reader.f32()? as f64,
+            ))
+        };
+        let normal = read_vec()?;
+        let vertices = [read_vec()?, read_vec()?, read_vec()?];
+
+        // Bit 15 flags a valid 5-bit per channel color, blue in the low bits and red in the
+        // high ones
+        let attribute = reader.u16()?;
+        let color = if attribute & 0x8000 != 0 {
+            let channel = |shift: u16| ((attribute >> shift) & 0x1f) as f64 / 31.0;
+            Some(Color::new(channel(10), channel(5), channel(0)))
+        } else {
+            None
+        };
+
+        facets.push(Facet {
+            normal,
+            vertices,
+            color,
+        });
+    }
+    Ok(facets)
+}
+
+/// Non-empty lines of an ASCII STL split into tokens, remembering the current line number
+struct AsciiLines<'a> {
+    file: &'a str,
+    lines: std::iter::Enumerate<std::str::Lines<'a>>,
+    line: usize,
+}
+
+impl<'a> AsciiLines<'a> {
+    fn next_tokens(&mut self) -> Result<Vec<&'a str>, LoadError> {
+        for (index, line) in self.lines.by_ref() {
+            self.line = index + 1;
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if !tokens.is_empty() {
+                return Ok(tokens);
+            }
+        }
+        Err(LoadError::parse(
+            self.file,
+            self.line,
+            "unexpected end of file",
+        ))
+    }
+
+    /// Read the next line, which must start with keyword, and parse the numbers after it
+    fn expect(&mut self, keyword: &[&str], count: usize) -> Result<Vec<f64>, LoadError> {
+        let tokens = self.next_tokens()?;
+        if tokens.len() < keyword.len() || tokens[..keyword.len()] != *keyword {
+            return Err(LoadError::parse(
+                self.file,
+                self.line,
+                format!(
+                    "expected '{}', found '{}'",
+                    keyword.join(" "),
+                    tokens.join(" ")
+                ),
+            ));
+        }
+        self.numbers(&tokens[keyword.len()..], count)
+    }
+
+    fn numbers(&self, tokens: &[&str], count: usize) -> Result<Vec<f64>, LoadError> {
+        if tokens.len() != count {
+            return Err(LoadError::parse(
+                self.file,
+                self.line,
+                format!("expected {count} numbers, found {}", tokens.len()),
+            ));
+        }
+        tokens
+            .iter()
+            .map(|t| {
+                t.parse::<f64>().map_err(|_| {
+                    LoadError::parse(self.file, self.line, format!("invalid number '{t}'"))
+                })
+            })
+            .collect()
+    }
+
+    fn vertex(&mut self) -> Result<Point3, LoadError> {
+        let v = self.expect(&["vertex"], 3)?;
+        Ok(Point3::new(v[0], v[1], v[2]))
+    }
+}
+
+fn parse_ascii(data: &[u8], file: &str) -> Result<Vec<Facet>, LoadError> {
+    let text = std::str::from_utf8(data)
+        .map_err(|_| LoadError::data(file, 0, "ASCII STL is not valid text"))?;
+    let mut lines = AsciiLines {
+        file,
+        lines: text.lines().enumerate(),
+        line: 0,
+    };
+
+    if lines.next_tokens()?[0] != "solid" {
+        return Err(LoadError::parse(file, lines.line, "expected 'solid'"));
+    }
+
+    let mut facets = Vec::new();
+    loop {
+        let tokens = lines.next_tokens()?;
+        match tokens[..] {
+            ["endsolid", ..] => break,
+            ["facet", "normal", ..] => {
+                let n = lines.numbers(&tokens[2..], 3)?;
+                lines.expect(&["outer", "loop"], 0)?;
+                let vertices = [lines.vertex()?, lines.vertex()?, lines.vertex()?];
+                lines.expect(&["endloop"], 0)?;
+                lines.expect(&["endfacet"], 0)?;
+                facets.push(Facet {
+                    normal: Vec3::new(n[0], n[1], n[2]),
+                    vertices,
+                    color: None,
+                });
+            }
+            _ => {
+                return Err(LoadError::parse(
+                    file,
+                    lines.line,
+                    format!("expected 'facet normal', found '{}'", tokens.join(" ")),
+                ))
+            }
+        }
+    }
+    Ok(facets)
+}
+
+/// Turn the facet soup into an indexed mesh. Vertices are welded on identical positions,
+/// unless facets carry colors or normals of their own, which need unshared vertices.
+fn build_mesh(facets: &[Facet]) -> MeshData {
+    let has_colors = facets.iter().any(|f| f.color.is_some());
+    let has_normals = facets.iter().all(|f| !f.normal.near_zero());
+
+    let mut mesh = MeshData::default();
+    if has_colors || has_normals {
+        for facet in facets {
+            let base = mesh.positions.len();
+            mesh.positions.extend_from_slice(&facet.vertices);
+            if has_normals {
+                let n = Vec3::unit_vector(&facet.normal);
+                mesh.normals.extend_from_slice(&[n, n, n]);
+            }
+            if has_colors {
+                let c = facet.color.unwrap_or(Color::new(1.0, 1.0, 1.0));
+                mesh.colors.extend_from_slice(&[c, c, c]);
+            }
+            mesh.indices.push([base, base + 1, base + 2]);
+        }
+        return mesh;
+    }
+
+    let mut lookup: HashMap<[u64; 3], usize> = HashMap::new();
+    for facet in facets {
+        let mut tri = [0; 3];
+        for (index, v) in tri.iter_mut().zip(facet.vertices.iter()) {
+            let key = [v.x().to_bits(), v.y().to_bits(), v.z().to_bits()];
+            *index = *lookup.entry(key).or_insert_with(|| {
+                mesh.positions.push(*v);
+                mesh.positions.len() - 1
+            });
+        }
+        mesh.indices.push(tri);
+    }
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    fn parse(data: &[u8]) -> Result<MeshData, LoadError> {
+        let m = Box::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5)));
+        parse_stl(data, "test.stl", m).map(|mesh| (**mesh.data()).clone())
+    }
+
+    /// Normal, vertices and attribute bits of a binary facet
+    type BinaryFacet = ([f32; 3], [[f32; 3]; 3], u16);
+
+    /// Binary file whose header starts with "solid", like many exporters write
+    fn binary(facets: &[BinaryFacet]) -> Vec<u8> {
+        let mut data = b"solid binary".to_vec();
+        data.resize(BINARY_HEADER_SIZE, 0);
+        data.extend_from_slice(&(facets.len() as u32).to_le_bytes());
+        for (normal, vertices, attribute) in facets {
+            for v in std::iter::once(normal).chain(vertices.iter()) {
+                for x in v {
+                    data.extend_from_slice(&x.to_le_bytes());
+                }
+            }
+            data.extend_from_slice(&attribute.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn ascii_facets_share_vertices_without_normals() {
+        let text = "solid square\n\
+            facet normal 0 0 0\n outer loop\n  vertex 0 0 0\n  vertex 1 0 0\n  vertex 1 1 0\n endloop\nendfacet\n\
+            \n\
+            facet normal 0 0 0\n outer loop\n  vertex 0 0 0\n  vertex 1 1 0\n  vertex 0 1 0\n endloop\nendfacet\n\
+            endsolid square\n";
+        let mesh = parse(text.as_bytes()).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        assert!(mesh.normals.is_empty());
+
+        // Facet normals need vertices of their own
+        let mesh = parse(text.replace("normal 0 0 0", "normal 0 0 2").as_bytes()).unwrap();
+        assert_eq!(mesh.positions.len(), 6);
+        assert_eq!(mesh.normals[4].z(), 1.0);
+    }
+
+    #[test]
+    fn ascii_errors_point_at_the_line() {
+        let text = "solid s\nfacet normal 0 0 1\nouter loop\nvertex 0 0\n";
+        match parse(text.as_bytes()).err().unwrap() {
+            LoadError::Parse { line, .. } => assert_eq!(line, 4),
+            other => panic!("unexpected error {other}"),
+        }
+        assert!(parse(b"solid s\nfacet normal 0 0 1\nouter loop\n").is_err());
+    }
+
+    #[test]
+    fn binary_colors_put_red_in_the_high_bits() {
+        let triangle = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let red = 0x8000 | (31 << 10);
+        let blue = 0x8000 | 31;
+        let uncolored = 16 << 5;
+        let data = binary(&[
+            ([0.0, 0.0, 1.0], triangle, red),
+            ([0.0, 0.0, 1.0], triangle, blue),
+            ([0.0, 0.0, 1.0], triangle, uncolored),
+        ]);
+        let mesh = parse(&data).unwrap();
+        assert_eq!(mesh.indices.len(), 3);
+
+        let colors: Vec<(f64, f64, f64)> = mesh
+            .colors
+            .iter()
+            .step_by(3)
+            .map(|c| (c.x(), c.y(), c.z()))
+            .collect();
+        assert_eq!(
+            colors,
+            vec![(1.0, 0.0, 0.0), (0.0, 0.0, 1.0), (1.0, 1.0, 1.0)]
+        );
+    }
+
+    #[test]
+    fn binary_counts_must_fit_the_file() {
+        let triangle = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let mut data = binary(&[([0.0, 0.0, 1.0], triangle, 0)]);
+        // Claim a second facet that isn't there, with a header that doesn't say "solid"
+        data[0] = b'S';
+        data[80..84].copy_from_slice(&2u32.to_le_bytes());
+        assert!(matches!(parse(&data), Err(LoadError::Data { .. })));
+        data[80..84].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(parse(&data), Err(LoadError::Data { .. })));
+    }
+}
@@ -0,0 +1,255 @@
+use super::color::{srgb_to_linear, Color};
+use super::image::Image;
+use super::load_error::LoadError;
+use super::perlin::Perlin;
+use super::vec3::Point3;
+use std::path::Path;
+use std::sync::Arc;
+
+pub trait Texture {
+    /// Color at surface coordinates u, v of point p
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SolidColor {
+    albedo: Color,
+}
+
+impl SolidColor {
+    pub fn new(albedo: &Color) -> Self {
+        SolidColor {
+            albedo: albedo.to_owned(),
+        }
+    }
+
+    /// Same value in every channel, for scalar parameters such as roughness
+    pub fn new_scalar(value: f64) -> Self {
+        SolidColor::new(&Color::new(value, value, value))
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        self.albedo
+    }
+}
+
+/// Alternating 3D cubes of two textures, `scale` units wide, filling space rather than the
+/// surface so it doesn't depend on UVs
+#[derive(Clone)]
+pub struct CheckerTexture {
+    inv_scale: f64,
+    even: Arc<dyn Texture + Sync + Send>,
+    odd: Arc<dyn Texture + Sync + Send>,
+}
+
+impl CheckerTexture {
+    pub fn new(
+        scale: f64,
+        even: Arc<dyn Texture + Sync + Send>,
+        odd: Arc<dyn Texture + Sync + Send>,
+    ) -> Self {
+        CheckerTexture {
+            inv_scale: 1.0 / scale,
+            even,
+            odd,
+        }
+    }
+
+    pub fn new_colors(scale: f64, c1: &Color, c2: &Color) -> Self {
+        CheckerTexture::new(
+            scale,
+            Arc::new(SolidColor::new(c1)),
+            Arc::new(SolidColor::new(c2)),
+        )
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let x_integer = (self.inv_scale * p.x()).floor() as i64;
+        let y_integer = (self.inv_scale * p.y()).floor() as i64;
+        let z_integer = (self.inv_scale * p.z()).floor() as i64;
+
+        if (x_integer + y_integer + z_integer) % 2 == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+/// What happens to texture coordinates outside of [0, 1]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WrapMode {
+    Repeat,
+    MirroredRepeat,
+    Clamp,
+}
+
+impl WrapMode {
+    /// Map a texel index that may be out of range onto [0, size)
+    fn apply(&self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let wrapped = match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::MirroredRepeat => {
+                let period = i.rem_euclid(2 * size);
+                if period < size {
+                    period
+                } else {
+                    2 * size - 1 - period
+                }
+            }
+            WrapMode::Clamp => i.clamp(0, size - 1),
+        };
+        wrapped as usize
+    }
+}
+
+/// Texture backed by a picture, sampled with bilinear filtering. Texels are kept as linear
+/// colors, v = 0 being the bottom row.
+#[derive(Debug, Clone)]
+pub struct ImageTexture {
+    image: Image,
+    pub wrap_u: WrapMode,
+    pub wrap_v: WrapMode,
+}
+
+impl ImageTexture {
+    pub fn new(image: Image) -> Self {
+        ImageTexture {
+            image,
+            wrap_u: WrapMode::Repeat,
+            wrap_v: WrapMode::Repeat,
+        }
+    }
+
+    /// Load a PNG, JPEG or Radiance HDR file. 8 and 16-bit pictures are assumed to be sRGB
+    /// encoded, HDR ones linear.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        ImageTexture::decode(path.as_ref(), false)
+    }
+
+    /// Load a picture whose values are data rather than colors, such as a normal or height
+    /// map, keeping 8 and 16-bit values as they are instead of decoding sRGB
+    pub fn load_linear<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        ImageTexture::decode(path.as_ref(), true)
+    }
+
+    fn decode(path: &Path, assume_linear: bool) -> Result<Self, LoadError> {
+        let file = path.display().to_string();
+        let decoded = ::image::open(path).map_err(|err| match err {
+            ::image::ImageError::IoError(err) => LoadError::Io(err),
+            err => LoadError::Decode {
+                file: file.clone(),
+                message: err.to_string(),
+            },
+        })?;
+
+        let is_linear = assume_linear
+            || matches!(
+                decoded,
+                ::image::DynamicImage::ImageRgb32F(_) | ::image::DynamicImage::ImageRgba32F(_)
+            );
+        let rgb = decoded.into_rgb32f();
+        let (width, height) = (rgb.width() as usize, rgb.height() as usize);
+        if width == 0 || height == 0 {
+            return Err(LoadError::Decode {
+                file,
+                message: String::from("image has no pixels"),
+            });
+        }
+
+        let mut image = Image::new(width, height);
+        for (x, y, pixel) in rgb.enumerate_pixels() {
+            let [r, g, b] = pixel.0.map(|c| {
+                if is_linear {
+                    c as f64
+                } else {
+                    srgb_to_linear(c as f64)
+                }
+            });
+            image.set_pixel(x as usize, y as usize, Color::new(r, g, b), 1);
+        }
+        Ok(ImageTexture::new(image))
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        let (width, height) = (self.image.width(), self.image.height());
+        if width == 0 || height == 0 {
+            // Debugging aid, cyan makes missing textures obvious
+            return Color::new(0.0, 1.0, 1.0);
+        }
+
+        // Continuous texel coordinates with texel centers on half integers
+        let x = u * (width as f64) - 0.5;
+        let y = (1.0 - v) * (height as f64) - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+
+        let texel = |i: f64, j: f64| {
+            self.image.pixel(
+                self.wrap_u.apply(i as i64, width),
+                self.wrap_v.apply(j as i64, height),
+            )
+        };
+        let top = texel(x0, y0) * (1.0 - tx) + texel(x0 + 1.0, y0) * tx;
+        let bottom = texel(x0, y0 + 1.0) * (1.0 - tx) + texel(x0 + 1.0, y0 + 1.0) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseKind {
+    /// Plain Perlin noise, remapped to [0, 1]
+    Smooth,
+    /// Sum of noise octaves, giving a cloudy look
+    Turbulence,
+    /// Sine bands along z, perturbed by turbulence
+    Marble,
+}
+
+#[derive(Debug, Clone)]
+pub struct NoiseTexture {
+    noise: Perlin,
+    scale: f64,
+    kind: NoiseKind,
+    color: Color,
+}
+
+impl NoiseTexture {
+    pub fn new(scale: f64, kind: NoiseKind) -> Self {
+        NoiseTexture::new_color(scale, kind, &Color::new(1.0, 1.0, 1.0))
+    }
+
+    /// Noise modulating color instead of white
+    pub fn new_color(scale: f64, kind: NoiseKind, color: &Color) -> Self {
+        NoiseTexture {
+            noise: Perlin::new(),
+            scale,
+            kind,
+            color: color.to_owned(),
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        let intensity = match self.kind {
+            NoiseKind::Smooth => 0.5 * (1.0 + self.noise.noise(&(*p * self.scale))),
+            NoiseKind::Turbulence => self.noise.turb(&(*p * self.scale), 7),
+            NoiseKind::Marble => {
+                0.5 * (1.0 + (self.scale * p.z() + 10.0 * self.noise.turb(p, 7)).sin())
+            }
+        };
+        self.color * intensity
+    }
+}
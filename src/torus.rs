@@ -0,0 +1,235 @@
+use super::aabb::Aabb;
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::Material;
+use super::onb::Onb;
+use super::poly::solve_quartic;
+use super::ray::Ray;
+use super::rtweekend::PI;
+use super::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Ring around center, in the plane perpendicular to axis. The tube of radius minor_radius
+/// runs major_radius away from the center. u is the angle around the axis and v the angle
+/// around the tube, both in [0, 1].
+pub struct Torus {
+    center: Point3,
+    major_radius: f64,
+    minor_radius: f64,
+    mat: Arc<Box<dyn Material + Sync + Send>>,
+    /// Frame whose w is the axis, hits are computed in it
+    frame: Onb,
+    bbox: Aabb,
+}
+
+impl Torus {
+    pub fn new(
+        center: Point3,
+        axis: Vec3,
+        major_radius: f64,
+        minor_radius: f64,
+        m: Box<dyn Material + Sync + Send>,
+    ) -> Self {
+        let frame = Onb::new_from_w(&axis);
+        let w = frame.w;
+
+        // The ring spans major + minor radius within its plane and the minor radius along
+        // the axis
+        let ring = major_radius + minor_radius;
+        let extent_along = |c: f64| ring * (1.0 - c * c).max(0.0).sqrt() + minor_radius * c.abs();
+        let extent = Vec3::new(
+            extent_along(w.x()),
+            extent_along(w.y()),
+            extent_along(w.z()),
+        );
+
+        Torus {
+            center,
+            major_radius,
+            minor_radius,
+            mat: Arc::new(m),
+            frame,
+            bbox: Aabb::new_points(&(center - extent), &(center + extent)),
+        }
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let direction_length = r.direction().length();
+        let d = self.frame.to_local(&r.direction()) / direction_length;
+        let mut o = self.frame.to_local(&(r.origin() - self.center));
+
+        // Skip rays missing the bounding sphere, and move the origin of the others up to it.
+        // The quartic coefficients grow with the fourth power of the distance, so solving
+        // from far away loses most of the precision.
+        let bounding_radius = self.major_radius + self.minor_radius;
+        let half_b = Vec3::dot(&o, &d);
+        let discriminant =
+            half_b * half_b - (o.length_squared() - bounding_radius * bounding_radius);
+        if discriminant < 0.0 {
+            return false;
+        }
+        let shift = -half_b - discriminant.sqrt();
+        o = o + d * shift;
+
+        // Expanding (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + y^2) along the ray
+        let major_squared = self.major_radius * self.major_radius;
+        let minor_squared = self.minor_radius * self.minor_radius;
+        let e = o.length_squared() - major_squared - minor_squared;
+        let f = Vec3::dot(&o, &d);
+        let (roots, count) = solve_quartic(
+            1.0,
+            4.0 * f,
+            2.0 * e + 4.0 * f * f + 4.0 * major_squared * d.z() * d.z(),
+            4.0 * f * e + 8.0 * major_squared * o.z() * d.z(),
+            e * e - 4.0 * major_squared * (minor_squared - o.z() * o.z()),
+        );
+
+        // Roots are distances along the unit direction from the shifted origin
+        let t = roots[..count]
+            .iter()
+            .map(|&s| (s + shift) / direction_length)
+            .filter(|&t| ray_t.surrounds(t))
+            .min_by(f64::total_cmp);
+        let t = match t {
+            Some(t) => t,
+            None => return false,
+        };
+
+        let local = self.frame.to_local(&(r.at(t) - self.center));
+        let k = local.length_squared() - major_squared - minor_squared;
+        let normal = Vec3::new(
+            local.x() * k,
+            local.y() * k,
+            local.z() * (k + 2.0 * major_squared),
+        );
+
+        let ring_distance = (local.x() * local.x() + local.y() * local.y()).sqrt();
+        rec.u = (local.y().atan2(local.x()) + PI) / (2.0 * PI);
+        rec.v = (local.z().atan2(ring_distance - self.major_radius) + PI) / (2.0 * PI);
+
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.mat = self.mat.clone();
+        rec.vertex_color = None;
+        rec.set_face_normal(r, &Vec3::unit_vector(&self.frame.local(&normal)));
+        let dpdu = Vec3::new(-local.y(), local.x(), 0.0) * (2.0 * PI);
+        let ring_offset = ring_distance - self.major_radius;
+        let outwards = Vec3::new(local.x(), local.y(), 0.0) / ring_distance.max(1e-8);
+        let dpdv = (outwards * -local.z() + Vec3::new(0.0, 0.0, ring_offset)) * (2.0 * PI);
+        rec.set_derivatives(&self.frame.local(&dpdu), &self.frame.local(&dpdv));
+
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::Lambertian;
+    use crate::rtweekend::{random_double, INFINITY};
+
+    const MAJOR: f64 = 2.0;
+    const MINOR: f64 = 0.5;
+
+    fn torus(center: Point3, axis: Vec3) -> Torus {
+        Torus::new(
+            center,
+            axis,
+            MAJOR,
+            MINOR,
+            Box::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5))),
+        )
+    }
+
+    fn hit(torus: &Torus, origin: Point3, direction: Vec3) -> Option<HitRecord> {
+        let mut rec = HitRecord::default();
+        let r = Ray::new(&origin, &direction);
+        if torus.hit(&r, Interval::new_val(0.001, INFINITY), &mut rec) {
+            Some(rec)
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn known_crossings() {
+        let ring = torus(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0));
+
+        // Through the middle of the ring, the first of four crossings is the outer edge
+        let rec = hit(
+            &ring,
+            Point3::new(-10.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+        )
+        .unwrap();
+        assert!((rec.t - (10.0 - MAJOR - MINOR) / 2.0).abs() < 1e-9);
+        assert!((rec.normal.x() + 1.0).abs() < 1e-9);
+
+        // Starting in the hole, the inner edge is next
+        let rec = hit(&ring, Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)).unwrap();
+        assert!((rec.t - (MAJOR - MINOR)).abs() < 1e-9);
+        assert!((rec.normal.y() + 1.0).abs() < 1e-9);
+
+        // Down the axis and just above the tube there is nothing to hit
+        assert!(hit(&ring, Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).is_none());
+        let above = Point3::new(-10.0, 0.0, MINOR + 1e-6);
+        assert!(hit(&ring, above, Vec3::new(1.0, 0.0, 0.0)).is_none());
+
+        // Straight down onto the top of the tube
+        let rec = hit(
+            &ring,
+            Point3::new(0.0, MAJOR, 3.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        )
+        .unwrap();
+        assert!((rec.t - (3.0 - MINOR)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn hits_lie_on_the_surface() {
+        let center = Point3::new(1.0, -2.0, 0.5);
+        let axis = Vec3::unit_vector(&Vec3::new(0.3, 1.0, -0.2));
+        let ring = torus(center, axis);
+        let frame = Onb::new_from_w(&axis);
+
+        let mut hits = 0;
+        for i in 0..2_000 {
+            // Some rays start far away, where the quartic is hardest to solve accurately
+            let distance = if i % 2 == 0 { 6.0 } else { 500.0 };
+            let origin = center + Vec3::random_unit_vector() * distance;
+            let target = center
+                + Vec3::new(
+                    random_double() - 0.5,
+                    random_double() - 0.5,
+                    random_double() - 0.5,
+                ) * 6.0;
+            let rec = match hit(&ring, origin, target - origin) {
+                Some(rec) => rec,
+                None => continue,
+            };
+            hits += 1;
+
+            let local = frame.to_local(&(rec.p - center));
+            let ring_distance = (local.x() * local.x() + local.y() * local.y()).sqrt();
+            let tube = ((ring_distance - MAJOR).powi(2) + local.z() * local.z()).sqrt();
+            assert!((tube - MINOR).abs() < 1e-6, "{tube} at distance {distance}");
+
+            // The normal points from the middle of the tube to the hit point
+            let tube_center = center
+                + frame.local(&(Vec3::new(local.x(), local.y(), 0.0) * (MAJOR / ring_distance)));
+            let outward = Vec3::unit_vector(&(rec.p - tube_center));
+            let facing = if rec.front_face { 1.0 } else { -1.0 };
+            assert!((rec.normal * facing - outward).length() < 1e-6);
+            let bbox = ring.bounding_box();
+            assert!((0..3).all(|a| bbox.axis(a).expand(1e-6).contains(rec.p.axis(a))));
+        }
+        assert!(hits > 200);
+    }
+}
@@ -0,0 +1,793 @@
+use super::aabb::{self, Aabb};
+use super::interval::Interval;
+use super::ray::Ray;
+use super::rtweekend::degrees_to_radians;
+use super::vec3::{Point3, Vec3};
+use std::ops::Mul;
+
+/// Row-major 4x4 matrix, points being column vectors
+pub type Matrix4 = [[f64; 4]; 4];
+
+pub const IDENTITY: Matrix4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Affine transform, stored together with its inverse so both directions are cheap.
+/// Transforms compose like matrices: `a * b` applies b first, then a.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    m: Matrix4,
+    inv: Matrix4,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+impl Transform {
+    pub const fn identity() -> Self {
+        Transform {
+            m: IDENTITY,
+            inv: IDENTITY,
+        }
+    }
+
+    /// Build a transform from a matrix, returning None when it can't be inverted
+    pub fn new(m: Matrix4) -> Option<Self> {
+        let inv = invert(&m)?;
+        Some(Transform { m, inv })
+    }
+
+    pub fn translate(offset: &Vec3) -> Self {
+        let (x, y, z) = (offset.x(), offset.y(), offset.z());
+        Transform {
+            m: [
+                [1.0, 0.0, 0.0, x],
+                [0.0, 1.0, 0.0, y],
+                [0.0, 0.0, 1.0, z],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+            inv: [
+                [1.0, 0.0, 0.0, -x],
+                [0.0, 1.0, 0.0, -y],
+                [0.0, 0.0, 1.0, -z],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    /// Scale by a factor per axis, none of them may be zero
+    pub fn scale(factors: &Vec3) -> Self {
+        let (x, y, z) = (factors.x(), factors.y(), factors.z());
+        Transform {
+            m: [
+                [x, 0.0, 0.0, 0.0],
+                [0.0, y, 0.0, 0.0],
+                [0.0, 0.0, z, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+            inv: [
+                [1.0 / x, 0.0, 0.0, 0.0],
+                [0.0, 1.0 / y, 0.0, 0.0],
+                [0.0, 0.0, 1.0 / z, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn uniform_scale(factor: f64) -> Self {
+        Transform::scale(&Vec3::new(factor, factor, factor))
+    }
+
+    pub fn rotate_x(degrees: f64) -> Self {
+        Transform::rotate(&Vec3::new(1.0, 0.0, 0.0), degrees)
+    }
+
+    pub fn rotate_y(degrees: f64) -> Self {
+        Transform::rotate(&Vec3::new(0.0, 1.0, 0.0), degrees)
+    }
+
+    pub fn rotate_z(degrees: f64) -> Self {
+        Transform::rotate(&Vec3::new(0.0, 0.0, 1.0), degrees)
+    }
+
+    /// Counter-clockwise rotation around axis, looking down the axis towards the origin
+    pub fn rotate(axis: &Vec3, degrees: f64) -> Self {
+        let a = Vec3::unit_vector(axis);
+        let (sin_theta, cos_theta) = degrees_to_radians(degrees).sin_cos();
+        let one_minus_cos = 1.0 - cos_theta;
+        let (x, y, z) = (a.x(), a.y(), a.z());
+
+        let m = [
+            [
+                x * x * one_minus_cos + cos_theta,
+                x * y * one_minus_cos - z * sin_theta,
+                x * z * one_minus_cos + y * sin_theta,
+                0.0,
+            ],
+            [
+                x * y * one_minus_cos + z * sin_theta,
+                y * y * one_minus_cos + cos_theta,
+                y * z * one_minus_cos - x * sin_theta,
+                0.0,
+            ],
+            [
+                x * z * one_minus_cos - y * sin_theta,
+                y * z * one_minus_cos + x * sin_theta,
+                z * z * one_minus_cos + cos_theta,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        // Rotations are orthogonal, the inverse is the transpose
+        Transform {
+            m,
+            inv: transpose(&m),
+        }
+    }
+
+    pub fn inverse(&self) -> Self {
+        Transform {
+            m: self.inv,
+            inv: self.m,
+        }
+    }
+
+    pub fn matrix(&self) -> &Matrix4 {
+        &self.m
+    }
+
+    pub fn inverse_matrix(&self) -> &Matrix4 {
+        &self.inv
+    }
+
+    /// Inverse transpose of the upper 3x3 part, which is what keeps normals perpendicular
+    /// to transformed surfaces
+    pub fn normal_matrix(&self) -> [[f64; 3]; 3] {
+        let mut n = [[0.0; 3]; 3];
+        for (i, row) in n.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.inv[j][i];
+            }
+        }
+        n
+    }
+
+    /// Determinant of the linear part, how much the transform scales volumes
+    pub fn determinant(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn point(&self, p: &Point3) -> Point3 {
+        apply_point(&self.m, p)
+    }
+
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        apply_vector(&self.m, v)
+    }
+
+    /// Transform a surface normal, the result is not normalized
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        // Multiplying by the transposed inverse is the same as using the inverse on the left
+        let inv = &self.inv;
+        Vec3::new(
+            inv[0][0] * n.x() + inv[1][0] * n.y() + inv[2][0] * n.z(),
+            inv[0][1] * n.x() + inv[1][1] * n.y() + inv[2][1] * n.z(),
+            inv[0][2] * n.x() + inv[1][2] * n.y() + inv[2][2] * n.z(),
+        )
+    }
+
+    /// Transform a ray without normalizing its direction, so the ray parameter t of a hit
+    /// is the same on both sides of the transform
+    pub fn ray(&self, r: &Ray) -> Ray {
+        Ray::new_time(
+            &self.point(&r.origin()),
+            &self.vector(&r.direction()),
+            r.time(),
+        )
+    }
+
+    pub fn inverse_point(&self, p: &Point3) -> Point3 {
+        apply_point(&self.inv, p)
+    }
+
+    pub fn inverse_vector(&self, v: &Vec3) -> Vec3 {
+        apply_vector(&self.inv, v)
+    }
+
+    pub fn inverse_ray(&self, r: &Ray) -> Ray {
+        Ray::new_time(
+            &self.inverse_point(&r.origin()),
+            &self.inverse_vector(&r.direction()),
+            r.time(),
+        )
+    }
+
+    /// Box enclosing the transformed box. Each axis is accumulated from the extreme
+    /// contributions of every matrix entry instead of transforming the eight corners, which
+    /// keeps unbounded boxes from producing NaNs.
+    pub fn bounding_box(&self, bbox: &Aabb) -> Aabb {
+        if bbox.is_empty() {
+            return aabb::EMPTY;
+        }
+
+        let mut axes = [Interval::new_val(0.0, 0.0); 3];
+        for (i, axis) in axes.iter_mut().enumerate() {
+            let mut min = self.m[i][3];
+            let mut max = self.m[i][3];
+            for j in 0..3 {
+                let factor = self.m[i][j];
+                if factor == 0.0 {
+                    continue;
+                }
+                let a = factor * bbox.axis(j).min;
+                let b = factor * bbox.axis(j).max;
+                min += a.min(b);
+                max += a.max(b);
+            }
+            *axis = Interval::new_val(min, max);
+        }
+        Aabb::new(axes[0], axes[1], axes[2])
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, rhs: Transform) -> Transform {
+        Transform {
+            m: multiply(&self.m, &rhs.m),
+            inv: multiply(&rhs.inv, &self.inv),
+        }
+    }
+}
+
+/// Number of steps each keyframe interval is cut into when bounding the motion
+const MOTION_BOUND_STEPS: usize = 64;
+
+/// Transform changing over time, interpolated between keyframes and held before the first and
+/// after the last. Every keyframe is split into a translation, a rotation and what's left of
+/// the matrix (scale and shear), which are interpolated separately, so rotating objects stay
+/// rigid instead of shrinking and shearing halfway like with interpolated matrices.
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    /// Sorted by time
+    keyframes: Vec<Keyframe>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Keyframe {
+    time: f64,
+    transform: Transform,
+    translation: Vec3,
+    rotation: Quaternion,
+    scale: Matrix3,
+}
+
+impl AnimatedTransform {
+    ///
+    /// Transform going through the given keyframes
+    /// * `keyframes` - Time and transform pairs, in any order, at least one
+    pub fn new(keyframes: Vec<(f64, Transform)>) -> Self {
+        assert!(
+            !keyframes.is_empty(),
+            "an animated transform needs a keyframe"
+        );
+        let mut keyframes: Vec<Keyframe> = keyframes
+            .into_iter()
+            .map(|(time, transform)| {
+                let (translation, rotation, scale) = decompose(&transform.m);
+                Keyframe {
+                    time,
+                    transform,
+                    translation,
+                    rotation,
+                    scale,
+                }
+            })
+            .collect();
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        AnimatedTransform { keyframes }
+    }
+
+    /// Transform going from start at time0 to end at time1
+    pub fn between(start: Transform, end: Transform, time0: f64, time1: f64) -> Self {
+        AnimatedTransform::new(vec![(time0, start), (time1, end)])
+    }
+
+    /// False when there is a single keyframe and the transform never changes
+    pub fn is_animated(&self) -> bool {
+        self.keyframes.len() > 1
+    }
+
+    /// Transform of the first keyframe
+    pub fn start(&self) -> Transform {
+        self.keyframes[0].transform
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return self.keyframes[0].transform;
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes[next - 1].transform;
+        }
+
+        let k0 = &self.keyframes[next - 1];
+        let k1 = &self.keyframes[next];
+        let s = (time - k0.time) / (k1.time - k0.time);
+        interpolate(k0, k1, s)
+    }
+
+    /// Box enclosing bbox over the whole motion. The box is transformed at many times along
+    /// every keyframe interval, and padded by the most any corner moves between two of those
+    /// times to cover what happens in between.
+    pub fn bounding_box(&self, bbox: &Aabb) -> Aabb {
+        let mut motion_box = self.keyframes[0].transform.bounding_box(bbox);
+        if bbox.is_empty() {
+            return motion_box;
+        }
+
+        let corners: Vec<Point3> = (0..8)
+            .map(|i| {
+                Point3::new(
+                    if i & 1 == 0 { bbox.x.min } else { bbox.x.max },
+                    if i & 2 == 0 { bbox.y.min } else { bbox.y.max },
+                    if i & 4 == 0 { bbox.z.min } else { bbox.z.max },
+                )
+            })
+            .collect();
+        let finite = corners
+            .iter()
+            .all(|c| c.x().is_finite() && c.y().is_finite() && c.z().is_finite());
+
+        let mut padding: f64 = 0.0;
+        for pair in self.keyframes.windows(2) {
+            let mut previous = pair[0].transform;
+            for step in 1..=MOTION_BOUND_STEPS {
+                let s = step as f64 / MOTION_BOUND_STEPS as f64;
+                let current = interpolate(&pair[0], &pair[1], s);
+                motion_box = Aabb::new_boxes(&motion_box, &current.bounding_box(bbox));
+                if finite {
+                    for corner in &corners {
+                        let moved = current.point(corner) - previous.point(corner);
+                        padding = padding.max(moved.length());
+                    }
+                }
+                previous = current;
+            }
+        }
+
+        Aabb::new(
+            motion_box.x.expand(2.0 * padding),
+            motion_box.y.expand(2.0 * padding),
+            motion_box.z.expand(2.0 * padding),
+        )
+    }
+}
+
+/// Row-major 3x3 matrix, the linear part of a transform
+type Matrix3 = [[f64; 3]; 3];
+
+/// Unit quaternion w + xi + yj + zk, a rotation
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Quaternion {
+    w: f64,
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl Quaternion {
+    fn from_matrix(r: &Matrix3) -> Self {
+        // Divide by the largest of the four candidates to stay accurate near 180 degrees
+        let trace = r[0][0] + r[1][1] + r[2][2];
+        let (w, x, y, z) = if trace > 0.0 {
+            let s = 2.0 * (trace + 1.0).sqrt();
+            (
+                0.25 * s,
+                (r[2][1] - r[1][2]) / s,
+                (r[0][2] - r[2][0]) / s,
+                (r[1][0] - r[0][1]) / s,
+            )
+        } else if r[0][0] > r[1][1] && r[0][0] > r[2][2] {
+            let s = 2.0 * (1.0 + r[0][0] - r[1][1] - r[2][2]).sqrt();
+            (
+                (r[2][1] - r[1][2]) / s,
+                0.25 * s,
+                (r[0][1] + r[1][0]) / s,
+                (r[0][2] + r[2][0]) / s,
+            )
+        } else if r[1][1] > r[2][2] {
+            let s = 2.0 * (1.0 + r[1][1] - r[0][0] - r[2][2]).sqrt();
+            (
+                (r[0][2] - r[2][0]) / s,
+                (r[0][1] + r[1][0]) / s,
+                0.25 * s,
+                (r[1][2] + r[2][1]) / s,
+            )
+        } else {
+            let s = 2.0 * (1.0 + r[2][2] - r[0][0] - r[1][1]).sqrt();
+            (
+                (r[1][0] - r[0][1]) / s,
+                (r[0][2] + r[2][0]) / s,
+                (r[1][2] + r[2][1]) / s,
+                0.25 * s,
+            )
+        };
+        Quaternion { w, x, y, z }
+    }
+
+    fn to_matrix(self) -> Matrix3 {
+        let Quaternion { w, x, y, z } = self;
+        [
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - w * z),
+                2.0 * (x * z + w * y),
+            ],
+            [
+                2.0 * (x * y + w * z),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - w * x),
+            ],
+            [
+                2.0 * (x * z - w * y),
+                2.0 * (y * z + w * x),
+                1.0 - 2.0 * (x * x + y * y),
+            ],
+        ]
+    }
+
+    /// Rotation a fraction s of the way from self to other, along the shorter arc
+    fn slerp(self, other: Quaternion, s: f64) -> Self {
+        let mut dot = self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z;
+        let mut other = other;
+        if dot < 0.0 {
+            dot = -dot;
+            other = Quaternion {
+                w: -other.w,
+                x: -other.x,
+                y: -other.y,
+                z: -other.z,
+            };
+        }
+
+        // Nearly identical rotations would divide by a vanishing sine
+        let (a, b) = if dot > 0.9995 {
+            (1.0 - s, s)
+        } else {
+            let theta = dot.acos();
+            let sin_theta = theta.sin();
+            (
+                ((1.0 - s) * theta).sin() / sin_theta,
+                (s * theta).sin() / sin_theta,
+            )
+        };
+        let q = Quaternion {
+            w: a * self.w + b * other.w,
+            x: a * self.x + b * other.x,
+            y: a * self.y + b * other.y,
+            z: a * self.z + b * other.z,
+        };
+        let length = (q.w * q.w + q.x * q.x + q.y * q.y + q.z * q.z).sqrt();
+        Quaternion {
+            w: q.w / length,
+            x: q.x / length,
+            y: q.y / length,
+            z: q.z / length,
+        }
+    }
+}
+
+/// Split an affine matrix into translation, rotation and the remaining scale and shear, so
+/// that m = T R S. R is the closest rotation to the linear part, found by polar decomposition.
+fn decompose(m: &Matrix4) -> (Vec3, Quaternion, Matrix3) {
+    let translation = Vec3::new(m[0][3], m[1][3], m[2][3]);
+    let mut linear = [[0.0; 3]; 3];
+    for (i, row) in linear.iter_mut().enumerate() {
+        row.copy_from_slice(&m[i][..3]);
+    }
+
+    // Averaging a matrix with its inverse transpose converges to its orthogonal factor
+    let mut r = linear;
+    for _ in 0..100 {
+        let inverse = match invert(&embed(&r)) {
+            Some(inverse) => inverse,
+            None => break,
+        };
+        let mut next = [[0.0; 3]; 3];
+        let mut change: f64 = 0.0;
+        for i in 0..3 {
+            for j in 0..3 {
+                next[i][j] = 0.5 * (r[i][j] + inverse[j][i]);
+                change = change.max((next[i][j] - r[i][j]).abs());
+            }
+        }
+        r = next;
+        if change < 1e-12 {
+            break;
+        }
+    }
+
+    // A mirroring transform leaves the mirroring to the scale, quaternions can't hold it
+    if determinant3(&r) < 0.0 {
+        for row in r.iter_mut() {
+            for value in row.iter_mut() {
+                *value = -*value;
+            }
+        }
+    }
+
+    // R is orthogonal, so S = R^T M
+    let mut scale = [[0.0; 3]; 3];
+    for (i, row) in scale.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| r[k][i] * linear[k][j]).sum();
+        }
+    }
+
+    (translation, Quaternion::from_matrix(&r), scale)
+}
+
+/// Transform a fraction s of the way from k0 to k1
+fn interpolate(k0: &Keyframe, k1: &Keyframe, s: f64) -> Transform {
+    let translation = k0.translation + (k1.translation - k0.translation) * s;
+    let rotation = k0.rotation.slerp(k1.rotation, s).to_matrix();
+
+    let mut m = IDENTITY;
+    for (i, row) in m.iter_mut().take(3).enumerate() {
+        for (j, value) in row.iter_mut().take(3).enumerate() {
+            *value = (0..3)
+                .map(|k| rotation[i][k] * (k0.scale[k][j] + (k1.scale[k][j] - k0.scale[k][j]) * s))
+                .sum();
+        }
+    }
+    m[0][3] = translation.x();
+    m[1][3] = translation.y();
+    m[2][3] = translation.z();
+
+    // The scale can pass through zero on the way, fall back to the closer keyframe there
+    Transform::new(m).unwrap_or(if s < 0.5 { k0.transform } else { k1.transform })
+}
+
+fn embed(m: &Matrix3) -> Matrix4 {
+    let mut out = IDENTITY;
+    for i in 0..3 {
+        out[i][..3].copy_from_slice(&m[i]);
+    }
+    out
+}
+
+fn determinant3(m: &Matrix3) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+fn apply_point(m: &Matrix4, p: &Point3) -> Point3 {
+    apply_vector(m, p) + Vec3::new(m[0][3], m[1][3], m[2][3])
+}
+
+fn apply_vector(m: &Matrix4, v: &Vec3) -> Vec3 {
+    Vec3::new(
+        m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+        m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+        m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+    )
+}
+
+fn multiply(a: &Matrix4, b: &Matrix4) -> Matrix4 {
+    let mut out = [[0.0; 4]; 4];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+fn transpose(m: &Matrix4) -> Matrix4 {
+    let mut out = [[0.0; 4]; 4];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = m[j][i];
+        }
+    }
+    out
+}
+
+/// Gauss-Jordan elimination with partial pivoting
+fn invert(m: &Matrix4) -> Option<Matrix4> {
+    let mut a = *m;
+    let mut inv = IDENTITY;
+
+    for col in 0..4 {
+        let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+
+        let scale = 1.0 / a[col][col];
+        for j in 0..4 {
+            a[col][j] *= scale;
+            inv[col][j] *= scale;
+        }
+
+        for row in 0..4 {
+            if row == col {
+                continue;
+            }
+            let factor = a[row][col];
+            if factor == 0.0 {
+                continue;
+            }
+            for j in 0..4 {
+                a[row][j] -= factor * a[col][j];
+                inv[row][j] -= factor * inv[col][j];
+            }
+        }
+    }
+    Some(inv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-9;
+
+    fn assert_close(a: &Matrix4, b: &Matrix4) {
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((a[i][j] - b[i][j]).abs() < EPSILON, "{a:?} != {b:?}");
+            }
+        }
+    }
+
+    fn linear_part(m: &Matrix4) -> Matrix3 {
+        let mut out = [[0.0; 3]; 3];
+        for (i, row) in out.iter_mut().enumerate() {
+            row.copy_from_slice(&m[i][..3]);
+        }
+        out
+    }
+
+    /// Put T R S back together from what decompose returned
+    fn recompose(translation: Vec3, rotation: Quaternion, scale: &Matrix3) -> Matrix4 {
+        let r = embed(&rotation.to_matrix());
+        let mut m = multiply(&r, &embed(scale));
+        m[0][3] = translation.x();
+        m[1][3] = translation.y();
+        m[2][3] = translation.z();
+        m
+    }
+
+    #[test]
+    fn decomposition_recomposes() {
+        let shear = Transform::new([
+            [1.0, 0.4, 0.0, 0.0],
+            [0.0, 1.0, -0.3, 0.0],
+            [0.2, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+        .unwrap();
+        let cases = [
+            Transform::identity(),
+            Transform::translate(&Vec3::new(1.0, -2.0, 3.0)) * Transform::rotate_y(30.0),
+            Transform::rotate(&Vec3::new(1.0, 2.0, -1.0), 179.9)
+                * Transform::scale(&Vec3::new(2.0, 0.5, 3.0)),
+            Transform::rotate_x(180.0) * Transform::uniform_scale(4.0),
+            Transform::rotate_z(-75.0) * shear,
+            Transform::rotate_x(40.0) * Transform::scale(&Vec3::new(-1.0, 1.0, 1.0)),
+        ];
+
+        for transform in cases {
+            let (translation, rotation, scale) = decompose(transform.matrix());
+            assert_close(
+                &recompose(translation, rotation, &scale),
+                transform.matrix(),
+            );
+
+            // The rotation is a proper rotation, and the quaternion a unit one
+            let r = rotation.to_matrix();
+            assert!((determinant3(&r) - 1.0).abs() < EPSILON);
+            let q = rotation;
+            let norm = q.w * q.w + q.x * q.x + q.y * q.y + q.z * q.z;
+            assert!((norm - 1.0).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn rotation_and_scale_separate() {
+        let rotation = Transform::rotate(&Vec3::new(0.0, 1.0, 1.0), 110.0);
+        let m = rotation * Transform::scale(&Vec3::new(2.0, 3.0, 0.5));
+        let (_, q, scale) = decompose(m.matrix());
+        assert_close(&embed(&q.to_matrix()), rotation.matrix());
+        assert_close(
+            &embed(&scale),
+            Transform::scale(&Vec3::new(2.0, 3.0, 0.5)).matrix(),
+        );
+        assert_close(
+            &embed(&linear_part(m.matrix())),
+            &multiply(&embed(&q.to_matrix()), &embed(&scale)),
+        );
+    }
+
+    #[test]
+    fn slerp_follows_the_arc() {
+        let q = |degrees: f64| {
+            Quaternion::from_matrix(&linear_part(Transform::rotate_z(degrees).matrix()))
+        };
+        let (start, end) = (q(10.0), q(130.0));
+
+        assert_close(
+            &embed(&start.slerp(end, 0.0).to_matrix()),
+            Transform::rotate_z(10.0).matrix(),
+        );
+        assert_close(
+            &embed(&start.slerp(end, 1.0).to_matrix()),
+            Transform::rotate_z(130.0).matrix(),
+        );
+        for s in [0.25, 0.5, 0.8] {
+            let expected = Transform::rotate_z(10.0 + 120.0 * s);
+            assert_close(&embed(&start.slerp(end, s).to_matrix()), expected.matrix());
+        }
+
+        // -q is the same rotation, the shorter arc is still taken
+        let flipped = Quaternion {
+            w: -end.w,
+            x: -end.x,
+            y: -end.y,
+            z: -end.z,
+        };
+        assert_close(
+            &embed(&start.slerp(flipped, 0.5).to_matrix()),
+            Transform::rotate_z(70.0).matrix(),
+        );
+
+        // Going from 170 to -170 degrees passes through 180, not through 0
+        let across = q(170.0).slerp(q(-170.0), 0.5);
+        assert_close(
+            &embed(&across.to_matrix()),
+            Transform::rotate_z(180.0).matrix(),
+        );
+
+        // Nearly equal rotations take the linear path and stay normalized
+        let close = q(20.0).slerp(q(20.001), 0.5);
+        assert_close(
+            &embed(&close.to_matrix()),
+            Transform::rotate_z(20.0005).matrix(),
+        );
+    }
+
+    #[test]
+    fn animated_rotation_stays_rigid() {
+        let motion = AnimatedTransform::between(
+            Transform::translate(&Vec3::new(0.0, 1.0, 0.0)),
+            Transform::translate(&Vec3::new(4.0, 1.0, 0.0)) * Transform::rotate_y(150.0),
+            0.0,
+            1.0,
+        );
+        let p = Point3::new(1.0, 0.0, 0.0);
+        for step in 0..=10 {
+            let time = step as f64 / 10.0;
+            let transform = motion.at(time);
+            let center = transform.point(&Point3::new(0.0, 0.0, 0.0));
+            assert!((center.x() - 4.0 * time).abs() < EPSILON);
+            assert!(((transform.point(&p) - center).length() - 1.0).abs() < EPSILON);
+        }
+        assert_close(motion.at(-1.0).matrix(), motion.start().matrix());
+    }
+}
@@ -0,0 +1,570 @@
+use super::aabb::Aabb;
+use super::color::Color;
+use super::flat_bvh::{FlatBvh, SplitMethod};
+use super::hittable::{HitRecord, Hittable};
+use super::interval::Interval;
+use super::material::Material;
+use super::ray::Ray;
+use super::rtweekend::{random_double, INFINITY};
+use super::vec3::{Point3, Vec3};
+use std::sync::Arc;
+
+/// Flat triangles get a thin box so the slab test still works on axis-aligned ones
+pub(crate) const BBOX_PADDING: f64 = 1e-4;
+
+///
+/// Watertight ray/triangle intersection (Woop, Benthin and Wald), which never lets a ray slip
+/// through the shared edge of two neighbouring triangles.
+/// * `r` - Ray
+/// * `ray_t` - Ray Interval
+/// * `p0`, `p1`, `p2` - Triangle vertices
+/// # Returns
+/// Ray parameter and barycentric weights of p1 and p2 if the triangle is hit
+pub fn intersect_triangle(
+    r: &Ray,
+    ray_t: Interval,
+    p0: &Point3,
+    p1: &Point3,
+    p2: &Point3,
+) -> Option<(f64, f64, f64)> {
+    let dir = r.direction();
+    let orig = r.origin();
+
+    // Permute the axes so the ray travels mostly along z, then shear it onto +z
+    let kz = if dir.x().abs() > dir.y().abs() {
+        if dir.x().abs() > dir.z().abs() {
+            0
+        } else {
+            2
+        }
+    } else if dir.y().abs() > dir.z().abs() {
+        1
+    } else {
+        2
+    };
+    let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+    if dir.axis(kz) < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    let sz = 1.0 / dir.axis(kz);
+    let sx = dir.axis(kx) * sz;
+    let sy = dir.axis(ky) * sz;
+
+    let a = *p0 - orig;
+    let b = *p1 - orig;
+    let c = *p2 - orig;
+
+    let ax = a.axis(kx) - sx * a.axis(kz);
+    let ay = a.axis(ky) - sy * a.axis(kz);
+    let bx = b.axis(kx) - sx * b.axis(kz);
+    let by = b.axis(ky) - sy * b.axis(kz);
+    let cx = c.axis(kx) - sx * c.axis(kz);
+    let cy = c.axis(ky) - sy * c.axis(kz);
+
+    // Scaled barycentric coordinates, the signs must agree for the ray to be inside
+    let u = cx * by - cy * bx;
+    let v = ax * cy - ay * cx;
+    let w = bx * ay - by * ax;
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+
+    let az = sz * a.axis(kz);
+    let bz = sz * b.axis(kz);
+    let cz = sz * c.axis(kz);
+    let t = (u * az + v * bz + w * cz) / det;
+    if !ray_t.surrounds(t) {
+        return None;
+    }
+
+    Some((t, v / det, w / det))
+}
+
+/// Fill in the hit record for a triangle hit with barycentric weights b1 and b2. Normals and
+/// UVs are interpolated from the per-vertex values when they are given.
+#[allow(clippy::too_many_arguments)]
+fn fill_hit_record(
+    r: &Ray,
+    rec: &mut HitRecord,
+    t: f64,
+    (b1, b2): (f64, f64),
+    p: [&Point3; 3],
+    normals: Option<[&Vec3; 3]>,
+    uvs: Option<[&(f64, f64); 3]>,
+    colors: Option<[&Color; 3]>,
+    mat: &Arc<Box<dyn Material + Sync + Send>>,
+) {
+    let b0 = 1.0 - b1 - b2;
+
+    rec.t = t;
+    rec.p = (*p[0] * b0) + (*p[1] * b1) + (*p[2] * b2);
+    rec.barycentric = (b1, b2);
+    rec.mat = mat.clone();
+
+    let (u, v) = match uvs {
+        Some([uv0, uv1, uv2]) => (
+            uv0.0 * b0 + uv1.0 * b1 + uv2.0 * b2,
+            uv0.1 * b0 + uv1.1 * b1 + uv2.1 * b2,
+        ),
+        None => (b1, b2),
+    };
+    rec.u = u;
+    rec.v = v;
+    rec.vertex_color = colors.map(|[c0, c1, c2]| (*c0 * b0) + (*c1 * b1) + (*c2 * b2));
+
+    let geometric_normal = Vec3::unit_vector(&Vec3::cross(&(*p[1] - *p[0]), &(*p[2] - *p[0])));
+    match normals {
+        Some([n0, n1, n2]) => {
+            let shading_normal = (*n0 * b0) + (*n1 * b1) + (*n2 * b2);
+            if shading_normal.near_zero() {
+                rec.set_face_normal(r, &geometric_normal);
+            } else {
+                rec.set_face_normal_smooth(
+                    r,
+                    &geometric_normal,
+                    &Vec3::unit_vector(&shading_normal),
+                );
+            }
+        }
+        None => rec.set_face_normal(r, &geometric_normal),
+    }
+
+    // Without UVs, u and v are the barycentrics, which run along the two edges from p0
+    let [uv0, uv1, uv2] = uvs.map_or([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)], |uvs| {
+        uvs.map(|uv| *uv)
+    });
+    let (du02, dv02) = (uv0.0 - uv2.0, uv0.1 - uv2.1);
+    let (du12, dv12) = (uv1.0 - uv2.0, uv1.1 - uv2.1);
+    let determinant = du02 * dv12 - dv02 * du12;
+    if determinant.abs() > 1e-12 {
+        let dp02 = *p[0] - *p[2];
+        let dp12 = *p[1] - *p[2];
+        let dpdu = (dp02 * dv12 - dp12 * dv02) / determinant;
+        let dpdv = (dp12 * du02 - dp02 * du12) / determinant;
+        rec.set_derivatives(&dpdu, &dpdv);
+    }
+}
+
+/// Uniformly distributed random point on the triangle
+fn random_point(p0: &Point3, p1: &Point3, p2: &Point3) -> Point3 {
+    let su = random_double().sqrt();
+    let r2 = random_double();
+    (*p0 * (1.0 - su)) + (*p1 * (su * (1.0 - r2))) + (*p2 * (su * r2))
+}
+
+fn area(p0: &Point3, p1: &Point3, p2: &Point3) -> f64 {
+    0.5 * Vec3::cross(&(*p1 - *p0), &(*p2 - *p0)).length()
+}
+
+/// Convert the density of uniformly picking a point over a surface of the given area into
+/// a solid angle density, for a hit t along direction with unit normal n
+pub(crate) fn area_to_solid_angle_pdf(t: f64, direction: &Vec3, normal: &Vec3, area: f64) -> f64 {
+    let distance_squared = t * t * direction.length_squared();
+    let cosine = (Vec3::dot(direction, normal) / direction.length()).abs();
+    if cosine <= 0.0 || area <= 0.0 {
+        return 0.0;
+    }
+    distance_squared / (cosine * area)
+}
+
+pub struct Triangle {
+    p0: Point3,
+    p1: Point3,
+    p2: Point3,
+    mat: Arc<Box<dyn Material + Sync + Send>>,
+    bbox: Aabb,
+}
+
+impl Triangle {
+    pub fn new(p0: Point3, p1: Point3, p2: Point3, m: Box<dyn Material + Sync + Send>) -> Self {
+        let bbox = Aabb::new_boxes(&Aabb::new_points(&p0, &p1), &Aabb::new_points(&p2, &p2))
+            .pad(BBOX_PADDING);
+        Triangle {
+            p0,
+            p1,
+            p2,
+            mat: Arc::new(m),
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        match intersect_triangle(r, ray_t, &self.p0, &self.p1, &self.p2) {
+            Some((t, b1, b2)) => {
+                fill_hit_record(
+                    r,
+                    rec,
+                    t,
+                    (b1, b2),
+                    [&self.p0, &self.p1, &self.p2],
+                    None,
+                    None,
+                    None,
+                    &self.mat,
+                );
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let r = Ray::new(origin, direction);
+        match intersect_triangle(
+            &r,
+            Interval::new_val(0.001, INFINITY),
+            &self.p0,
+            &self.p1,
+            &self.p2,
+        ) {
+            Some((t, _, _)) => {
+                let normal =
+                    Vec3::unit_vector(&Vec3::cross(&(self.p1 - self.p0), &(self.p2 - self.p0)));
+                area_to_solid_angle_pdf(t, direction, &normal, area(&self.p0, &self.p1, &self.p2))
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        random_point(&self.p0, &self.p1, &self.p2) - origin
+    }
+}
+
+/// Vertex buffers of an indexed triangle mesh. `normals`, `uvs` and `colors` are either empty
+/// or hold one entry per position, and every index triple refers into all of them at once.
+#[derive(Debug, Clone, Default)]
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub colors: Vec<Color>,
+    pub indices: Vec<[usize; 3]>,
+}
+
+/// Single triangle of a mesh, referencing the shared buffers by index
+struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    mat: Arc<Box<dyn Material + Sync + Send>>,
+    index: usize,
+}
+
+impl MeshTriangle {
+    fn vertices(&self) -> [&Point3; 3] {
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        [
+            &self.mesh.positions[i0],
+            &self.mesh.positions[i1],
+            &self.mesh.positions[i2],
+        ]
+    }
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let p = self.vertices();
+        match intersect_triangle(r, ray_t, p[0], p[1], p[2]) {
+            Some((t, b1, b2)) => {
+                let [i0, i1, i2] = self.mesh.indices[self.index];
+                let normals = if self.mesh.normals.is_empty() {
+                    None
+                } else {
+                    Some([
+                        &self.mesh.normals[i0],
+                        &self.mesh.normals[i1],
+                        &self.mesh.normals[i2],
+                    ])
+                };
+                let uvs = if self.mesh.uvs.is_empty() {
+                    None
+                } else {
+                    Some([&self.mesh.uvs[i0], &self.mesh.uvs[i1], &self.mesh.uvs[i2]])
+                };
+                let colors = if self.mesh.colors.is_empty() {
+                    None
+                } else {
+                    Some([
+                        &self.mesh.colors[i0],
+                        &self.mesh.colors[i1],
+                        &self.mesh.colors[i2],
+                    ])
+                };
+                fill_hit_record(r, rec, t, (b1, b2), p, normals, uvs, colors, &self.mat);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let p = self.vertices();
+        Aabb::new_boxes(&Aabb::new_points(p[0], p[1]), &Aabb::new_points(p[2], p[2]))
+            .pad(BBOX_PADDING)
+    }
+}
+
+/// Triangle mesh whose triangles share one set of vertex buffers and one material, with its
+/// own hierarchy over the triangles so it can be dropped into a scene as a single object.
+pub struct TriangleMesh {
+    mesh: Arc<MeshData>,
+    bvh: FlatBvh,
+    /// Running total of the triangle areas, to sample triangles proportionally to their area
+    area_cdf: Vec<f64>,
+}
+
+impl TriangleMesh {
+    pub fn new(mesh: MeshData, m: Box<dyn Material + Sync + Send>) -> Self {
+        TriangleMesh::new_shared(Arc::new(mesh), Arc::new(m))
+    }
+
+    /// Build a mesh from buffers and a material that may also be used by other meshes
+    pub fn new_shared(mesh: Arc<MeshData>, mat: Arc<Box<dyn Material + Sync + Send>>) -> Self {
+        assert!(
+            mesh.normals.is_empty() || mesh.normals.len() == mesh.positions.len(),
+            "mesh needs either no normals or one per vertex"
+        );
+        assert!(
+            mesh.uvs.is_empty() || mesh.uvs.len() == mesh.positions.len(),
+            "mesh needs either no uvs or one per vertex"
+        );
+        assert!(
+            mesh.colors.is_empty() || mesh.colors.len() == mesh.positions.len(),
+            "mesh needs either no colors or one per vertex"
+        );
+        assert!(
+            mesh.indices
+                .iter()
+                .all(|tri| tri.iter().all(|&i| i < mesh.positions.len())),
+            "mesh index out of range"
+        );
+
+        let triangles: Vec<Arc<dyn Hittable + Sync + Send>> = (0..mesh.indices.len())
+            .map(|index| {
+                Arc::new(MeshTriangle {
+                    mesh: mesh.clone(),
+                    mat: mat.clone(),
+                    index,
+                }) as Arc<dyn Hittable + Sync + Send>
+            })
+            .collect();
+
+        let mut total_area = 0.0;
+        let area_cdf = mesh
+            .indices
+            .iter()
+            .map(|&[i0, i1, i2]| {
+                total_area += area(
+                    &mesh.positions[i0],
+                    &mesh.positions[i1],
+                    &mesh.positions[i2],
+                );
+                total_area
+            })
+            .collect();
+
+        TriangleMesh {
+            mesh,
+            bvh: FlatBvh::from_objects(triangles, SplitMethod::BinnedSah { bins: 16 }),
+            area_cdf,
+        }
+    }
+
+    pub fn data(&self) -> &Arc<MeshData> {
+        &self.mesh
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.mesh.indices.len()
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        self.bvh.hit(r, ray_t, rec)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bvh.bounding_box()
+    }
+
+    /// Points are picked uniformly over the whole surface of the mesh. Only the closest
+    /// crossing along direction is accounted for.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let total_area = self.area_cdf.last().copied().unwrap_or(0.0);
+        let mut rec = HitRecord::default();
+        if !self.hit(
+            &Ray::new(origin, direction),
+            Interval::new_val(0.001, INFINITY),
+            &mut rec,
+        ) {
+            return 0.0;
+        }
+        area_to_solid_angle_pdf(rec.t, direction, &rec.normal, total_area)
+    }
+
+    fn random(&self, origin: &Point3) -> Vec3 {
+        let total_area = match self.area_cdf.last() {
+            Some(&total_area) => total_area,
+            None => return Vec3::new(1.0, 0.0, 0.0),
+        };
+        let target = random_double() * total_area;
+        let index = self
+            .area_cdf
+            .partition_point(|&a| a < target)
+            .min(self.area_cdf.len() - 1);
+
+        let [i0, i1, i2] = self.mesh.indices[index];
+        let p = &self.mesh.positions;
+        random_point(&p[i0], &p[i1], &p[i2]) - origin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::rtweekend::random_double_range;
+
+    fn gray() -> Box<dyn Material + Sync + Send> {
+        Box::new(Lambertian::new(&Color::new(0.5, 0.5, 0.5)))
+    }
+
+    fn open() -> Interval {
+        Interval::new_val(0.001, INFINITY)
+    }
+
+    #[test]
+    fn barycentrics_and_distance() {
+        let (p0, p1, p2) = (
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+        );
+        let r = Ray::new(&Point3::new(0.25, 0.5, 2.0), &Vec3::new(0.0, 0.0, -0.5));
+        let (t, b1, b2) = intersect_triangle(&r, open(), &p0, &p1, &p2).unwrap();
+        assert!((t - 4.0).abs() < 1e-12);
+        assert!((b1 - 0.25).abs() < 1e-12);
+        assert!((b2 - 0.5).abs() < 1e-12);
+
+        // Both windings are hit, points outside and behind the origin are not
+        assert!(intersect_triangle(&r, open(), &p0, &p2, &p1).is_some());
+        let outside = Ray::new(&Point3::new(0.75, 0.5, 2.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(intersect_triangle(&outside, open(), &p0, &p1, &p2).is_none());
+        let behind = Ray::new(&Point3::new(0.25, 0.5, -2.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert!(intersect_triangle(&behind, open(), &p0, &p1, &p2).is_none());
+    }
+
+    #[test]
+    fn shared_edges_are_watertight() {
+        // A fan of triangles around a common vertex, tilted so no edge is axis aligned
+        let center = Point3::new(0.1, -0.2, 0.3);
+        let rim: Vec<Point3> = (0..7)
+            .map(|i| {
+                let angle = i as f64 * std::f64::consts::TAU / 7.0;
+                center + Vec3::new(angle.cos(), angle.sin(), 0.3 * angle.sin())
+            })
+            .collect();
+
+        for i in 0..rim.len() {
+            let (a, b) = (rim[i], rim[(i + 1) % rim.len()]);
+            for _ in 0..2_000 {
+                // Aim at a point on the edge from the center, and at the center itself
+                let target = if random_double() < 0.1 {
+                    center
+                } else {
+                    center + (a - center) * random_double()
+                };
+                let origin = target
+                    + Vec3::new(
+                        random_double_range(-1.0, 1.0),
+                        random_double_range(-1.0, 1.0),
+                        random_double_range(0.2, 2.0),
+                    );
+                let r = Ray::new(&origin, &(target - origin));
+                let hit = (0..rim.len()).any(|j| {
+                    let (c, d) = (rim[j], rim[(j + 1) % rim.len()]);
+                    intersect_triangle(&r, open(), &center, &c, &d).is_some()
+                });
+                assert!(hit, "ray slipped between triangles next to {a:?} {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn mesh_interpolates_vertex_data() {
+        let mesh = MeshData {
+            positions: vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(2.0, 0.0, 0.0),
+                Point3::new(0.0, 2.0, 0.0),
+            ],
+            normals: vec![
+                Vec3::new(0.0, 0.0, 1.0),
+                Vec3::new(1.0, 0.0, 1.0),
+                Vec3::new(0.0, 1.0, 1.0),
+            ],
+            uvs: vec![(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+            colors: vec![
+                Color::new(1.0, 0.0, 0.0),
+                Color::new(0.0, 1.0, 0.0),
+                Color::new(0.0, 0.0, 1.0),
+            ],
+            indices: vec![[0, 1, 2]],
+        };
+        let mesh = TriangleMesh::new(mesh, gray());
+        let r = Ray::new(&Point3::new(0.5, 1.0, 1.0), &Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::default();
+        assert!(mesh.hit(&r, open(), &mut rec));
+
+        assert!((rec.t - 1.0).abs() < 1e-12);
+        assert!((rec.u - 0.25).abs() < 1e-12 && (rec.v - 0.5).abs() < 1e-12);
+        let color = rec.vertex_color.unwrap();
+        assert!((color.x() - 0.25).abs() < 1e-12);
+        assert!((color.y() - 0.25).abs() < 1e-12);
+        assert!((color.z() - 0.5).abs() < 1e-12);
+
+        // The geometric normal faces the ray, the shading one leans toward +x and +y
+        assert!((rec.normal.z() - 1.0).abs() < 1e-12);
+        let expected = Vec3::unit_vector(&Vec3::new(0.25, 0.5, 1.0));
+        assert!((rec.shading_normal - expected).length() < 1e-12);
+        assert!((rec.dpdu - Vec3::new(2.0, 0.0, 0.0)).length() < 1e-12);
+        assert!((rec.dpdv - Vec3::new(0.0, 2.0, 0.0)).length() < 1e-12);
+    }
+
+    #[test]
+    fn mesh_light_pdf_matches_triangle() {
+        let (p0, p1, p2) = (
+            Point3::new(-1.0, 2.0, -1.0),
+            Point3::new(1.0, 2.0, -1.0),
+            Point3::new(0.0, 2.0, 1.0),
+        );
+        let triangle = Triangle::new(p0, p1, p2, gray());
+        let mesh = TriangleMesh::new(
+            MeshData {
+                positions: vec![p0, p1, p2],
+                indices: vec![[0, 1, 2]],
+                ..Default::default()
+            },
+            gray(),
+        );
+        let origin = Point3::new(0.2, 0.0, 0.1);
+        for _ in 0..100 {
+            let direction = mesh.random(&origin);
+            let pdf = triangle.pdf_value(&origin, &direction);
+            assert!(pdf > 0.0);
+            assert!((mesh.pdf_value(&origin, &direction) - pdf).abs() < 1e-9 * pdf);
+        }
+    }
+}
@@ -126,7 +126,7 @@ impl ToString for Vec3 {
 }
 
 impl Vec3 {
-    pub fn new(x: f64, y: f64, z: f64) -> Self {
+    pub const fn new(x: f64, y: f64, z: f64) -> Self {
         Vec3 { e: (x, y, z) }
     }
     pub fn new_random() -> Self {
@@ -150,6 +150,15 @@ impl Vec3 {
         self.e.2
     }
 
+    /// Component by index, 0 for x, 1 for y and 2 for z
+    pub fn axis(&self, n: usize) -> f64 {
+        match n {
+            1 => self.e.1,
+            2 => self.e.2,
+            _ => self.e.0,
+        }
+    }
+
     pub fn length_squared(&self) -> f64 {
         self.x() * self.x() + self.y() * self.y() + self.z() * self.z()
     }
@@ -219,7 +228,7 @@ impl Vec3 {
 
     #[inline(always)]
     pub fn reflect(v: &Self, n: &Self) -> Self {
-        return v - (n * Self::dot(v, n));
+        v - (n * (2.0 * Self::dot(v, n)))
     }
 
     #[inline(always)]
@@ -0,0 +1,245 @@
+use super::vec3::Point3;
+
+/// Edge length, in voxels, of the bricks a `BrickedGrid` is made of
+pub const BRICK_SIZE: usize = 8;
+const BRICK_VOXELS: usize = BRICK_SIZE * BRICK_SIZE * BRICK_SIZE;
+
+/// Scalar field sampled on a regular 3D grid. Grid space is the unit cube, voxel (x, y, z) is
+/// centered on ((x + 0.5) / nx, (y + 0.5) / ny, (z + 0.5) / nz).
+pub trait VoxelGrid {
+    /// Number of voxels along x, y and z
+    fn resolution(&self) -> [usize; 3];
+
+    /// Value of one voxel, indices must be within the resolution
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f32;
+
+    /// Largest voxel value, which bounds every lookup
+    fn max_value(&self) -> f32;
+
+    /// Trilinearly interpolated value at p in grid space. Points outside the unit cube get the
+    /// value of the closest border voxels.
+    fn lookup(&self, p: &Point3) -> f64 {
+        let resolution = self.resolution();
+        let mut base = [0usize; 3];
+        let mut next = [0usize; 3];
+        let mut weight = [0.0; 3];
+        for axis in 0..3 {
+            let count = resolution[axis];
+            if count == 0 {
+                return 0.0;
+            }
+            let g = (p.axis(axis) * count as f64 - 0.5).clamp(0.0, (count - 1) as f64);
+            base[axis] = g as usize;
+            next[axis] = (base[axis] + 1).min(count - 1);
+            weight[axis] = g - base[axis] as f64;
+        }
+
+        let value = |x: usize, y: usize, z: usize| self.voxel(x, y, z) as f64;
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let [x0, y0, z0] = base;
+        let [x1, y1, z1] = next;
+        let [wx, wy, wz] = weight;
+
+        let c00 = lerp(value(x0, y0, z0), value(x1, y0, z0), wx);
+        let c10 = lerp(value(x0, y1, z0), value(x1, y1, z0), wx);
+        let c01 = lerp(value(x0, y0, z1), value(x1, y0, z1), wx);
+        let c11 = lerp(value(x0, y1, z1), value(x1, y1, z1), wx);
+        lerp(lerp(c00, c10, wy), lerp(c01, c11, wy), wz)
+    }
+}
+
+/// Grid storing every voxel, x varying fastest, then y, then z
+#[derive(Debug, Clone)]
+pub struct DenseGrid {
+    resolution: [usize; 3],
+    data: Vec<f32>,
+    max_value: f32,
+}
+
+impl DenseGrid {
+    pub fn new(resolution: [usize; 3], data: Vec<f32>) -> Self {
+        assert!(
+            data.len() == resolution[0] * resolution[1] * resolution[2],
+            "{} values for a {resolution:?} grid",
+            data.len()
+        );
+        let max_value = data.iter().copied().fold(0.0, f32::max);
+        DenseGrid {
+            resolution,
+            data,
+            max_value,
+        }
+    }
+
+    /// Grid filled by evaluating f at the center of every voxel, in grid space
+    pub fn from_fn<F: Fn(&Point3) -> f32>(resolution: [usize; 3], f: F) -> Self {
+        let [nx, ny, nz] = resolution;
+        let mut data = Vec::with_capacity(nx * ny * nz);
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    data.push(f(&Point3::new(
+                        (x as f64 + 0.5) / nx as f64,
+                        (y as f64 + 0.5) / ny as f64,
+                        (z as f64 + 0.5) / nz as f64,
+                    )));
+                }
+            }
+        }
+        DenseGrid::new(resolution, data)
+    }
+
+    /// All voxels, x varying fastest
+    pub fn data(&self) -> &[f32] {
+        &self.data
+    }
+}
+
+impl VoxelGrid for DenseGrid {
+    fn resolution(&self) -> [usize; 3] {
+        self.resolution
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f32 {
+        let [nx, ny, _] = self.resolution;
+        self.data[(z * ny + y) * nx + x]
+    }
+
+    fn max_value(&self) -> f32 {
+        self.max_value
+    }
+}
+
+/// Sparse grid cut into bricks of `BRICK_SIZE` cubed voxels, where bricks holding nothing but
+/// zeros aren't stored. Simulation caches of smoke and fire are mostly empty space, so this
+/// usually takes a fraction of the memory of the dense grid it was built from.
+#[derive(Debug, Clone)]
+pub struct BrickedGrid {
+    resolution: [usize; 3],
+    /// Number of bricks along x, y and z
+    bricks_per_axis: [usize; 3],
+    /// Index into bricks for every brick position, None for empty bricks
+    brick_index: Vec<Option<u32>>,
+    /// Stored bricks one after the other, `BRICK_SIZE` cubed voxels each
+    bricks: Vec<f32>,
+    max_value: f32,
+}
+
+impl BrickedGrid {
+    pub fn from_dense(grid: &DenseGrid) -> Self {
+        let resolution = grid.resolution;
+        let bricks_per_axis = resolution.map(|n| n.div_ceil(BRICK_SIZE));
+        let [bx, by, bz] = bricks_per_axis;
+
+        let mut brick_index = Vec::with_capacity(bx * by * bz);
+        let mut bricks = Vec::new();
+        for brick_z in 0..bz {
+            for brick_y in 0..by {
+                for brick_x in 0..bx {
+                    let mut brick = [0.0f32; BRICK_VOXELS];
+                    let mut empty = true;
+                    for (i, value) in brick.iter_mut().enumerate() {
+                        let x = brick_x * BRICK_SIZE + i % BRICK_SIZE;
+                        let y = brick_y * BRICK_SIZE + (i / BRICK_SIZE) % BRICK_SIZE;
+                        let z = brick_z * BRICK_SIZE + i / (BRICK_SIZE * BRICK_SIZE);
+                        if x < resolution[0] && y < resolution[1] && z < resolution[2] {
+                            *value = grid.voxel(x, y, z);
+                            empty &= *value == 0.0;
+                        }
+                    }
+
+                    if empty {
+                        brick_index.push(None);
+                    } else {
+                        brick_index.push(Some((bricks.len() / BRICK_VOXELS) as u32));
+                        bricks.extend_from_slice(&brick);
+                    }
+                }
+            }
+        }
+
+        BrickedGrid {
+            resolution,
+            bricks_per_axis,
+            brick_index,
+            bricks,
+            max_value: grid.max_value,
+        }
+    }
+
+    /// Number of bricks actually stored
+    pub fn brick_count(&self) -> usize {
+        self.bricks.len() / BRICK_VOXELS
+    }
+}
+
+impl VoxelGrid for BrickedGrid {
+    fn resolution(&self) -> [usize; 3] {
+        self.resolution
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f32 {
+        let [bx, by, _] = self.bricks_per_axis;
+        let brick = ((z / BRICK_SIZE) * by + y / BRICK_SIZE) * bx + x / BRICK_SIZE;
+        match self.brick_index[brick] {
+            Some(index) => {
+                let local =
+                    ((z % BRICK_SIZE) * BRICK_SIZE + y % BRICK_SIZE) * BRICK_SIZE + x % BRICK_SIZE;
+                self.bricks[index as usize * BRICK_VOXELS + local]
+            }
+            None => 0.0,
+        }
+    }
+
+    fn max_value(&self) -> f32 {
+        self.max_value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtweekend::random_double;
+
+    #[test]
+    fn lookups_interpolate_between_voxel_centers() {
+        // Linear along x, constant along y and z
+        let grid = DenseGrid::from_fn([4, 2, 3], |p| p.x() as f32);
+        assert_eq!(grid.voxel(1, 1, 2), 0.375);
+        assert_eq!(grid.max_value(), 0.875);
+
+        for x in [0.125, 0.3, 0.5, 0.8, 0.875] {
+            let p = Point3::new(x, random_double(), random_double());
+            assert!((grid.lookup(&p) - x).abs() < 1e-6, "{x}");
+        }
+
+        // Past the outer voxel centers the border values hold
+        assert!((grid.lookup(&Point3::new(0.0, 0.5, 0.5)) - 0.125).abs() < 1e-6);
+        assert!((grid.lookup(&Point3::new(1.5, 0.5, 0.5)) - 0.875).abs() < 1e-6);
+    }
+
+    #[test]
+    fn bricks_match_the_dense_grid() {
+        // Not a multiple of the brick size, and empty except for a ball in one corner
+        let dense = DenseGrid::from_fn([19, 10, 13], |p| {
+            let d = (*p - Point3::new(0.2, 0.2, 0.2)).length();
+            if d < 0.25 {
+                (1.0 - d) as f32
+            } else {
+                0.0
+            }
+        });
+        let bricked = BrickedGrid::from_dense(&dense);
+
+        assert_eq!(bricked.resolution(), dense.resolution());
+        assert_eq!(bricked.max_value(), dense.max_value());
+        assert!(bricked.brick_count() > 0 && bricked.brick_count() < 3 * 2 * 2);
+        for z in 0..13 {
+            for y in 0..10 {
+                for x in 0..19 {
+                    assert_eq!(bricked.voxel(x, y, z), dense.voxel(x, y, z));
+                }
+            }
+        }
+    }
+}